@@ -2,57 +2,468 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::path::PathBuf;
+
 use blsforme::bootloader::systemd_boot::{self};
-use clap::{ArgMatches, Command};
+use clap::{arg, ArgAction, ArgMatches, Command};
 use thiserror::Error;
+use tui::Styled;
 
-use moss::Installation;
+use moss::{
+    client::{
+        self,
+        boot::{self, plan::Action},
+        Client,
+    },
+    environment, state, Installation,
+};
 
 pub fn command() -> Command {
     Command::new("boot")
         .about("Boot management")
         .long_about("Manage boot configuration")
         .subcommand_required(true)
-        .subcommand(Command::new("status").about("Status of boot configuration"))
+        .subcommand(
+            Command::new("status")
+                .about("Status of boot configuration")
+                .arg(arg!(--json "Output in JSON format").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Synchronize boot entries")
+                .long_about(
+                    "Synchronize boot entries for the active state and its most recent predecessors, \
+                     as done by a transaction. Unlike a transaction, a sync which can't be performed \
+                     is reported as an error.",
+                )
+                .arg(
+                    arg!(--"dry-run" "Print the planned changes without modifying the system")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(arg!(--json "Output in JSON format").action(ArgAction::SetTrue))
+                .arg(
+                    arg!(--state <STATE> "Synchronize the active state and this state only")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference))
+                        .conflicts_with("all-states"),
+                )
+                .arg(arg!(--"all-states" "Synchronize every retained state").action(ArgAction::SetTrue))
+                .arg(
+                    arg!(--"refresh-cmdline" <STATE> "Re-resolve the stored kernel command line of a state")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                ),
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("Remove stale boot entries")
+                .long_about("Remove moss generated boot entries for removed states or with missing kernels")
+                .arg(arg!(--"dry-run" "Print the stale entries without removing them").action(ArgAction::SetTrue)),
+        )
 }
 
-/// Handle status for now
-pub fn handle(_args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let root = installation.root.clone();
-    let is_native = root.to_string_lossy() == "/";
-    let config = blsforme::Configuration {
-        root: if is_native {
-            blsforme::Root::Native(root.clone())
-        } else {
-            blsforme::Root::Image(root.clone())
-        },
-        vfs: "/".into(),
+/// The one-off boot management policy override passed via `--boot-manage`
+pub fn manage_override(args: &ArgMatches) -> Option<boot::Manage> {
+    args.get_one::<String>("boot-manage")
+        .and_then(|policy| policy.parse().ok())
+}
+
+/// Handle execution of `moss boot`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("status", args)) => status(args, installation),
+        Some(("sync", args)) => sync(args, installation),
+        Some(("gc", args)) => gc(args, installation),
+        _ => unreachable!(),
+    }
+}
+
+/// Report the boot status of the installation
+pub fn status(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let json = args.get_flag("json");
+    let is_native = installation.root.to_string_lossy() == "/";
+
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(manage_override(args));
+    let status = boot::status(&client)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    let display = |path: &Option<PathBuf>| {
+        path.as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "-".to_owned())
     };
 
-    let manager = blsforme::Manager::new(&config)?;
-    match manager.boot_environment().firmware {
-        blsforme::Firmware::UEFI => {
-            println!("ESP            : {:?}", manager.boot_environment().esp());
-            println!("XBOOTLDR       : {:?}", manager.boot_environment().xbootldr());
+    match status.firmware {
+        Some(boot::status::Firmware::Uefi) => {
+            println!("Firmware       : {}", "UEFI".bold());
+            println!("ESP            : {}", display(&status.esp));
+            println!("XBOOTLDR       : {}", display(&status.xbootldr));
             if is_native {
-                if let Ok(bootloader) = systemd_boot::interface::BootLoaderInterface::new(&config.vfs) {
+                if let Ok(bootloader) = systemd_boot::interface::BootLoaderInterface::new(&PathBuf::from("/")) {
                     let v = bootloader.get_ucs2_string(systemd_boot::interface::VariableName::Info)?;
                     println!("Bootloader     : {v}");
                 }
             }
         }
-        blsforme::Firmware::BIOS => {
-            println!("BOOT           : {:?}", manager.boot_environment().boot_partition());
+        Some(boot::status::Firmware::Bios) => {
+            println!("Firmware       : {}", "BIOS".bold());
+            println!("BOOT           : {}", display(&status.boot_partition));
+        }
+        None => println!("Firmware       : {}", "unknown".dim()),
+    }
+    println!(
+        "Mountpoint     : {}{}",
+        display(&status.mountpoint),
+        space(status.esp_space.as_ref())
+    );
+    if let Some(error) = &status.mount_error {
+        println!("Mount          : {}", format!("(failed, {error})").yellow());
+    }
+    if status.xbootldr_mountpoint.is_some() {
+        println!(
+            "XBOOTLDR mount : {}{}",
+            display(&status.xbootldr_mountpoint),
+            space(status.xbootldr_space.as_ref())
+        );
+    }
+    for mirror in &status.mirrors {
+        match &mirror.error {
+            None => println!(
+                "Mirror ESP     : {} {}{}",
+                display(&mirror.mountpoint),
+                format!("({})", mirror.target).dim(),
+                space(mirror.space.as_ref())
+            ),
+            Some(error) => println!("Mirror ESP     : {} {}", mirror.target, format!("({error})").yellow()),
+        }
+    }
+    match status.backend {
+        Some(backend) => println!("Backend        : {backend}"),
+        None => println!("Backend        : {}", "none".dim()),
+    }
+    match &status.unmanaged {
+        Some(reason) => println!(
+            "Management     : {} {}",
+            status.manage,
+            format!("(skipped, {reason})").yellow()
+        ),
+        None => println!("Management     : {}", status.manage),
+    }
+
+    println!("Kernel globs   : {}", status.kernel_patterns.join(", "));
+    if !status.asset_patterns.is_empty() {
+        println!("Asset globs    : {}", status.asset_patterns.join(", "));
+    }
+
+    println!();
+    println!("{}", "Bootloader assets".bold());
+    for asset in &status.bootloader_assets {
+        println!(" {} {}", "»".green(), asset.display());
+    }
+
+    println!();
+    println!("{}", "Kernels".bold());
+    for kernel in &status.kernels {
+        match &kernel.entry {
+            Some(entry) => println!(
+                " {} State #{} {} {}",
+                "»".green(),
+                kernel.state,
+                kernel.version.clone().bold(),
+                entry.display().to_string().dim()
+            ),
+            None => println!(
+                " {} State #{} {} {}",
+                "×".yellow(),
+                kernel.state,
+                kernel.version.clone().bold(),
+                "(no entry)".dim()
+            ),
+        }
+        if let Some(cmdline) = &kernel.cmdline {
+            println!("     {}", cmdline.clone().dim());
+        }
+    }
+
+    if !status.invalid_entries.is_empty() {
+        println!();
+        println!("{}", "Invalid entries".bold());
+        for invalid in &status.invalid_entries {
+            println!(" {} {invalid}", "×".yellow());
+        }
+    }
+
+    let mirror_stale = status.mirrors.iter().flat_map(|mirror| &mirror.stale_entries);
+    if !status.stale_entries.is_empty() || mirror_stale.clone().next().is_some() {
+        println!();
+        println!("{}", "Stale entries".bold());
+        for stale in status.stale_entries.iter().chain(mirror_stale) {
+            println!(
+                " {} {} {}",
+                "×".yellow(),
+                stale.path.display(),
+                format!("({})", stale.reason).dim()
+            );
+        }
+    }
+
+    if !status.orphaned.is_empty() {
+        println!();
+        println!("{}", "Orphaned files".bold());
+        for path in &status.orphaned {
+            println!(" {} {}", "×".yellow(), path.display());
+        }
+    }
+
+    let pending = status.plan.pending().collect::<Vec<_>>();
+    let loader = status
+        .plan
+        .loader
+        .iter()
+        .filter(|update| update.action != Action::Unchanged)
+        .collect::<Vec<_>>();
+    if !pending.is_empty() || !loader.is_empty() {
+        println!();
+        println!("{}", "Pending changes".bold());
+        for update in loader {
+            print_loader(update);
+        }
+        for entry in pending {
+            print_entry(entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Format the free space of a boot partition, if known
+fn space(space: Option<&boot::esp::Space>) -> String {
+    const MIB: u64 = 1024 * 1024;
+
+    space
+        .map(|space| format!(" ({} MiB free of {} MiB)", space.available / MIB, space.total / MIB))
+        .unwrap_or_default()
+}
+
+/// Synchronize boot entries for the selected states
+///
+/// Fails if nothing could be synchronized, so scripts can detect an unbootable system.
+pub fn sync(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let dry_run = args.get_flag("dry-run");
+    let json = args.get_flag("json");
+
+    let Some(active) = installation.active_state else {
+        return Err(Error::NoActiveState);
+    };
+
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(manage_override(args));
+
+    if !dry_run {
+        for reference in args
+            .get_many::<state::Reference>("refresh-cmdline")
+            .into_iter()
+            .flatten()
+        {
+            boot::refresh_cmdline(&client, client.resolve_state(*reference)?)?;
+        }
+    }
+
+    let head = client.state_db.get(active)?;
+    let states = if args.get_flag("all-states") {
+        boot::retained_states(&client)?
+    } else if let Some(reference) = args.get_one::<state::Reference>("state") {
+        // The active state always provides the bootloader and lives in the root
+        let state = client.state_db.get(client.resolve_state(*reference)?)?;
+        if state.id == head.id {
+            vec![head]
+        } else {
+            vec![head, state]
+        }
+    } else {
+        boot::transaction_states(&client, &head)?
+    };
+
+    let outcome = boot::synchronize_all(&client, &states, dry_run)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&outcome)?);
+        return match outcome {
+            boot::SyncOutcome::Skipped(reason) => Err(Error::Skipped(reason)),
+            boot::SyncOutcome::Synced(plan) if !plan.invalid.is_empty() => {
+                Err(Error::InvalidEntries(plan.invalid.len()))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    match outcome {
+        boot::SyncOutcome::Planned(plan) => print_plan(&plan),
+        boot::SyncOutcome::Synced(plan) => {
+            let transfer = plan.transfer;
+            println!(
+                "Boot assets    : {} copied, {} unchanged, {} verified",
+                transfer.copied, transfer.skipped, transfer.verified
+            );
+            print_mirrors(&plan.mirrors);
+
+            if !plan.invalid.is_empty() {
+                for invalid in &plan.invalid {
+                    println!(" {} {invalid}", "×".yellow());
+                }
+                return Err(Error::InvalidEntries(plan.invalid.len()));
+            }
         }
+        boot::SyncOutcome::Skipped(reason) => return Err(Error::Skipped(reason)),
     }
 
-    println!("Global cmdline : {:?}", manager.cmdline());
+    Ok(())
+}
+
+/// Remove stale boot entries
+pub fn gc(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let dry_run = args.get_flag("dry-run");
+
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(manage_override(args));
+    let plan = match boot::gc(&client, dry_run)? {
+        boot::SyncOutcome::Planned(plan) | boot::SyncOutcome::Synced(plan) => plan,
+        boot::SyncOutcome::Skipped(reason) => return Err(Error::Skipped(reason)),
+    };
+
+    if plan.entries.is_empty() && plan.orphaned.is_empty() {
+        println!("No stale boot entries");
+    }
+    for entry in &plan.entries {
+        print_entry(entry);
+    }
+    for path in &plan.orphaned {
+        println!(
+            " {} delete {} {}",
+            "»".green(),
+            "orphaned".bold(),
+            path.display().to_string().dim()
+        );
+    }
+    print_mirrors(&plan.mirrors);
 
     Ok(())
 }
 
+/// Print the outcome of each reconciled mirror ESP
+fn print_mirrors(mirrors: &[boot::mirror::Outcome]) {
+    for mirror in mirrors {
+        match &mirror.error {
+            None => println!(
+                "Mirror ESP     : {} {}",
+                mirror.target,
+                format!(
+                    "({} copied, {} unchanged, {} removed)",
+                    mirror.transfer.copied,
+                    mirror.transfer.skipped,
+                    mirror.removed.len()
+                )
+                .dim()
+            ),
+            Some(error) => println!(
+                "Mirror ESP     : {} {}",
+                mirror.target,
+                format!("(failed: {error})").yellow()
+            ),
+        }
+    }
+}
+
+/// Print the mounts, copies and entries of a boot sync `plan`
+fn print_plan(plan: &boot::Plan) {
+    match (plan.backend, plan.mode) {
+        (Some(backend), Some(mode)) => println!("Backend        : {backend} ({mode})"),
+        (Some(backend), None) => println!("Backend        : {backend}"),
+        (None, _) => {
+            println!("Backend        : {}", "none".dim());
+            return;
+        }
+    }
+
+    for mount in &plan.mounts {
+        println!(" {} mount {}", "»".green(), mount.display());
+    }
+    for hook in &plan.hooks {
+        println!(" {} run {} hook {}", "»".green(), hook.phase, hook.path.display());
+    }
+    for copy in &plan.copies {
+        let destination = copy
+            .destination
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "(boot partition)".to_owned());
+        println!(" {} copy {} → {}", "»".green(), copy.source.display(), destination);
+    }
+    for update in &plan.loader {
+        print_loader(update);
+    }
+    for entry in &plan.entries {
+        print_entry(entry);
+    }
+    if let Some(default) = &plan.default {
+        println!(" {} default {}", "»".green(), default.clone().bold());
+    }
+    for requirement in &plan.required {
+        println!(
+            " {} write {} MiB to {}{}",
+            "»".green(),
+            requirement.required.div_ceil(1024 * 1024),
+            requirement.partition.display(),
+            space(boot::esp::Space::of(&requirement.partition).as_ref()).dim()
+        );
+    }
+}
+
+/// Print a single planned loader installation
+fn print_loader(update: &boot::bootloader::Update) {
+    let version = |version: &Option<String>| version.clone().unwrap_or_else(|| "unknown".to_owned());
+    println!(
+        " {} {} loader {} {}",
+        "»".green(),
+        update.action,
+        update.destination.display(),
+        format!("({} → {})", version(&update.installed), version(&update.packaged)).dim()
+    );
+}
+
+/// Print a single planned boot entry
+fn print_entry(entry: &boot::plan::PlannedEntry) {
+    let path = entry
+        .path
+        .as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    println!(
+        " {} {} State #{} {} {}",
+        "»".green(),
+        entry.action,
+        entry.state,
+        entry.version.clone().bold(),
+        path.dim()
+    );
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("no active state")]
+    NoActiveState,
+
+    #[error("nothing to synchronize: {0}")]
+    Skipped(boot::SkipReason),
+
+    #[error("{0} boot entries failed validation")]
+    InvalidEntries(usize),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+
     #[error("blsforme")]
     Blsforme(#[from] blsforme::Error),
 
@@ -64,4 +475,16 @@ pub enum Error {
 
     #[error("os-release")]
     OsRelease(#[from] blsforme::os_release::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("state")]
+    Resolve(#[from] client::resolve::Error),
+
+    #[error("boot")]
+    Boot(#[from] boot::Error),
 }
@@ -4,8 +4,9 @@
 
 use std::path::PathBuf;
 
-use clap::{arg, value_parser, ArgMatches, Command};
-use moss::{client::Client, environment, Installation};
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
+use fs_err as fs;
+use moss::{client::Client, environment, state, Installation};
 
 pub use moss::client::install::Error;
 
@@ -14,7 +15,25 @@ pub fn command() -> Command {
         .visible_alias("it")
         .about("Install packages")
         .long_about("Install the requested software to the local system")
-        .arg(arg!(<NAME> ... "packages to install").value_parser(value_parser!(String)))
+        .arg(
+            arg!([NAME] ... "packages to install")
+                .value_parser(value_parser!(String))
+                .required_unless_present("from-manifest"),
+        )
+        .arg(
+            arg!(--"from-manifest" <FILE> "Recreate the state of a manifest exported by `moss state export`")
+                .long_help(
+                    "Recreate the state of a manifest exported by `moss state export`\n\n\
+                     The exact packages of the manifest replace all installed packages",
+                )
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("NAME"),
+        )
+        .arg(
+            arg!(--"allow-newer" "Accept newer releases of manifest packages no longer available")
+                .requires("from-manifest")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--to <blit_target> "Blit this install to the provided directory instead of the root")
                 .long_help(
@@ -24,6 +43,7 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(super::state::summary_arg())
 }
 
 /// Handle execution of `moss install`
@@ -37,14 +57,27 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let yes = *args.get_one::<bool>("yes").unwrap();
 
     // Grab a client for the root
-    let mut client = Client::new(environment::NAME, installation)?;
+    let mut client = Client::new(environment::NAME, installation)?
+        .with_boot_manage(super::boot::manage_override(args))
+        .with_summary(super::state::summary_override(args));
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
         client = client.ephemeral(blit_target)?;
     }
+    super::state::warn_incomplete(&client)?;
 
-    client.install(&pkgs, yes)?;
+    let timing = match args.get_one::<PathBuf>("from-manifest") {
+        Some(path) => {
+            let manifest = state::Manifest::from_json(&fs::read_to_string(path)?)?;
+            client.install_manifest(&manifest, args.get_flag("allow-newer"), yes)?
+        }
+        None => client.install(&pkgs, yes)?,
+    };
+
+    if let Some(boot) = timing.boot {
+        println!("{boot}");
+    }
 
     Ok(())
 }
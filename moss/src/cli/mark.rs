@@ -0,0 +1,100 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgAction, ArgGroup, ArgMatches, Command};
+use itertools::Itertools;
+use thiserror::Error;
+
+use moss::{
+    client::{self, Client},
+    environment,
+    package::Flags,
+    state, Installation, Provider,
+};
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("mark")
+        .about("Mark packages as explicitly or automatically installed")
+        .long_about(
+            "Mark packages as explicitly or automatically installed\n\n\
+             The marks are recorded as a new state. Automatically installed packages are removed by \
+             `moss sync` once no explicitly installed package depends on them",
+        )
+        .arg(arg!(<NAME> ... "packages to mark").value_parser(clap::value_parser!(String)))
+        .arg(arg!(--explicit "Mark as explicitly installed").action(ArgAction::SetTrue))
+        .arg(arg!(--auto "Mark as automatically installed as a dependency").action(ArgAction::SetTrue))
+        .group(ArgGroup::new("mark").args(["explicit", "auto"]).required(true))
+        .arg(super::state::summary_arg())
+}
+
+/// Handle execution of `moss mark`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let explicit = args.get_flag("explicit");
+    let Some(active) = installation.active_state else {
+        return Err(Error::Client(client::Error::NoActiveState));
+    };
+
+    let client = Client::new(environment::NAME, installation)?
+        .with_boot_manage(super::boot::manage_override(args))
+        .with_summary(super::state::summary_override(args));
+    super::state::warn_incomplete(&client)?;
+
+    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
+
+    // Every package must be selected by the active state
+    let packages = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .map(|name| {
+            let provider = Provider::from_name(name).unwrap();
+
+            installed
+                .iter()
+                .find(|package| package.meta.providers.contains(&provider))
+                .ok_or_else(|| Error::NotInstalled(name.clone(), active))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut selections = client.state_db.get(active)?.selections;
+    let ids = packages.iter().map(|package| package.id.clone()).collect::<Vec<_>>();
+    let marked = state::mark_selections(&mut selections, &ids, explicit);
+    let mark = if explicit { "explicit" } else { "auto" };
+
+    for package in packages.iter().filter(|package| !marked.contains(&package.id)) {
+        println!("{} is already marked {mark}", package.meta.name.to_string().bold());
+    }
+    if marked.is_empty() {
+        return Ok(());
+    }
+
+    let names = packages
+        .iter()
+        .filter(|package| marked.contains(&package.id))
+        .map(|package| package.meta.name.to_string())
+        .collect::<Vec<_>>();
+    for name in &names {
+        println!("{} {} {mark}", "Marked".green(), name.as_str().bold());
+    }
+
+    let summary = format!("marked {} {mark}", names.iter().join(", "));
+    if let Some((_, boot)) = client.new_state_with_kind(&selections, Some(&summary), None, state::Kind::Transaction)? {
+        println!("{boot}");
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0} is not installed in the active state {1}")]
+    NotInstalled(String, state::Id),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+}
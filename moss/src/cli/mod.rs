@@ -20,6 +20,7 @@ mod info;
 mod inspect;
 mod install;
 mod list;
+mod mark;
 mod remove;
 mod repo;
 mod search;
@@ -65,6 +66,15 @@ fn command() -> Command {
                 .help("Assume yes for all questions")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("boot-manage")
+                .long("boot-manage")
+                .global(true)
+                .help("Override the installation's boot management policy for this operation")
+                .action(ArgAction::Set)
+                .value_name("POLICY")
+                .value_parser(["auto", "always", "never"]),
+        )
         .arg(
             Arg::new("generate-manpages")
                 .long("generate-manpages")
@@ -89,10 +99,12 @@ fn command() -> Command {
         .subcommand(inspect::command())
         .subcommand(install::command())
         .subcommand(list::command())
+        .subcommand(mark::command())
         .subcommand(remove::command())
         .subcommand(repo::command())
         .subcommand(search::command())
         .subcommand(state::command())
+        .subcommand(state::rollback_command())
         .subcommand(sync::command())
         .subcommand(version::command())
 }
@@ -175,10 +187,12 @@ pub fn process() -> Result<(), Error> {
         Some(("inspect", args)) => inspect::handle(args).map_err(Error::Inspect),
         Some(("install", args)) => install::handle(args, installation).map_err(Error::Install),
         Some(("list", args)) => list::handle(args, installation).map_err(Error::List),
+        Some(("mark", args)) => mark::handle(args, installation).map_err(Error::Mark),
         Some(("remove", args)) => remove::handle(args, installation).map_err(Error::Remove),
         Some(("repo", args)) => repo::handle(args, installation).map_err(Error::Repo),
         Some(("search", args)) => search::handle(args, installation).map_err(Error::Search),
         Some(("state", args)) => state::handle(args, installation).map_err(Error::State),
+        Some(("rollback", args)) => state::rollback(args, installation).map_err(Error::State),
         Some(("sync", args)) => sync::handle(args, installation).map_err(Error::Sync),
         Some(("version", args)) => {
             version::handle(args);
@@ -242,6 +256,9 @@ pub enum Error {
     #[error("list")]
     List(#[from] list::Error),
 
+    #[error("mark")]
+    Mark(#[from] mark::Error),
+
     #[error("inspect")]
     Inspect(#[from] inspect::Error),
 
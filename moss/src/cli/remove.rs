@@ -27,6 +27,7 @@ pub fn command() -> Command {
         .about("Remove packages")
         .long_about("Remove packages by name")
         .arg(arg!(<NAME> ... "packages to install").value_parser(clap::value_parser!(String)))
+        .arg(super::state::summary_arg())
 }
 
 /// Handle execution of `moss remove`
@@ -40,7 +41,10 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let yes = *args.get_one::<bool>("yes").unwrap();
 
     // Grab a client for the target, enumerate packages
-    let client = Client::new(environment::NAME, installation)?;
+    let client = Client::new(environment::NAME, installation)?
+        .with_boot_manage(super::boot::manage_override(args))
+        .with_summary(super::state::summary_override(args));
+    super::state::warn_incomplete(&client)?;
 
     let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
     let installed_ids = installed.iter().map(|p| p.id.clone()).collect::<BTreeSet<_>>();
@@ -117,18 +121,16 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
                     .unwrap_or_else(|| {
                         eprintln!("Unreachable: previous selection not found during removal for package {id:?}, marking as not explicit");
 
-                        Selection {
-                            package: id,
-                            explicit: false,
-                            reason: None,
-                        }
+                        Selection::transitive(id)
                     })
             })
             .collect::<Vec<_>>()
     };
 
     // Apply state
-    client.new_state(&new_state_pkgs, "Remove")?;
+    if let Some((_, boot)) = client.new_state(&new_state_pkgs)? {
+        println!("{boot}");
+    }
 
     Ok(())
 }
@@ -2,67 +2,320 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use chrono::Local;
-use clap::{arg, ArgAction, ArgMatches, Command};
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use chrono::{Duration, Local, Utc};
+use clap::{arg, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use fs_err as fs;
 use moss::{
-    client::{self, prune, Client},
-    environment, state, Installation,
+    client::{self, boot, manifest, prune, resolve, rollback, usage, verify, Client},
+    db::meta::Filter,
+    environment, package, state, Installation,
 };
+use serde::Serialize;
 use thiserror::Error;
-use tui::Styled;
+use tui::{
+    pretty::{autoprint_columns, print_columns, Column, ColumnDisplay},
+    Styled,
+};
 
 pub fn command() -> Command {
     Command::new("state")
         .about("Manage state")
         .long_about("Manage state ...")
+        .after_long_help(
+            "States are given by id, or relative to the active state as `active`, `previous`, \
+             `active~<n>` for the nth state before it, or `latest` for the most recently recorded state",
+        )
         .subcommand_required(true)
         .subcommand(Command::new("active").about("List the active state"))
-        .subcommand(Command::new("list").about("List all states"))
+        .subcommand(
+            Command::new("list")
+                .about("List all states")
+                .arg(arg!(--pinned "Only list pinned states").action(ArgAction::SetTrue))
+                .arg(arg!(--sizes "Show the size of the assets referenced by each state").action(ArgAction::SetTrue))
+                .arg(
+                    arg!(--kind <KIND> "Only list states of this kind")
+                        .action(ArgAction::Set)
+                        .value_parser([
+                            "transaction",
+                            "rollback",
+                            "snapshot",
+                            "repair",
+                            "incomplete",
+                            "abandoned",
+                        ]),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .about("Record a copy of the active state")
+                .arg(arg!(--summary <SUMMARY> "Summary of the snapshot").action(ArgAction::Set))
+                .arg(arg!(--description <DESCRIPTION> "Description of the snapshot").action(ArgAction::Set)),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Show details of a state")
+                .arg(
+                    arg!(<ID> "State to be shown")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(--json "Output in JSON format").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export a state to a portable manifest")
+                .long_about(
+                    "Export a state to a portable manifest\n\n\
+                     The manifest pins every package of the state by hash, and is recreated on any machine \
+                     via `moss install --from-manifest`",
+                )
+                .arg(state_arg("State to be exported"))
+                .arg(
+                    arg!(-o --output <FILE> "Write the manifest to this file instead of stdout")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(Command::new("tree").about("Show the lineage of all states").long_about(
+            "Show the lineage of all states\n\n\
+             States branch off whenever a new state is applied to an older one. \
+             States recorded before lineage was tracked start their own tree",
+        ))
         .subcommand(
             Command::new("activate")
                 .about("Activate a state")
+                .long_about(
+                    "Activate a state\n\n\
+                     The packages of the state are applied as a new state, leaving the state history untouched",
+                )
                 .arg(
-                    arg!(<ID> "State id to be activated")
+                    arg!(<ID> "State to be activated")
                         .action(ArgAction::Set)
-                        .value_parser(clap::value_parser!(u64)),
+                        .value_parser(clap::value_parser!(state::Reference)),
                 )
-                .arg(arg!(--"skip-triggers" "Do not run triggers on activation").action(ArgAction::SetTrue)),
+                .arg(fetch_arg())
+                .arg(skip_triggers_arg())
+                .arg(summary_arg()),
+        )
+        .subcommand(
+            Command::new("describe")
+                .about("Edit the summary and description of a state")
+                .long_about(
+                    "Edit the summary and description of a state\n\n\
+                     An empty value clears the field. Boot entry titles of the state are updated accordingly",
+                )
+                .arg(state_arg("State to be described"))
+                .arg(arg!(--summary <SUMMARY> "New summary of the state").action(ArgAction::Set))
+                .arg(arg!(--description <DESCRIPTION> "New description of the state").action(ArgAction::Set))
+                .group(
+                    ArgGroup::new("fields")
+                        .args(["summary", "description"])
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            Command::new("pin")
+                .about("Protect a state from pruning")
+                .arg(state_arg("State to be pinned")),
+        )
+        .subcommand(
+            Command::new("unpin")
+                .about("Allow a pinned state to be pruned")
+                .arg(state_arg("State to be unpinned")),
         )
         .subcommand(
             Command::new("prune")
                 .about("Prune archived states")
+                .long_about(
+                    "Prune archived states\n\n\
+                     The retention policy defaults to the `prune` configuration of the installation, \
+                     overridden by the given arguments. The active, booted and pinned states are always kept",
+                )
                 .arg(
-                    arg!(-k --keep "Keep this many states")
+                    arg!(-k --keep <COUNT> "Keep this many states (default: 10)")
                         .action(ArgAction::Set)
-                        .default_value("10")
                         .value_parser(clap::value_parser!(u64).range(1..)),
                 )
+                .arg(
+                    arg!(--weekly <WEEKS> "Keep the newest state of each week for this many weeks")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                )
                 .arg(
                     arg!(--"include-newer" "Include states newer than the active state when pruning")
                         .action(ArgAction::SetTrue),
+                )
+                .arg(arg!(--"dry-run" "Print what would be removed without removing it").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("remove")
+                .about("Remove an archived state")
+                .arg(
+                    arg!(<ID> "State to be removed")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(--"dry-run" "Print what would be removed without removing it").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare the packages of two states")
+                .long_about("Compare the packages of two states, or of a state and the active state")
+                .arg(
+                    arg!(<FROM> "State to compare from")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(
+                    arg!([TO] "State to compare to, defaulting to the active state")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(--json "Output in JSON format").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("resume")
+                .about("Retry an interrupted transaction")
+                .long_about(
+                    "Retry an interrupted transaction\n\n\
+                     The state recorded ahead of the transaction is blitted again from its selections",
+                ),
+        )
+        .subcommand(
+            Command::new("discard")
+                .about("Give up on an interrupted transaction")
+                .long_about(
+                    "Give up on an interrupted transaction\n\n\
+                     The state recorded ahead of the transaction is kept as abandoned, releasing its packages",
                 ),
         )
         .subcommand(
-            Command::new("remove").about("Remove an archived state").arg(
-                arg!(<ID> "State id to be removed")
-                    .action(ArgAction::Set)
-                    .value_parser(clap::value_parser!(u64)),
-            ),
+            Command::new("search")
+                .about("Find the states containing a package")
+                .long_about(
+                    "Find the states containing a package\n\n\
+                     Packages are given by name, matching every installed version, or by id",
+                )
+                .arg(arg!(<PACKAGE> "Package name or id").action(ArgAction::Set))
+                .arg(arg!(--version <VERSION> "Only match this version, e.g. `8.3` or `8.3-1`").action(ArgAction::Set))
+                .arg(arg!(--json "Output in JSON format").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("repair")
+                .about("Re-derive which packages were explicitly installed")
+                .long_about(
+                    "Re-derive which packages were explicitly installed\n\n\
+                     Packages only required as dependencies of others in a state are marked as transitive",
+                )
+                .arg(arg!(--"dry-run" "Print the changes without recording them").action(ArgAction::SetTrue)),
         )
         .subcommand(
             Command::new("verify")
-                .about("Verify TODO")
+                .about("Verify installed files against the layouts of a state")
+                .long_about(
+                    "Verify installed files against the layouts of a state\n\n\
+                     Files are checked for their presence, content, symlink target and type. Extra files beneath \
+                     /usr, such as those generated by triggers, are reported without failing verification",
+                )
+                .arg(
+                    arg!(--state <ID> "Verify the archived tree of this state instead of the active state")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(--json "Output in JSON format").action(ArgAction::SetTrue))
+                .arg(
+                    arg!(--repair "Verify the cached assets of all states instead, reblitting affected states")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["state", "json"]),
+                )
                 .arg(arg!(--verbose "Vebose output").action(ArgAction::SetTrue)),
         )
 }
 
+/// The `rollback` command, activating the state preceding the active state
+pub fn rollback_command() -> Command {
+    Command::new("rollback")
+        .about("Roll back to the previous state")
+        .long_about(
+            "Roll back to the previous state\n\n\
+             The packages of the state preceding the active state are applied as a new state",
+        )
+        .arg(fetch_arg())
+        .arg(skip_triggers_arg())
+        .arg(summary_arg())
+}
+
+fn state_arg(help: &'static str) -> Arg {
+    Arg::new("ID")
+        .help(help)
+        .required(true)
+        .action(ArgAction::Set)
+        .value_parser(clap::value_parser!(state::Reference))
+}
+
+/// Resolve the state given as the argument `name`
+fn resolve(client: &Client, args: &ArgMatches, name: &str) -> Result<state::Id, Error> {
+    let reference = *args.get_one::<state::Reference>(name).unwrap();
+
+    Ok(client.resolve_state(reference)?)
+}
+
+/// The `--summary` of states recorded by a command, replacing the generated one
+pub fn summary_arg() -> Arg {
+    arg!(--summary <SUMMARY> "Summary of the new state, instead of one describing its changes").action(ArgAction::Set)
+}
+
+/// The summary passed via [`summary_arg`]
+pub fn summary_override(args: &ArgMatches) -> Option<String> {
+    args.get_one::<String>("summary").cloned()
+}
+
+/// Warn of a state left incomplete by an interrupted transaction, which blocks any
+/// new transaction until dealt with
+pub fn warn_incomplete(client: &Client) -> Result<(), client::Error> {
+    if let Some(state) = client.incomplete_state()? {
+        eprintln!(
+            "{} | The transaction of state {} was interrupted, retry it via `moss state resume` \
+             or give up on it via `moss state discard`",
+            "Warning".yellow(),
+            state.id
+        );
+    }
+
+    Ok(())
+}
+
+fn skip_triggers_arg() -> Arg {
+    arg!(--"skip-triggers" "Do not run system triggers nor synchronize boot entries on activation")
+        .action(ArgAction::SetTrue)
+}
+
+fn fetch_arg() -> Arg {
+    arg!(--fetch "Download packages of the state which are no longer cached").action(ArgAction::SetTrue)
+}
+
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     match args.subcommand() {
         Some(("active", _)) => active(installation),
-        Some(("list", _)) => list(installation),
+        Some(("list", args)) => list(args, installation),
+        Some(("show", args)) => show(args, installation),
+        Some(("tree", _)) => tree(installation),
+        Some(("export", args)) => export(args, installation),
+        Some(("snapshot", args)) => snapshot(args, installation),
         Some(("activate", args)) => activate(args, installation),
+        Some(("describe", args)) => describe(args, installation),
+        Some(("pin", args)) => pin(args, installation, true),
+        Some(("unpin", args)) => pin(args, installation, false),
         Some(("prune", args)) => prune(args, installation),
         Some(("remove", args)) => remove(args, installation),
+        Some(("diff", args)) => diff(args, installation),
+        Some(("resume", args)) => resume(args, installation),
+        Some(("discard", _)) => discard(installation),
+        Some(("search", args)) => search(args, installation),
+        Some(("repair", args)) => repair(args, installation),
         Some(("verify", args)) => verify(args, installation),
         _ => unreachable!(),
     }
@@ -75,14 +328,19 @@ pub fn active(installation: Installation) -> Result<(), Error> {
 
         let state = client.state_db.get(id)?;
 
-        print_state(state);
+        print_state(state, false);
     }
 
     Ok(())
 }
 
 /// List all known states, newest first
-pub fn list(installation: Installation) -> Result<(), Error> {
+pub fn list(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let pinned = args.get_flag("pinned");
+    let kind = args
+        .get_one::<String>("kind")
+        .map(|kind| state::Kind::from(kind.clone()));
+
     let client = Client::new(environment::NAME, installation)?;
 
     let state_ids = client.state_db.list_ids()?;
@@ -90,46 +348,596 @@ pub fn list(installation: Installation) -> Result<(), Error> {
     let mut states = state_ids
         .into_iter()
         .map(|(id, _)| client.state_db.get(id).map_err(Error::DB))
+        .filter(|state| {
+            state.as_ref().map_or(true, |state| {
+                (!pinned || state.pinned) && kind.map_or(true, |kind| state.kind == kind)
+            })
+        })
         .collect::<Result<Vec<_>, _>>()?;
 
+    // Refcounted across all states in a single pass
+    let sizes = if args.get_flag("sizes") {
+        Some(client.usage()?.states())
+    } else {
+        None
+    };
+
     states.reverse();
-    states.into_iter().for_each(print_state);
+    let verbose = args.get_flag("verbose");
+    for state in states {
+        print_details(&state, verbose);
+        if let Some(sizes) = &sizes {
+            print_sizes(sizes.get(&state.id).copied().unwrap_or_default());
+        }
+        println!();
+    }
     Ok(())
 }
 
-pub fn activate(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let new_id = *args.get_one::<u64>("ID").unwrap() as i32;
-    let skip_triggers = args.get_flag("skip-triggers");
+/// Edit the summary and description of a state
+pub fn describe(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let summary = args.get_one::<String>("summary");
+    let description = args.get_one::<String>("description");
 
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(super::boot::manage_override(args));
+    let id = resolve(&client, args, "ID")?;
+    let state = client
+        .state_db
+        .describe(id, summary.map(String::as_str), description.map(String::as_str))
+        .map_err(|error| match error {
+            moss::db::Error::RowNotFound => Error::NoSuchState(id),
+            error => Error::DB(error),
+        })?;
+
+    print_details(&state, true);
+    println!();
+
+    // Titles are rendered from the state, so refresh them where it's booted from
+    if let Some(active) = client.installation.active_state {
+        let head = client.state_db.get(active)?;
+        if boot::transaction_states(&client, &head)?
+            .iter()
+            .any(|state| state.id == id)
+        {
+            println!("{}", boot::synchronize(&client, &head)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Pin or unpin a state
+pub fn pin(args: &ArgMatches, installation: Installation, pinned: bool) -> Result<(), Error> {
     let client = Client::new(environment::NAME, installation)?;
-    let old_id = client.activate_state(new_id.into(), skip_triggers)?;
+    let id = resolve(&client, args, "ID")?;
+    let is_active = client.installation.active_state == Some(id);
+
+    client.state_db.set_pinned(id, pinned)?;
+
+    if pinned {
+        println!("State {} pinned", id.to_string().bold());
+    } else {
+        println!("State {} unpinned", id.to_string().bold());
+
+        if is_active {
+            eprintln!(
+                "{} | State {id} remains protected from pruning while it is active",
+                "Warning".yellow()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Retry the transaction of the interrupted state
+pub fn resume(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(super::boot::manage_override(args));
+    let (state, boot) = client.resume_state()?;
+
+    println!("State {} completed", state.id.to_string().bold());
+    println!("{boot}");
+
+    Ok(())
+}
+
+/// Give up on the interrupted state
+pub fn discard(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let state = client.discard_state()?;
+
+    println!("State {} abandoned", state.id.to_string().bold());
+
+    if client.installation.active_state == Some(state.id) {
+        eprintln!(
+            "{} | The root was left with the interrupted tree of state {}, activate another state to replace it",
+            "Warning".yellow(),
+            state.id
+        );
+    }
+
+    Ok(())
+}
+
+/// Record a copy of the active state, without any package changes
+pub fn snapshot(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let Some(active) = installation.active_state else {
+        return Err(Error::Client(client::Error::NoActiveState));
+    };
+    let summary = args.get_one::<String>("summary").map_or("Snapshot", String::as_str);
+    let description = args.get_one::<String>("description");
+
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(super::boot::manage_override(args));
+    warn_incomplete(&client)?;
+
+    let selections = client.state_db.get(active)?.selections;
+
+    if let Some((state, boot)) = client.new_state_with_kind(
+        &selections,
+        Some(summary),
+        description.map(String::as_str),
+        state::Kind::Snapshot,
+    )? {
+        println!(
+            "State {} recorded {}",
+            state.id.to_string().bold(),
+            format!("(snapshot of state {active})").dim()
+        );
+        println!("{boot}");
+    }
+
+    Ok(())
+}
+
+/// Show a single state in full, along with its stored kernel command line
+pub fn show(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    let id = resolve(&client, args, "ID")?;
+    let state = client.state_db.get(id).map_err(|error| match error {
+        moss::db::Error::RowNotFound => Error::NoSuchState(id),
+        error => Error::DB(error),
+    })?;
+    let cmdline = client.state_db.cmdline(id)?;
+    let sizes = client.state_usage(id)?;
+
+    let selections = state
+        .selections
+        .iter()
+        .map(|selection| {
+            let meta = client.install_db.get(&selection.package).ok();
+
+            SelectionDetails {
+                id: &selection.package,
+                name: meta.as_ref().map(|meta| meta.name.to_string()),
+                version: meta
+                    .as_ref()
+                    .map(|meta| format!("{}-{}", meta.version_identifier, meta.source_release)),
+                explicit: selection.explicit,
+                reason: selection.reason.as_ref(),
+            }
+        })
+        .collect::<Vec<_>>();
+    let explicit = selections.iter().filter(|selection| selection.explicit).count();
+
+    if args.get_flag("json") {
+        let details = Details {
+            id: state.id,
+            parent: state.parent,
+            kind: state.kind.to_string(),
+            summary: state.summary.as_deref(),
+            description: state.description.as_deref(),
+            created: state.created.to_rfc3339(),
+            updated: state.updated.map(|updated| updated.to_rfc3339()),
+            pinned: state.pinned,
+            packages: selections.len(),
+            explicit,
+            transitive: selections.len() - explicit,
+            cmdline: cmdline.as_deref(),
+            size: sizes,
+            selections,
+        };
+        println!("{}", serde_json::to_string_pretty(&details)?);
+        return Ok(());
+    }
+
+    print_details(&state, true);
+    println!(
+        "{} {} explicit, {} transitive",
+        "Selections:".bold(),
+        explicit,
+        selections.len() - explicit
+    );
+    print_sizes(sizes);
+    println!(
+        "{} {}",
+        "Cmdline:".bold(),
+        cmdline.as_deref().unwrap_or("not yet synchronized")
+    );
+
+    if !selections.is_empty() {
+        println!();
+        print_columns(&selections, 1);
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Export a state to a manifest, written to stdout unless an output file is given
+pub fn export(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let id = resolve(&client, args, "ID")?;
+    let json = client.export_state(id)?.to_json();
+
+    match args.get_one::<PathBuf>("output") {
+        Some(path) => {
+            fs::write(path, format!("{json}\n"))?;
+            println!("State {} exported to {}", id.to_string().bold(), path.display());
+        }
+        None => println!("{json}"),
+    }
+
+    Ok(())
+}
+
+/// JSON output of `moss state show`
+#[derive(Serialize)]
+struct Details<'a> {
+    id: state::Id,
+    /// Unknown for states recorded before lineage was tracked
+    parent: Option<state::Id>,
+    kind: String,
+    summary: Option<&'a str>,
+    description: Option<&'a str>,
+    created: String,
+    updated: Option<String>,
+    pinned: bool,
+    packages: usize,
+    explicit: usize,
+    transitive: usize,
+    cmdline: Option<&'a str>,
+    /// Asset sizes in bytes
+    size: usage::Sizes,
+    selections: Vec<SelectionDetails<'a>>,
+}
+
+/// A selection of `moss state show`, resolved against the install db
+#[derive(Serialize)]
+struct SelectionDetails<'a> {
+    id: &'a package::Id,
+    /// Name and version, unless the package is no longer installed
+    name: Option<String>,
+    version: Option<String>,
+    explicit: bool,
+    reason: Option<&'a state::Reason>,
+}
+
+impl ColumnDisplay for SelectionDetails<'_> {
+    fn get_display_width(&self) -> usize {
+        self.name.as_ref().map_or(self.id.to_string().len(), String::len)
+            + self.version.as_ref().map_or(0, |version| version.len() + 1)
+    }
+
+    fn display_column(&self, writer: &mut impl Write, _col: Column, width: usize) {
+        let name = self.name.clone().unwrap_or_else(|| self.id.to_string());
+        let name = if self.explicit { name.bold() } else { name.dim() };
+
+        let _ = write!(writer, "{name}");
+        if let Some(version) = &self.version {
+            let _ = write!(writer, " {}", version.clone().magenta());
+        }
+        let _ = write!(writer, "{:width$}", " ");
+        if let Some(reason) = self.reason {
+            let _ = write!(writer, " {}", reason.to_string().dim());
+        }
+    }
+}
+
+/// Coarse age of a state, e.g. `3 days ago`
+fn ago(age: Duration) -> String {
+    let days = age.num_days();
+    let (count, unit) = [
+        (days / 365, "year"),
+        (days / 30, "month"),
+        (age.num_weeks(), "week"),
+        (days, "day"),
+        (age.num_hours(), "hour"),
+        (age.num_minutes(), "minute"),
+    ]
+    .into_iter()
+    .find(|(count, _)| *count > 0)
+    .unwrap_or((0, ""));
+
+    match count {
+        0 => "just now".to_owned(),
+        1 => format!("1 {unit} ago"),
+        count => format!("{count} {unit}s ago"),
+    }
+}
+
+/// Print the lineage of all states as a tree
+pub fn tree(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let states = client.state_db.all()?;
+
+    print_columns(&state::tree(&states), 1);
+
+    Ok(())
+}
+
+pub fn activate(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    activate_state(args, installation, args.get_one::<state::Reference>("ID").copied())
+}
+
+/// Activate the state preceding the active state
+pub fn rollback(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    activate_state(args, installation, None)
+}
+
+/// Activate the `target` state, or the state preceding the active state
+fn activate_state(
+    args: &ArgMatches,
+    installation: Installation,
+    target: Option<state::Reference>,
+) -> Result<(), Error> {
+    let fetch = args.get_flag("fetch");
+
+    let client = Client::new(environment::NAME, installation)?
+        .with_boot_manage(super::boot::manage_override(args))
+        .with_summary(summary_override(args))
+        .with_skip_triggers(args.get_flag("skip-triggers"));
+    warn_incomplete(&client)?;
+
+    let id = target.map(|reference| client.resolve_state(reference)).transpose()?;
+    let rollback = client.rollback(id, fetch)?;
 
     println!(
         "State {} activated {}",
-        new_id.to_string().bold(),
-        format!("({old_id} archived)").dim()
+        rollback.source.to_string().bold(),
+        format!("(recorded as state {})", rollback.state.id).dim()
     );
+    println!("{}", rollback.boot);
+
+    if !rollback.diff.is_empty() {
+        println!();
+        autoprint_columns(&rollback.diff.lines());
+        println!();
+        println!(
+            "{} added, {} removed, {} changed, {} flipped",
+            rollback.diff.added.len(),
+            rollback.diff.removed.len(),
+            rollback.diff.changed.len(),
+            rollback.diff.flipped.len()
+        );
+    }
 
     Ok(())
 }
 
 pub fn prune(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let keep = *args.get_one::<u64>("keep").unwrap();
-    let include_newer = args.get_flag("include-newer");
     let yes = args.get_flag("yes");
+    let dry_run = args.get_flag("dry-run");
 
-    let client = Client::new(environment::NAME, installation)?;
-    client.prune(prune::Strategy::KeepRecent { keep, include_newer }, yes)?;
+    let policy = prune::Policy::load(&config::Manager::system(&installation.root, "moss")).merge(prune::Policy {
+        keep: args.get_one::<u64>("keep").copied(),
+        weekly: args.get_one::<u64>("weekly").copied(),
+        pinned: None,
+        include_newer: args.get_flag("include-newer").then_some(true),
+    });
+
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(super::boot::manage_override(args));
+    client.prune(prune::Strategy::Retain(policy), yes, dry_run)?;
 
     Ok(())
 }
 
 pub fn remove(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let id = *args.get_one::<u64>("ID").unwrap() as i32;
     let yes = args.get_flag("yes");
+    let dry_run = args.get_flag("dry-run");
+
+    let client = Client::new(environment::NAME, installation)?.with_boot_manage(super::boot::manage_override(args));
+    let id = resolve(&client, args, "ID")?;
+    client.prune(prune::Strategy::Remove(id), yes, dry_run)?;
+
+    Ok(())
+}
+
+/// Compare the selections of two states
+pub fn diff(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let json = args.get_flag("json");
 
     let client = Client::new(environment::NAME, installation)?;
-    client.prune(prune::Strategy::Remove(id.into()), yes)?;
+    let from = resolve(&client, args, "FROM")?;
+    let to = client.resolve_state(
+        args.get_one::<state::Reference>("TO")
+            .copied()
+            .unwrap_or(state::Reference::Active(0)),
+    )?;
+    let diff = state::diff(&client.state_db.get(from)?, &client.state_db.get(to)?, |id| {
+        client.install_db.get(id).ok()
+    });
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    println!("State #{} → State #{}", from.to_string().bold(), to.to_string().bold());
+    println!();
+
+    if diff.is_empty() {
+        println!("No differences");
+        return Ok(());
+    }
+
+    autoprint_columns(&diff.lines());
+    println!();
+    println!(
+        "{} added, {} removed, {} changed, {} flipped",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.flipped.len()
+    );
+
+    Ok(())
+}
+
+/// Find every state selecting a package, given by name or id
+pub fn search(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let package = args.get_one::<String>("PACKAGE").unwrap();
+    let version = args.get_one::<String>("version");
+
+    let client = Client::new(environment::NAME, installation)?;
+    let active = client.installation.active_state;
+
+    // An id matches itself, a name every installed package of that name
+    let id = package::Id::from(package.clone());
+    let mut candidates = client
+        .install_db
+        .query(Some(Filter::Name(package::Name::from(package.clone()))))?;
+    if let Ok(meta) = client.install_db.get(&id) {
+        candidates.push((id.clone(), meta));
+    }
+    let candidates = candidates
+        .into_iter()
+        .filter(|(_, meta)| {
+            version.map_or(true, |version| {
+                *version == meta.version_identifier
+                    || *version == format!("{}-{}", meta.version_identifier, meta.source_release)
+            })
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    // Without metadata an id can still be looked up, just not by version
+    let ids = if candidates.is_empty() && version.is_none() {
+        vec![&id]
+    } else {
+        candidates.keys().collect()
+    };
+
+    let matches = client
+        .state_db
+        .containing(ids)?
+        .into_iter()
+        .map(|containing| {
+            let meta = candidates.get(&containing.selection.package);
+
+            SearchMatch {
+                state: containing.state,
+                kind: containing.kind.to_string(),
+                created: containing.created.to_rfc3339(),
+                local: containing
+                    .created
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                active: Some(containing.state) == active,
+                name: meta.map(|meta| meta.name.to_string()),
+                version: meta.map(|meta| format!("{}-{}", meta.version_identifier, meta.source_release)),
+                id: containing.selection.package,
+                explicit: containing.selection.explicit,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if args.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&matches)?);
+        return Ok(());
+    }
+
+    if matches.is_empty() {
+        println!("No state contains {package}");
+        return Ok(());
+    }
+
+    print_columns(&matches, 1);
+
+    Ok(())
+}
+
+/// A state selecting the package searched for by `moss state search`
+#[derive(Serialize)]
+struct SearchMatch {
+    state: state::Id,
+    kind: String,
+    created: String,
+    /// Creation time for display
+    #[serde(skip)]
+    local: String,
+    active: bool,
+    id: package::Id,
+    /// Name and version, unless the package is no longer installed
+    name: Option<String>,
+    version: Option<String>,
+    explicit: bool,
+}
+
+impl ColumnDisplay for SearchMatch {
+    fn get_display_width(&self) -> usize {
+        self.state.to_string().len() + 2 + self.kind.len() + ACTIVE.len()
+    }
+
+    fn display_column(&self, writer: &mut impl Write, _col: Column, width: usize) {
+        let package = match (&self.name, &self.version) {
+            (Some(name), Some(version)) => format!("{name} {version}"),
+            _ => self.id.to_string(),
+        };
+        let mut state = format!("#{} {}", self.state, self.kind);
+        if self.active {
+            state = format!("{}{}", state.bold(), ACTIVE.green());
+        } else {
+            state.push_str(&" ".repeat(ACTIVE.len()));
+        }
+
+        let _ = write!(writer, "{state}{:width$} {}  {package}", " ", self.local);
+    }
+}
+
+/// Marker of the active state in `moss state search`
+const ACTIVE: &str = " (active)";
+
+/// Re-derive the explicit selections of all states
+pub fn repair(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let dry_run = args.get_flag("dry-run");
+
+    let client = Client::new(environment::NAME, installation)?;
+    let changes = client.repair_selections(dry_run)?;
+
+    if changes.is_empty() {
+        println!("All selections are up to date");
+        return Ok(());
+    }
+
+    let name = |id: &package::Id| {
+        client
+            .install_db
+            .get(id)
+            .map_or_else(|_| id.to_string(), |meta| meta.name.to_string())
+    };
+
+    for change in &changes {
+        println!(
+            "State #{} - {} package(s) marked as transitive",
+            change.state.to_string().bold(),
+            change.demoted.len()
+        );
+        for id in &change.demoted {
+            println!("  » {}", name(id));
+        }
+    }
+
+    let total = changes.iter().map(|change| change.demoted.len()).sum::<usize>();
+    println!();
+    if dry_run {
+        println!(
+            "{total} selection(s) across {} state(s) would be changed",
+            changes.len()
+        );
+    } else {
+        println!("{total} selection(s) across {} state(s) changed", changes.len());
+    }
 
     Ok(())
 }
@@ -139,27 +947,102 @@ pub fn verify(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let yes = args.get_flag("yes");
 
     let client = Client::new(environment::NAME, installation)?;
-    client.verify(yes, verbose)?;
+    let state = args
+        .get_one::<state::Reference>("state")
+        .map(|reference| client.resolve_state(*reference))
+        .transpose()?;
 
-    Ok(())
+    if args.get_flag("repair") {
+        client.verify(yes, verbose)?;
+        return Ok(());
+    }
+
+    let report = client.verify_files(state)?;
+
+    if args.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for finding in &report.findings {
+            match finding {
+                verify::Finding::Extra { .. } if !verbose => {}
+                verify::Finding::Extra { .. } => println!(" {} {finding}", "?".dim()),
+                _ => println!(" {} {finding}", "×".yellow()),
+            }
+        }
+
+        println!(
+            "Verified {} paths of state {}: {} missing, {} modified, {} extra",
+            report.checked,
+            report.state.to_string().bold(),
+            report.missing,
+            report.modified,
+            report.extra
+        );
+    }
+
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(Error::VerifyFailed(report.state))
+    }
 }
 
 /// Emit a state description for the TUI
-fn print_state(state: state::State) {
+fn print_state(state: state::State, verbose: bool) {
+    print_details(&state, verbose);
+    println!();
+}
+
+/// Emit the details of a state, without a trailing separator
+fn print_details(state: &state::State, verbose: bool) {
     let local_time = state.created.with_timezone(&Local);
     let formatted_time = local_time.format("%Y-%m-%d %H:%M:%S %Z");
 
+    let pinned = if state.pinned { " (pinned)" } else { "" };
+    let incomplete = match state.kind {
+        state::Kind::Incomplete => " (incomplete)",
+        state::Kind::Abandoned => " (abandoned)",
+        _ => "",
+    };
+
     println!(
-        "State #{} - {}",
+        "State #{} - {}{}{}",
         state.id.to_string().bold(),
-        state.summary.unwrap_or_else(|| String::from("system transaction"))
+        state.summary.as_deref().unwrap_or("system transaction"),
+        pinned.yellow(),
+        incomplete.red()
     );
-    println!("{} {formatted_time}", "Created:".bold());
+    if verbose {
+        let ago = ago(Utc::now().signed_duration_since(state.created));
+        println!("{} {formatted_time} {}", "Created:".bold(), format!("({ago})").dim());
+    } else {
+        println!("{} {formatted_time}", "Created:".bold());
+    }
+    if verbose {
+        if let Some(updated) = state.updated {
+            let updated = updated.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S %Z");
+            println!("{} {updated}", "Updated:".bold());
+        }
+        println!("{} {}", "Kind:".bold(), state.kind);
+        match state.parent {
+            Some(parent) => println!("{} #{parent}", "Parent:".bold()),
+            None => println!("{} unknown", "Parent:".bold()),
+        }
+    }
     if let Some(desc) = &state.description {
         println!("{} {desc}", "Description:".bold());
     }
     println!("{} {}", "Packages:".bold(), state.selections.len());
-    println!();
+}
+
+/// Emit the asset sizes of a state
+fn print_sizes(sizes: usage::Sizes) {
+    println!(
+        "{} {} total, {} unique",
+        "Size:".bold(),
+        usage::format_size(sizes.total),
+        usage::format_size(sizes.unique)
+    );
 }
 
 #[derive(Debug, Error)]
@@ -169,4 +1052,28 @@ pub enum Error {
 
     #[error("db")]
     DB(#[from] moss::db::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+
+    #[error("rollback")]
+    Rollback(#[from] rollback::Error),
+
+    #[error("state")]
+    Resolve(#[from] resolve::Error),
+
+    #[error("export")]
+    Export(#[from] manifest::Error),
+
+    #[error("io")]
+    Io(#[from] std::io::Error),
+
+    #[error("boot")]
+    Boot(#[from] boot::Error),
+
+    #[error("state {0} doesn't exist")]
+    NoSuchState(state::Id),
+
+    #[error("state {0} failed verification")]
+    VerifyFailed(state::Id),
 }
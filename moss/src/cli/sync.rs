@@ -2,12 +2,12 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
 
 use clap::{arg, value_parser, ArgMatches, Command};
 use moss::registry::transaction;
-use moss::state::Selection;
+use moss::state::{Reason, Selection};
 use moss::{
     client::{self, Client},
     package::{self},
@@ -36,6 +36,7 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(super::state::summary_arg())
 }
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
@@ -43,12 +44,15 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let update = *args.get_one::<bool>("update").unwrap();
     let upgrade_only = *args.get_one::<bool>("upgrade-only").unwrap();
 
-    let mut client = Client::new(environment::NAME, installation)?;
+    let mut client = Client::new(environment::NAME, installation)?
+        .with_boot_manage(super::boot::manage_override(args))
+        .with_summary(super::state::summary_override(args));
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
         client = client.ephemeral(blit_target)?;
     }
+    super::state::warn_incomplete(&client)?;
 
     // Update repos if requested
     if update {
@@ -65,7 +69,7 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     }
 
     // Resolve the final state of packages after considering sync updates
-    let finalized = resolve_with_sync(&client, upgrade_only, &installed)?;
+    let (finalized, dependency_of) = resolve_with_sync(&client, upgrade_only, &installed)?;
 
     // Synced are packages are:
     //
@@ -141,24 +145,34 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
                         ..s
                     })
                     // Must be transitive
-                    .unwrap_or(Selection {
-                        package: p.id,
-                        explicit: false,
-                        reason: None,
+                    .unwrap_or_else(|| {
+                        let selection = Selection::transitive(p.id.clone());
+
+                        match dependency_of.get(&p.id) {
+                            Some(name) => selection.reason(Reason::DependencyOf(name.clone())),
+                            None => selection,
+                        }
                     })
             })
             .collect::<Vec<_>>()
     };
 
     // Perfect, apply state.
-    client.new_state(&new_selections, "Sync")?;
+    if let Some((_, boot)) = client.new_state(&new_selections)? {
+        println!("{boot}");
+    }
 
     Ok(())
 }
 
 /// Returns the resolved package set w/ sync'd changes swapped in using
-/// the provided `packages`
-fn resolve_with_sync(client: &Client, upgrade_only: bool, packages: &[Package]) -> Result<Vec<Package>, Error> {
+/// the provided `packages`, along with the name of the package pulling
+/// in each dependency
+fn resolve_with_sync(
+    client: &Client,
+    upgrade_only: bool,
+    packages: &[Package],
+) -> Result<(Vec<Package>, BTreeMap<package::Id, package::Name>), Error> {
     let all_ids = packages.iter().map(|p| &p.id).collect::<BTreeSet<_>>();
 
     // For each package, replace it w/ it's sync'd change (if available)
@@ -208,7 +222,19 @@ fn resolve_with_sync(client: &Client, upgrade_only: bool, packages: &[Package])
     tx.add(explicit)?;
 
     // Resolve the tx
-    Ok(client.resolve_packages(tx.finalize())?)
+    let resolved = client.resolve_packages(tx.finalize())?;
+
+    let dependency_of = resolved
+        .iter()
+        .filter_map(|p| {
+            let parent = tx.dependency_of(&p.id)?;
+            let parent = resolved.iter().find(|r| r.id == *parent)?;
+
+            Some((p.id.clone(), parent.meta.name.clone()))
+        })
+        .collect();
+
+    Ok((resolved, dependency_of))
 }
 
 #[derive(Debug, Error)]
@@ -0,0 +1,214 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Architecture namespacing for boot partitions shared between installations
+//!
+//! Installations of differing architectures, e.g. an x86_64 and an aarch64 root on a
+//! removable disk, may share a single ESP. Every moss generated entry therefore
+//! carries the `architecture` key (which also hides foreign entries in systemd-boot),
+//! a file name suffixed with the architecture and its kernel assets moved into a
+//! per-architecture directory. The architecture is the packaged one of the
+//! installation, never that of the running kernel.
+//!
+//! blsforme chooses the entry and asset names, so entries are namespaced once it has
+//! written them, moving the assets out of its shared directories. Entries lacking
+//! the `architecture` key predate namespacing and are owned by any architecture,
+//! being migrated by the next sync.
+
+use std::{collections::BTreeMap, fmt};
+
+use super::entry::LoaderEntry;
+
+/// Package architectures and their EFI names, as used by the `architecture` key
+const ARCHITECTURES: &[(&str, &str)] = &[
+    ("x86_64", "x64"),
+    ("x86", "ia32"),
+    ("i686", "ia32"),
+    ("aarch64", "aa64"),
+    ("armv7h", "arm"),
+    ("riscv64", "riscv64"),
+    ("loongarch64", "loongarch64"),
+];
+
+/// Assets installed by moss itself, which are already shared safely between entries
+const MOSS_ASSETS: &str = "EFI/moss/";
+
+/// Keys of entry lines referencing kernel assets written by blsforme
+const ASSET_KEYS: &[&str] = &["linux", "initrd"];
+
+/// The EFI architecture of an installation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arch(&'static str);
+
+impl Arch {
+    /// The EFI architecture of the package `architecture`, if known
+    pub fn from_package(architecture: &str) -> Option<Self> {
+        ARCHITECTURES
+            .iter()
+            .find(|(package, _)| *package == architecture)
+            .map(|(_, efi)| Self(efi))
+    }
+
+    /// The EFI architecture of an EFI name, e.g. `x64`
+    pub fn from_efi(name: &str) -> Option<Self> {
+        ARCHITECTURES
+            .iter()
+            .find(|(_, efi)| *efi == name)
+            .map(|(_, efi)| Self(efi))
+    }
+
+    /// The predominant architecture of an installation's package `architectures`
+    ///
+    /// Architecture independent and multilib packages are outnumbered by native ones.
+    pub fn of_packages(architectures: impl IntoIterator<Item = impl AsRef<str>>) -> Option<Self> {
+        let mut counts = BTreeMap::new();
+
+        for arch in architectures
+            .into_iter()
+            .filter_map(|architecture| Self::from_package(architecture.as_ref()))
+        {
+            *counts.entry(arch.0).or_insert(0usize) += 1;
+        }
+
+        counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(efi, _)| Self(efi))
+    }
+
+    /// Returns true if `entry` belongs to this architecture
+    ///
+    /// Entries without an architecture predate namespacing and belong to any.
+    pub fn owns(&self, entry: &LoaderEntry) -> bool {
+        entry.architecture.as_deref().map_or(true, |arch| arch == self.0)
+    }
+
+    /// Suffix the file `name` with the architecture, ahead of its extension
+    pub fn file_name(&self, name: &str) -> String {
+        let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+
+        if stem.ends_with(&format!("-{}", self.0)) {
+            return name.to_owned();
+        }
+
+        match extension {
+            "" => format!("{stem}-{}", self.0),
+            extension => format!("{stem}-{}.{extension}", self.0),
+        }
+    }
+
+    /// Move the `asset` path into a per-architecture directory next to it
+    pub fn asset(&self, asset: &str) -> String {
+        let Some((dir, file)) = asset.rsplit_once('/') else {
+            return format!("{}/{asset}", self.0);
+        };
+
+        if dir.rsplit('/').next() == Some(self.0) {
+            asset.to_owned()
+        } else {
+            format!("{dir}/{}/{file}", self.0)
+        }
+    }
+
+    /// Namespace the `contents` of a loader entry, returning the rewritten contents and
+    /// the ESP-relative asset moves `(from, to)` it depends on
+    pub fn namespace(&self, contents: &str) -> (String, Vec<(String, String)>) {
+        let mut moves = vec![];
+        let mut lines = vec![];
+
+        for line in contents.lines() {
+            let (key, value) = line
+                .trim()
+                .split_once(char::is_whitespace)
+                .map_or((line.trim(), ""), |(key, value)| (key, value.trim()));
+
+            if key == "architecture" {
+                continue;
+            }
+
+            let asset = value.trim_start_matches('/');
+            if ASSET_KEYS.contains(&key) && !asset.starts_with(MOSS_ASSETS) {
+                let namespaced = self.asset(asset);
+                if namespaced != asset {
+                    moves.push((asset.to_owned(), namespaced.clone()));
+                }
+                lines.push(format!("{key} /{namespaced}"));
+            } else {
+                lines.push(line.to_owned());
+            }
+        }
+
+        lines.push(format!("architecture {}", self.0));
+
+        let mut output = lines.join("\n");
+        output.push('\n');
+
+        (output, moves)
+    }
+
+    /// The architecture suffix of a namespaced file `name`, if any
+    pub fn of_file_name(name: &str) -> Option<Self> {
+        let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+        let (_, suffix) = stem.rsplit_once('-')?;
+
+        Self::from_efi(suffix)
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn namespace_entry() {
+        let arch = Arch::of_packages(["x86_64", "x86", "x86_64", "noarch"]).unwrap();
+        assert_eq!(arch.to_string(), "x64");
+
+        assert_eq!(
+            arch.file_name("aerynos-6.12.9-1-12.conf"),
+            "aerynos-6.12.9-1-12-x64.conf"
+        );
+        assert_eq!(
+            arch.file_name("aerynos-6.12.9-1-12-x64.conf"),
+            "aerynos-6.12.9-1-12-x64.conf"
+        );
+        assert_eq!(Arch::of_file_name("moss-12-6.12.9-1-x64.efi"), Some(arch));
+        assert_eq!(Arch::of_file_name("moss-12-6.12.9-1.efi"), None);
+
+        let (contents, moves) = arch.namespace(
+            "title AerynOS\nlinux /EFI/os/6.12.9-1/vmlinuz\ninitrd /EFI/moss/ucode/intel-ucode.img\n\
+             initrd /EFI/os/6.12.9-1/initrd\noptions moss.fstx=12\n",
+        );
+        assert_eq!(
+            contents,
+            "title AerynOS\nlinux /EFI/os/6.12.9-1/x64/vmlinuz\ninitrd /EFI/moss/ucode/intel-ucode.img\n\
+             initrd /EFI/os/6.12.9-1/x64/initrd\noptions moss.fstx=12\narchitecture x64\n"
+        );
+        assert_eq!(
+            moves,
+            vec![
+                (
+                    "EFI/os/6.12.9-1/vmlinuz".to_owned(),
+                    "EFI/os/6.12.9-1/x64/vmlinuz".to_owned()
+                ),
+                (
+                    "EFI/os/6.12.9-1/initrd".to_owned(),
+                    "EFI/os/6.12.9-1/x64/initrd".to_owned()
+                ),
+            ]
+        );
+        // Already namespaced entries are left as they are
+        assert_eq!(arch.namespace(&contents), (contents.clone(), vec![]));
+
+        let foreign = LoaderEntry::parse("aarch64.conf", "title AerynOS\narchitecture aa64\n");
+        assert!(!arch.owns(&foreign));
+        assert!(arch.owns(&LoaderEntry::parse("legacy.conf", "title AerynOS\n")));
+    }
+}
@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Bootloader backends supported by boot synchronization
+
+use std::str::FromStr;
+
+use fnmatch::Pattern;
+use serde::{Deserialize, Serialize};
+use stone::payload::layout::{self, Layout};
+
+use crate::package::Id;
+
+use super::Error;
+
+/// The bootloader moss synchronizes entries for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Backend {
+    /// systemd-boot, consuming BLS type #1 entries written via blsforme
+    SystemdBoot,
+    /// GRUB, consuming a generated `grub.d` snippet
+    Grub,
+}
+
+impl Backend {
+    /// Backends in order of auto-detection preference
+    const ALL: [Self; 2] = [Self::SystemdBoot, Self::Grub];
+
+    /// Default patterns for the backend's bootloader assets, relative to `/usr`
+    pub fn asset_patterns(&self) -> &'static [&'static str] {
+        match self {
+            Backend::SystemdBoot => &["lib*/systemd/boot/efi/*.efi", "lib*/shim/*.efi", "share/shim/*.efi"],
+            Backend::Grub => &["lib*/grub/*/*.mod"],
+        }
+    }
+
+    /// Pattern of the backend's own assets, identifying an installed bootloader
+    ///
+    /// Shared assets such as shim don't identify a backend, as shim chain loads either.
+    fn detection_pattern(&self) -> &'static str {
+        self.asset_patterns()[0]
+    }
+
+    /// Detect the backend from the layouts of a state, preferring systemd-boot
+    /// when multiple bootloaders are installed
+    pub fn detect(layouts: &[(Id, Layout)]) -> Result<Option<Self>, Error> {
+        for backend in Self::ALL {
+            let patterns = [Pattern::from_str(backend.detection_pattern())?];
+
+            if !assets(layouts, &patterns).is_empty() {
+                return Ok(Some(backend));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Return all regular files within `layouts` matching any of `patterns`, relative to `/usr`
+pub fn assets<'a>(layouts: &'a [(Id, Layout)], patterns: &[Pattern]) -> Vec<&'a str> {
+    layouts
+        .iter()
+        .filter_map(|(_, layout)| match &layout.entry {
+            layout::Entry::Regular(_, target) if patterns.iter().any(|p| p.match_path(target).is_some()) => {
+                Some(target.as_str())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use super::*;
+
+    /// Synthesize layouts for a single package with the given regular files
+    pub fn layouts(files: &[&str]) -> Vec<(Id, Layout)> {
+        files
+            .iter()
+            .map(|file| {
+                (
+                    Id::from("test".to_owned()),
+                    Layout {
+                        uid: 0,
+                        gid: 0,
+                        mode: 0o100644,
+                        tag: 0,
+                        entry: layout::Entry::Regular(0, (*file).to_owned()),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detect_backend() {
+        let systemd = layouts(&["lib/systemd/boot/efi/systemd-bootx64.efi", "bin/bootctl"]);
+        let grub = layouts(&[
+            "lib/grub/x86_64-efi/normal.mod",
+            "lib/shim/shimx64.efi",
+            "bin/grub-mkconfig",
+        ]);
+        let both = layouts(&[
+            "lib/grub/x86_64-efi/normal.mod",
+            "lib/systemd/boot/efi/systemd-bootx64.efi",
+        ]);
+        let none = layouts(&["lib/kernel/6.12.9-1/vmlinuz"]);
+
+        assert_eq!(Backend::detect(&systemd).unwrap(), Some(Backend::SystemdBoot));
+        assert_eq!(Backend::detect(&grub).unwrap(), Some(Backend::Grub));
+        assert_eq!(Backend::detect(&both).unwrap(), Some(Backend::SystemdBoot));
+        assert_eq!(Backend::detect(&none).unwrap(), None);
+    }
+
+    #[test]
+    fn backend_assets() {
+        let layouts = layouts(&[
+            "lib/systemd/boot/efi/systemd-bootx64.efi",
+            "lib/systemd/boot/efi/addonx64.efi.stub",
+            "lib/grub/x86_64-efi/normal.mod",
+            "lib/shim/shimx64.efi",
+        ]);
+
+        let systemd = [Pattern::from_str(Backend::SystemdBoot.asset_patterns()[0]).unwrap()];
+        let grub = [Pattern::from_str(Backend::Grub.asset_patterns()[0]).unwrap()];
+
+        assert_eq!(
+            assets(&layouts, &systemd),
+            vec!["lib/systemd/boot/efi/systemd-bootx64.efi"]
+        );
+        assert_eq!(assets(&layouts, &grub), vec!["lib/grub/x86_64-efi/normal.mod"]);
+
+        let defaults = Backend::SystemdBoot
+            .asset_patterns()
+            .iter()
+            .map(|pattern| Pattern::from_str(pattern).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            assets(&layouts, &defaults),
+            vec!["lib/systemd/boot/efi/systemd-bootx64.efi", "lib/shim/shimx64.efi"]
+        );
+    }
+}
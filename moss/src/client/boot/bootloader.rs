@@ -0,0 +1,321 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Installation and updates of the systemd-boot binary on the ESP
+//!
+//! The loader is installed to `EFI/systemd` as well as the removable media path
+//! `EFI/BOOT`, which some firmware insists on. Installed loaders are only replaced
+//! when the packaged build is newer, as reported by the `LoaderInfo` marker
+//! embedded in the binary, falling back to a content hash comparison.
+//!
+//! When the state ships shim, it takes the removable media path instead, with
+//! systemd-boot installed next to it as the second stage shim chain loads.
+
+use std::{
+    cmp::Ordering,
+    io,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_128;
+
+use super::{copy, plan::Action};
+
+/// Marker preceding the version string embedded in systemd-boot binaries
+const LOADER_INFO: &[u8] = b"#### LoaderInfo: ";
+
+/// Terminator of the embedded version string
+const LOADER_INFO_END: &[u8] = b" ####";
+
+/// File name prefix of the packaged systemd-boot binaries, e.g. `systemd-bootx64.efi`
+const PREFIX: &str = "systemd-boot";
+
+/// File name prefix of the packaged shim binaries, e.g. `shimx64.efi`
+const SHIM_PREFIX: &str = "shim";
+
+/// File name prefix of the MOK manager shipped with shim, e.g. `mmx64.efi`
+const MOK_MANAGER_PREFIX: &str = "mm";
+
+/// File name prefix of the second stage loaded by shim, e.g. `grubx64.efi`
+const SHIM_SECOND_STAGE_PREFIX: &str = "grub";
+
+/// Removable media path of the ESP
+const REMOVABLE_DIR: &str = "EFI/BOOT";
+
+/// How the packaged loaders are arranged on the ESP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Arrangement {
+    /// systemd-boot is booted directly from the removable media path
+    #[default]
+    Direct,
+    /// shim is booted from the removable media path, chain loading systemd-boot
+    Shim,
+}
+
+impl Arrangement {
+    /// The arrangement of the packaged loader `sources`, using shim whenever the state ships it
+    pub fn detect(sources: &[PathBuf]) -> Self {
+        if sources.iter().any(|source| arch(source, SHIM_PREFIX).is_some()) {
+            Self::Shim
+        } else {
+            Self::Direct
+        }
+    }
+}
+
+/// Policy for replacing an already installed loader
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Policy {
+    /// Replace the installed loader when the packaged one is newer
+    #[default]
+    Newer,
+    /// Replace the installed loader whenever it differs from the packaged one
+    Always,
+    /// Never replace an installed loader, only install missing ones
+    Never,
+}
+
+/// A planned installation of a packaged loader binary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Update {
+    pub action: Action,
+    pub source: PathBuf,
+    /// ESP-relative destination
+    pub destination: PathBuf,
+    /// Version of the installed loader, if known
+    pub installed: Option<String>,
+    /// Version of the packaged loader, if known
+    pub packaged: Option<String>,
+    /// Bytes written by the update
+    pub size: u64,
+}
+
+/// The version embedded in a systemd-boot binary, e.g. `systemd-boot 257.1`
+pub fn version(contents: &[u8]) -> Option<String> {
+    let start = find(contents, LOADER_INFO)? + LOADER_INFO.len();
+    let len = find(&contents[start..], LOADER_INFO_END)?;
+
+    std::str::from_utf8(&contents[start..start + len])
+        .ok()
+        .map(|version| version.trim().to_owned())
+}
+
+/// Compare two embedded loader versions by their numeric components
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let components = |version: &str| {
+        version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|part| part.parse::<u64>().ok())
+            .collect::<Vec<_>>()
+    };
+
+    components(a).cmp(&components(b))
+}
+
+/// Returns true if `source` is a packaged loader binary installed by moss, rather than blsforme
+pub fn is_loader(source: &Path) -> bool {
+    !destinations(source, Arrangement::Shim).is_empty()
+}
+
+/// ESP-relative destinations of the packaged loader `source` in the given `arrangement`,
+/// or none if it isn't a loader
+pub fn destinations(source: &Path, arrangement: Arrangement) -> Vec<PathBuf> {
+    let removable = Path::new(REMOVABLE_DIR);
+
+    if let Some(arch) = arch(source, PREFIX) {
+        let fallback = match arrangement {
+            Arrangement::Direct => format!("BOOT{}.EFI", arch.to_uppercase()),
+            Arrangement::Shim => format!("{SHIM_SECOND_STAGE_PREFIX}{arch}.efi"),
+        };
+
+        return vec![
+            Path::new("EFI/systemd").join(format!("{PREFIX}{arch}.efi")),
+            removable.join(fallback),
+        ];
+    }
+
+    if arrangement != Arrangement::Shim {
+        return vec![];
+    }
+
+    if let Some(arch) = arch(source, SHIM_PREFIX) {
+        vec![removable.join(format!("BOOT{}.EFI", arch.to_uppercase()))]
+    } else if let Some(arch) = arch(source, MOK_MANAGER_PREFIX) {
+        vec![removable.join(format!("{MOK_MANAGER_PREFIX}{arch}.efi"))]
+    } else {
+        vec![]
+    }
+}
+
+/// Plan the installation of all packaged loader `sources` into the `esp`
+pub fn plan(esp: &Path, sources: &[PathBuf], policy: Policy) -> io::Result<Vec<Update>> {
+    let arrangement = Arrangement::detect(sources);
+    let mut updates = vec![];
+
+    for source in sources {
+        let destinations = destinations(source, arrangement);
+        if destinations.is_empty() {
+            continue;
+        }
+
+        let packaged = fs::read(source)?;
+
+        for destination in destinations {
+            let installed = match fs::read(esp.join(&destination)) {
+                Ok(installed) => Some(installed),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+
+            let action = action(&packaged, installed.as_deref(), policy);
+
+            updates.push(Update {
+                action,
+                source: source.clone(),
+                installed: installed.as_deref().and_then(version),
+                packaged: version(&packaged),
+                size: if action == Action::Unchanged {
+                    0
+                } else {
+                    packaged.len() as u64
+                },
+                destination,
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Apply all pending `updates` to the `esp`
+pub fn apply(esp: &Path, updates: &[Update]) -> Result<copy::Stats, copy::Failure> {
+    let jobs = updates
+        .iter()
+        .filter(|update| matches!(update.action, Action::Create | Action::Update))
+        .map(|update| copy::Job {
+            source: update.source.clone(),
+            destination: esp.join(&update.destination),
+        })
+        .collect::<Vec<_>>();
+
+    copy::install(&jobs)
+}
+
+/// The action taken for the `packaged` loader given the `installed` one
+fn action(packaged: &[u8], installed: Option<&[u8]>, policy: Policy) -> Action {
+    let Some(installed) = installed else {
+        return Action::Create;
+    };
+
+    if xxh3_128(packaged) == xxh3_128(installed) {
+        return Action::Unchanged;
+    }
+
+    let replace = match policy {
+        Policy::Always => true,
+        Policy::Never => false,
+        Policy::Newer => match (version(packaged), version(installed)) {
+            (Some(packaged), Some(installed)) => compare(&packaged, &installed) == Ordering::Greater,
+            // Unknown builds differing in content are assumed to be outdated
+            _ => true,
+        },
+    };
+
+    if replace {
+        Action::Update
+    } else {
+        Action::Unchanged
+    }
+}
+
+/// The EFI architecture suffix of the loader `source` named `<prefix><arch>.efi`, e.g. `x64`
+fn arch<'a>(source: &'a Path, prefix: &str) -> Option<&'a str> {
+    source
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix(prefix)?.strip_suffix(".efi"))
+        .filter(|arch| !arch.is_empty() && arch.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Position of `needle` within `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loader(version: &str) -> Vec<u8> {
+        format!("MZ\0\0.sdmagic\0#### LoaderInfo: systemd-boot {version} ####\0").into_bytes()
+    }
+
+    #[test]
+    fn loader_version() {
+        assert_eq!(version(&loader("257.1")).as_deref(), Some("systemd-boot 257.1"));
+        assert_eq!(version(b"MZ\0\0"), None);
+        assert_eq!(compare("systemd-boot 257.1", "systemd-boot 256.10"), Ordering::Greater);
+        assert_eq!(compare("systemd-boot 255", "systemd-boot 255"), Ordering::Equal);
+    }
+
+    #[test]
+    fn update_actions() {
+        let old = loader("255.4");
+        let new = loader("257.1");
+
+        assert_eq!(action(&new, None, Policy::Never), Action::Create);
+        assert_eq!(action(&new, Some(&new), Policy::Always), Action::Unchanged);
+        assert_eq!(action(&new, Some(&old), Policy::Newer), Action::Update);
+        assert_eq!(action(&old, Some(&new), Policy::Newer), Action::Unchanged);
+        assert_eq!(action(&old, Some(&new), Policy::Always), Action::Update);
+        assert_eq!(action(&new, Some(&old), Policy::Never), Action::Unchanged);
+        assert_eq!(action(b"MZ\x01", Some(b"MZ\x02"), Policy::Newer), Action::Update);
+    }
+
+    #[test]
+    fn loader_destinations() {
+        assert_eq!(
+            destinations(
+                Path::new("/usr/lib/systemd/boot/efi/systemd-bootx64.efi"),
+                Arrangement::Direct
+            ),
+            vec![
+                PathBuf::from("EFI/systemd/systemd-bootx64.efi"),
+                PathBuf::from("EFI/BOOT/BOOTX64.EFI")
+            ]
+        );
+        assert!(destinations(Path::new("/usr/lib/systemd/boot/efi/addonx64.efi"), Arrangement::Direct).is_empty());
+        assert!(destinations(Path::new("/usr/lib/shim/shimx64.efi"), Arrangement::Direct).is_empty());
+    }
+
+    #[test]
+    fn shim_destinations() {
+        let sources = [
+            PathBuf::from("/usr/lib/systemd/boot/efi/systemd-bootx64.efi"),
+            PathBuf::from("/usr/lib/shim/shimx64.efi"),
+            PathBuf::from("/usr/lib/shim/mmx64.efi"),
+        ];
+        assert_eq!(Arrangement::detect(&sources), Arrangement::Shim);
+        assert_eq!(Arrangement::detect(&sources[..1]), Arrangement::Direct);
+
+        let destinations = sources
+            .iter()
+            .flat_map(|source| destinations(source, Arrangement::Shim))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            destinations,
+            vec![
+                PathBuf::from("EFI/systemd/systemd-bootx64.efi"),
+                PathBuf::from("EFI/BOOT/grubx64.efi"),
+                PathBuf::from("EFI/BOOT/BOOTX64.EFI"),
+                PathBuf::from("EFI/BOOT/mmx64.efi"),
+            ]
+        );
+        assert!(sources.iter().all(|source| is_loader(source)));
+    }
+}
@@ -0,0 +1,391 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! User configured kernel command line fragments
+//!
+//! Fragments are read from `/etc/moss/cmdline.d/*.cmdline` in lexical order and
+//! appended to every generated entry. Parameters already provided by the system
+//! snippets (`/usr/lib/kernel/cmdline.d`, `/etc/kernel/cmdline.d`) or an earlier
+//! fragment are dropped.
+//!
+//! The resolved fragments are persisted per state, so rolling back to an older
+//! state boots it with the command line it was created with.
+//!
+//! The final command line of an entry is [`assemble`]d from the system snippets in
+//! lexical order, then the user fragments and finally the moss managed parameters.
+//! Parameters are deduplicated by key, with the last writer winning. Keys the kernel
+//! accepts more than once, such as `console`, are only deduplicated by value.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use thiserror::Error;
+
+use super::entry::STATE_PARAMETER;
+use crate::state;
+
+/// User fragment directory, relative to the installation root
+pub const DIR: &str = "etc/moss/cmdline.d";
+
+/// System snippet directories, relative to the installation root
+const SYSTEM_DIRS: &[&str] = &["usr/lib/kernel/cmdline.d", "etc/kernel/cmdline.d"];
+
+/// Fragment file extension
+const EXTENSION: &str = "cmdline";
+
+/// Name of the fragment holding a persisted state command line
+const STORED: &str = "state";
+
+/// Name of the moss managed fragment identifying the state of an entry
+pub const STATE_FRAGMENT: &str = "moss-state-id";
+
+/// Parameter keys that are legitimately repeated on the command line
+const MULTI_VALUED: &[&str] = &[
+    "console",
+    "ip",
+    "rd.luks.name",
+    "rd.luks.uuid",
+    "rd.driver.blacklist",
+    "modprobe.blacklist",
+];
+
+/// A kernel command line fragment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    /// File stem of the fragment
+    pub name: String,
+    /// Deduplicated parameters, space separated
+    pub snippet: String,
+}
+
+/// A parameter dropped in favour of a later one with the same key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    /// The dropped parameter and the fragment providing it
+    pub dropped: (String, String),
+    /// The winning parameter and the fragment providing it
+    pub winner: (String, String),
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} from {} overrides {} from {}",
+            self.winner.0, self.winner.1, self.dropped.0, self.dropped.1
+        )
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Malformed {
+    #[error("unbalanced quotes")]
+    UnbalancedQuotes,
+    #[error("control character {0:?}")]
+    ControlCharacter(char),
+}
+
+/// Load all user fragments in `root`, deduplicated against the system snippets
+///
+/// The moss managed `moss.fstx` parameter can never be overridden by a fragment.
+/// Unreadable or malformed fragments are skipped with a warning.
+pub fn load(root: &Path) -> Vec<Fragment> {
+    let mut seen = SYSTEM_DIRS
+        .iter()
+        .flat_map(|dir| files(&root.join(dir)))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|contents| parse(&contents).ok())
+        .flatten()
+        .collect::<BTreeSet<_>>();
+
+    let mut fragments = vec![];
+
+    for path in files(&root.join(DIR)) {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let params = match fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| parse(&contents).map_err(|e| e.to_string()))
+        {
+            Ok(params) => params,
+            Err(e) => {
+                log::warn!("Ignoring malformed cmdline fragment {}: {e}", path.display());
+                continue;
+            }
+        };
+
+        let params = params
+            .into_iter()
+            .filter(|param| !param.starts_with(STATE_PARAMETER) && seen.insert(param.clone()))
+            .collect::<Vec<_>>();
+
+        if !params.is_empty() {
+            fragments.push(Fragment {
+                name,
+                snippet: params.join(" "),
+            });
+        }
+    }
+
+    fragments
+}
+
+/// Fragments of a command line previously resolved with [`join`] and persisted for a state
+pub fn stored(cmdline: &str) -> Vec<Fragment> {
+    if cmdline.trim().is_empty() {
+        return vec![];
+    }
+
+    vec![Fragment {
+        name: STORED.to_owned(),
+        snippet: cmdline.trim().to_owned(),
+    }]
+}
+
+/// All system snippets in `sysroot`, ordered by file name across all snippet directories
+///
+/// A snippet in `/etc` replaces one of the same name in `/usr`.
+pub fn system(sysroot: &Path) -> Vec<Fragment> {
+    let mut snippets = BTreeMap::new();
+
+    for path in SYSTEM_DIRS.iter().flat_map(|dir| files(&sysroot.join(dir))) {
+        let Some(name) = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()) else {
+            continue;
+        };
+
+        match fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| parse(&contents).map_err(|e| e.to_string()))
+        {
+            Ok(params) => {
+                snippets.insert(name, params.join(" "));
+            }
+            Err(e) => log::warn!("Ignoring malformed cmdline snippet {}: {e}", path.display()),
+        }
+    }
+
+    snippets
+        .into_iter()
+        .map(|(name, snippet)| Fragment { name, snippet })
+        .collect()
+}
+
+/// The moss managed fragment identifying `state`
+pub fn state_fragment(state: state::Id) -> Fragment {
+    Fragment {
+        name: STATE_FRAGMENT.to_owned(),
+        snippet: format!("{STATE_PARAMETER}={state}"),
+    }
+}
+
+/// Assemble the final command line from `fragments`, in order
+///
+/// Parameters are deduplicated by key (the part ahead of any `=`) with the last
+/// writer winning, except for [`MULTI_VALUED`] keys where only the exact same
+/// parameter is deduplicated. Exact duplicates are dropped silently, whereas every
+/// differing parameter dropped is returned as an [`Override`]. Fragments left empty
+/// are removed.
+pub fn assemble(fragments: impl IntoIterator<Item = Fragment>) -> (Vec<Fragment>, Vec<Override>) {
+    let fragments = fragments.into_iter().collect::<Vec<_>>();
+    let params = fragments
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, fragment)| {
+            parse(&fragment.snippet)
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |param| (idx, param))
+        })
+        .collect::<Vec<_>>();
+
+    let key = |param: &str| {
+        let key = param.split_once('=').map_or(param, |(key, _)| key);
+        if MULTI_VALUED.contains(&key) { param } else { key }.to_owned()
+    };
+
+    // Index of the winning occurrence of each key
+    let winners = params
+        .iter()
+        .enumerate()
+        .map(|(pos, (_, param))| (key(param), pos))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut overrides = vec![];
+    let mut kept = vec![vec![]; fragments.len()];
+
+    for (pos, (idx, param)) in params.iter().enumerate() {
+        let winner = winners[&key(param)];
+
+        if winner == pos {
+            kept[*idx].push(param.as_str());
+        } else if params[winner].1 != *param {
+            overrides.push(Override {
+                dropped: (param.clone(), fragments[*idx].name.clone()),
+                winner: (params[winner].1.clone(), fragments[params[winner].0].name.clone()),
+            });
+        }
+    }
+
+    let assembled = fragments
+        .into_iter()
+        .zip(kept)
+        .filter(|(_, params)| !params.is_empty())
+        .map(|(fragment, params)| Fragment {
+            name: fragment.name,
+            snippet: params.join(" "),
+        })
+        .collect();
+
+    (assembled, overrides)
+}
+
+/// Join all `fragments` into a single command line
+pub fn join(fragments: &[Fragment]) -> String {
+    fragments
+        .iter()
+        .map(|fragment| fragment.snippet.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parse fragment `contents` into individual parameters
+///
+/// `#` starts a comment running to the end of the line. Double quoted values
+/// may contain whitespace, as in `param="a b"`.
+pub fn parse(contents: &str) -> Result<Vec<String>, Malformed> {
+    let mut params = vec![];
+
+    for line in contents.lines() {
+        let line = line.split_once('#').map_or(line, |(line, _)| line);
+
+        if let Some(c) = line.chars().find(|c| c.is_control() && *c != '\t') {
+            return Err(Malformed::ControlCharacter(c));
+        }
+
+        let mut param = String::new();
+        let mut quoted = false;
+
+        for c in line.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    param.push(c);
+                }
+                c if c.is_whitespace() && !quoted => {
+                    if !param.is_empty() {
+                        params.push(std::mem::take(&mut param));
+                    }
+                }
+                c => param.push(c),
+            }
+        }
+
+        if quoted {
+            return Err(Malformed::UnbalancedQuotes);
+        }
+        if !param.is_empty() {
+            params.push(param);
+        }
+    }
+
+    Ok(params)
+}
+
+/// All fragment files in `dir`, sorted by file name
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION) && path.is_file())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    paths
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn parse_fragment() {
+        assert_eq!(
+            parse("quiet splash # boot quietly\n\nacpi_osi=\"Windows 2020\"\n").unwrap(),
+            vec!["quiet", "splash", "acpi_osi=\"Windows 2020\""]
+        );
+        assert_eq!(parse("acpi_osi=\"Windows"), Err(Malformed::UnbalancedQuotes));
+        assert_eq!(parse("quiet\u{7}"), Err(Malformed::ControlCharacter('\u{7}')));
+    }
+
+    #[test]
+    fn load_fragments() {
+        let root = Scratch::new(&[
+            ("usr/lib/kernel/cmdline.d/00-base.cmdline", "rw quiet"),
+            ("etc/moss/cmdline.d/20-nvidia.cmdline", "nvidia-drm.modeset=1 splash"),
+            ("etc/moss/cmdline.d/10-quiet.cmdline", "quiet splash"),
+            ("etc/moss/cmdline.d/30-broken.cmdline", "foo=\"bar"),
+            ("etc/moss/cmdline.d/40-ignored.conf", "ignored"),
+            ("etc/moss/cmdline.d/50-fstx.cmdline", "moss.fstx=1"),
+        ]);
+
+        assert_eq!(
+            load(&root),
+            vec![
+                Fragment {
+                    name: "10-quiet".to_owned(),
+                    snippet: "splash".to_owned()
+                },
+                Fragment {
+                    name: "20-nvidia".to_owned(),
+                    snippet: "nvidia-drm.modeset=1".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_conflicting_fragments() {
+        let fragment = |name: &str, snippet: &str| Fragment {
+            name: name.to_owned(),
+            snippet: snippet.to_owned(),
+        };
+        let fragments = vec![
+            fragment("00-base", "root=UUID=abcd rw quiet console=tty0"),
+            fragment("10-debug", "loglevel=7 quiet moss.fstx=3 console=tty0"),
+            fragment("20-user", "loglevel=3 splash console=ttyS0,115200"),
+            state_fragment(state::Id::from(12)),
+        ];
+
+        let (assembled, overrides) = assemble(fragments.clone());
+        let cmdline = join(&assembled);
+
+        assert_eq!(
+            cmdline,
+            "root=UUID=abcd rw quiet console=tty0 loglevel=3 splash console=ttyS0,115200 moss.fstx=12"
+        );
+        assert_eq!(cmdline.matches("moss.fstx=").count(), 1);
+        assert_eq!(
+            overrides.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "loglevel=3 from 20-user overrides loglevel=7 from 10-debug",
+                "moss.fstx=12 from moss-state-id overrides moss.fstx=3 from 10-debug"
+            ]
+        );
+        assert_eq!(assembled.last().map(|f| f.name.as_str()), Some(STATE_FRAGMENT));
+
+        // Deterministic across runs
+        assert_eq!(join(&assemble(fragments).0), cmdline);
+    }
+}
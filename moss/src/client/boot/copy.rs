@@ -0,0 +1,195 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Checksummed installation of assets into the boot partitions
+//!
+//! Destinations matching their source by size and SHA-256 are skipped, so slow
+//! (SD card) ESPs aren't rewritten on every sync. All other assets are copied in
+//! parallel to a temporary file, synced, renamed into place and read back to
+//! verify what actually landed on the VFAT partition.
+
+use std::{
+    fs::File,
+    io,
+    ops::Add,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// An asset to install into a boot partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+}
+
+/// Counts of the assets handled by [`install`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    /// Assets written to their destination
+    pub copied: usize,
+    /// Assets already up to date
+    pub skipped: usize,
+    /// Written assets verified after the copy
+    pub verified: usize,
+}
+
+impl Add for Stats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            copied: self.copied + other.copied,
+            skipped: self.skipped + other.skipped,
+            verified: self.verified + other.verified,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Failure {
+    #[error("io: {0}")]
+    IO(#[from] io::Error),
+    #[error("checksum mismatch after copying to {}", .0.display())]
+    Checksum(PathBuf),
+}
+
+/// Size and SHA-256 digest of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Checksum {
+    size: u64,
+    digest: Vec<u8>,
+}
+
+/// Install all `jobs` in parallel, skipping destinations identical to their source
+///
+/// A destination that differs from its source, including one corrupted on disk,
+/// is rewritten.
+pub fn install(jobs: &[Job]) -> Result<Stats, Failure> {
+    jobs.par_iter()
+        .map(install_one)
+        .try_reduce(Stats::default, |a, b| Ok(a + b))
+}
+
+/// Bytes [`install`] would write for `source`, i.e. none if the `destination` is
+/// already identical to it
+pub fn pending(source: &Path, destination: Option<&Path>) -> io::Result<u64> {
+    let source = checksum(source)?;
+
+    match destination.map(checksum) {
+        Some(Ok(destination)) if destination == source => Ok(0),
+        Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(source.size),
+    }
+}
+
+fn install_one(job: &Job) -> Result<Stats, Failure> {
+    let source = checksum(&job.source)?;
+
+    match checksum(&job.destination) {
+        Ok(destination) if destination == source => {
+            return Ok(Stats {
+                skipped: 1,
+                ..Default::default()
+            })
+        }
+        Ok(_) => log::info!("Rewriting outdated or corrupted {}", job.destination.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let parent = job.destination.parent().unwrap_or(Path::new("/"));
+    fs::create_dir_all(parent)?;
+
+    let staging = staging(&job.destination);
+    let result = write(&job.source, &staging).and_then(|_| {
+        fs::rename(&staging, &job.destination)?;
+        // Persist the rename itself
+        File::open(parent)?.sync_all()
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&staging);
+        return Err(e.into());
+    }
+
+    if checksum(&job.destination)? != source {
+        return Err(Failure::Checksum(job.destination.clone()));
+    }
+
+    Ok(Stats {
+        copied: 1,
+        verified: 1,
+        ..Default::default()
+    })
+}
+
+/// Copy `source` to `destination`, syncing it to disk
+fn write(source: &Path, destination: &Path) -> io::Result<()> {
+    let mut input = fs::File::open(source)?;
+    let mut output = fs::File::create(destination)?;
+
+    io::copy(&mut input, &mut output)?;
+    output.sync_all()
+}
+
+fn checksum(path: &Path) -> io::Result<Checksum> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+
+    Ok(Checksum {
+        size,
+        digest: hasher.finalize().to_vec(),
+    })
+}
+
+/// Temporary path to copy `path` into, within the same directory
+fn staging(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{name}.moss-new"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn skip_unchanged_and_rewrite_corrupted() {
+        let dir = Scratch::new(&[("vmlinuz", "kernel image")]);
+
+        let source = dir.join("vmlinuz");
+        let job = Job {
+            source: source.clone(),
+            destination: dir.join("esp/EFI/os/vmlinuz"),
+        };
+
+        let copied = install(std::slice::from_ref(&job)).unwrap();
+        assert_eq!(
+            copied,
+            Stats {
+                copied: 1,
+                skipped: 0,
+                verified: 1
+            }
+        );
+
+        let skipped = install(std::slice::from_ref(&job)).unwrap();
+        assert_eq!(skipped.skipped, 1);
+
+        // Same size, different contents
+        fs::write(&job.destination, b"kernel imagf").unwrap();
+        assert_eq!(install(std::slice::from_ref(&job)).unwrap().copied, 1);
+        assert_eq!(fs::read(&job.destination).unwrap(), b"kernel image");
+    }
+}
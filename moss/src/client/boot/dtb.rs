@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Device-tree blobs shipped alongside a kernel, as needed by most AArch64 boards
+//!
+//! Kernels ship their dtbs beneath `lib/kernel/<version>/dtbs/`. When a board dtb is
+//! configured, only that blob is installed and referenced via the `devicetree` key
+//! of each entry. Otherwise all blobs are installed into a per-kernel directory and
+//! the device tree is left to the firmware. Kernels without dtbs are unaffected.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use stone::payload::layout::{self, Layout};
+
+use super::{copy, entry::LoaderEntry};
+use crate::package::Id;
+
+/// Installed dtb directory, relative to the boot partition, holding a directory per kernel version
+pub const DIR: &str = "EFI/moss/dtb";
+
+/// A device-tree blob of a kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dtb {
+    /// Blob path, relative to the state's sysroot
+    pub path: PathBuf,
+    /// Blob path relative to the kernel's `dtbs` directory, e.g. `broadcom/bcm2711-rpi-4-b.dtb`
+    pub name: PathBuf,
+}
+
+impl Dtb {
+    /// Boot partition relative path of the installed blob for kernel `version`, with a leading `/`
+    pub fn asset(&self, version: &str) -> String {
+        format!("/{DIR}/{version}/{}", self.name.display())
+    }
+}
+
+/// Find all dtbs of kernel `version` in `layouts`, sorted by name
+pub fn from_layouts(layouts: &[(Id, Layout)], version: &str) -> Vec<Dtb> {
+    let dir = Path::new("lib/kernel").join(version).join("dtbs");
+
+    let mut dtbs = layouts
+        .iter()
+        .filter_map(|(_, layout)| match &layout.entry {
+            layout::Entry::Regular(_, target) if target.ends_with(".dtb") => {
+                let name = Path::new(target).strip_prefix(&dir).ok()?;
+
+                Some(Dtb {
+                    path: PathBuf::from("usr").join(target),
+                    name: name.to_path_buf(),
+                })
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    dtbs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    dtbs
+}
+
+/// The configured board dtb among `dtbs`, if any
+pub fn select<'a>(dtbs: &'a [Dtb], board: Option<&Path>) -> Option<&'a Dtb> {
+    let board = board?;
+    dtbs.iter().find(|dtb| dtb.name == board)
+}
+
+/// The installation of kernel `version`'s `dtbs` from `sysroot` into the boot partition `tree`
+///
+/// Only the `board` dtb is installed when configured, otherwise all of them.
+pub fn jobs(tree: &Path, sysroot: &Path, version: &str, dtbs: &[Dtb], board: Option<&Path>) -> Vec<copy::Job> {
+    let installed = match (board, select(dtbs, board)) {
+        (Some(_), Some(dtb)) => std::slice::from_ref(dtb),
+        // A missing board dtb must not fill the partition with all others
+        (Some(_), None) => &[],
+        (None, _) => dtbs,
+    };
+
+    installed
+        .iter()
+        .map(|dtb| copy::Job {
+            source: sysroot.join(&dtb.path),
+            destination: tree.join(dtb.asset(version).trim_start_matches('/')),
+        })
+        .collect()
+}
+
+/// Set the `devicetree` of the loader entry `contents`, replacing an existing one
+///
+/// Without an existing key the line is added after the last initrd, otherwise
+/// directly after the kernel.
+pub fn inject(contents: &str, devicetree: &str) -> String {
+    let line = format!("devicetree {devicetree}");
+    let mut lines = contents.lines().map(str::to_owned).collect::<Vec<_>>();
+
+    let key = |line: &str| line.split_whitespace().next().map(str::to_owned);
+
+    if let Some(existing) = lines
+        .iter_mut()
+        .find(|existing| key(existing).as_deref() == Some("devicetree"))
+    {
+        *existing = line;
+    } else {
+        let position = lines
+            .iter()
+            .rposition(|line| matches!(key(line).as_deref(), Some("initrd" | "linux")))
+            .map(|idx| idx + 1)
+            .unwrap_or(lines.len());
+        lines.insert(position, line);
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+/// Remove the installed dtb directories beneath `tree` of all kernel versions no
+/// longer booted by any of the `retained` entries
+pub fn remove_unreferenced(tree: &Path, retained: &[LoaderEntry]) -> io::Result<Vec<PathBuf>> {
+    let dir = tree.join(DIR);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut removed = vec![];
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let Some(version) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        let referenced = retained.iter().any(|entry| {
+            entry.version.as_deref() == Some(version) || entry.assets().any(|asset| asset.contains(version))
+        });
+
+        if !referenced && path.is_dir() {
+            fs::remove_dir_all(&path)?;
+            removed.push(path);
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dtbs_from_layouts() {
+        let layouts = crate::client::boot::backend::test::layouts(&[
+            "lib/kernel/6.12.9-1/vmlinuz",
+            "lib/kernel/6.12.9-1/dtbs/rockchip/rk3588-rock-5b.dtb",
+            "lib/kernel/6.12.9-1/dtbs/broadcom/bcm2711-rpi-4-b.dtb",
+            "lib/kernel/6.13.0-1/dtbs/broadcom/bcm2711-rpi-4-b.dtb",
+        ]);
+
+        let dtbs = from_layouts(&layouts, "6.12.9-1");
+        assert_eq!(
+            dtbs.iter().map(|dtb| dtb.name.clone()).collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("broadcom/bcm2711-rpi-4-b.dtb"),
+                PathBuf::from("rockchip/rk3588-rock-5b.dtb")
+            ]
+        );
+        assert_eq!(
+            dtbs[0].asset("6.12.9-1"),
+            "/EFI/moss/dtb/6.12.9-1/broadcom/bcm2711-rpi-4-b.dtb"
+        );
+
+        let board = Path::new("broadcom/bcm2711-rpi-4-b.dtb");
+        assert_eq!(
+            jobs(Path::new("/efi"), Path::new("/"), "6.12.9-1", &dtbs, Some(board)).len(),
+            1
+        );
+        assert_eq!(
+            jobs(Path::new("/efi"), Path::new("/"), "6.12.9-1", &dtbs, None).len(),
+            2
+        );
+        assert!(from_layouts(
+            &crate::client::boot::backend::test::layouts(&["lib/kernel/6.12.9-1/vmlinuz"]),
+            "6.12.9-1"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn inject_devicetree() {
+        let entry = "title AerynOS\nlinux /EFI/os/vmlinuz\ninitrd /EFI/os/initrd\noptions moss.fstx=1\n";
+        let dtb = "/EFI/moss/dtb/6.12.9-1/broadcom/bcm2711-rpi-4-b.dtb";
+
+        let injected = inject(entry, dtb);
+        assert_eq!(
+            injected,
+            "title AerynOS\nlinux /EFI/os/vmlinuz\ninitrd /EFI/os/initrd\n\
+             devicetree /EFI/moss/dtb/6.12.9-1/broadcom/bcm2711-rpi-4-b.dtb\noptions moss.fstx=1\n"
+        );
+        assert_eq!(inject(&injected, dtb), injected);
+    }
+}
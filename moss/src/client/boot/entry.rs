@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Parsing of Boot Loader Specification (type #1) entries found on the ESP
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::state;
+
+/// Kernel command line parameter identifying the moss state for an entry
+pub const STATE_PARAMETER: &str = "moss.fstx";
+
+/// A loader entry parsed from `loader/entries/*.conf`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoaderEntry {
+    /// Location of the entry file
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub version: Option<String>,
+    pub sort_key: Option<String>,
+    /// ESP-relative path of the kernel image
+    pub linux: Option<String>,
+    /// ESP-relative paths of the initrds, in load order
+    pub initrd: Vec<String>,
+    /// ESP-relative path of the device-tree blob
+    pub devicetree: Option<String>,
+    /// All `options` lines, in order
+    pub options: Vec<String>,
+    /// EFI architecture the entry is restricted to, e.g. `x64`
+    pub architecture: Option<String>,
+}
+
+impl LoaderEntry {
+    /// Parse the contents of a loader entry file
+    pub fn parse(path: impl Into<PathBuf>, contents: &str) -> Self {
+        let mut entry = Self {
+            path: path.into(),
+            ..Default::default()
+        };
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim().to_owned();
+
+            match key {
+                "title" => entry.title = Some(value),
+                "version" => entry.version = Some(value),
+                "sort-key" => entry.sort_key = Some(value),
+                "linux" => entry.linux = Some(value),
+                "initrd" => entry.initrd.push(value),
+                "devicetree" => entry.devicetree = Some(value),
+                "options" => entry.options.push(value),
+                "architecture" => entry.architecture = Some(value),
+                _ => {}
+            }
+        }
+
+        entry
+    }
+
+    /// Read and parse the loader entry at `path`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        Ok(Self::parse(path, &contents))
+    }
+
+    /// The full kernel command line for this entry
+    pub fn cmdline(&self) -> String {
+        self.options.join(" ")
+    }
+
+    /// The moss state this entry boots into, if it was generated by moss
+    pub fn state_id(&self) -> Option<state::Id> {
+        self.options
+            .iter()
+            .flat_map(|options| options.split_whitespace())
+            .find_map(|param| param.strip_prefix(STATE_PARAMETER)?.strip_prefix('='))
+            .and_then(|id| id.parse::<i32>().ok())
+            .map(state::Id::from)
+    }
+
+    /// Root of the partition tree holding this entry, i.e. the parent of `loader/entries`
+    pub fn tree(&self) -> Option<&Path> {
+        self.path.ancestors().nth(3)
+    }
+
+    /// Returns true if this entry boots kernel `version` of `state`
+    ///
+    /// Without a `version` line, an asset must be within a directory named by the
+    /// version, or be named like `vmlinuz-<version>`.
+    pub fn matches(&self, state: state::Id, version: &str) -> bool {
+        let is_versioned = |component: &str| {
+            component == version || component.strip_suffix(version).is_some_and(|name| name.ends_with('-'))
+        };
+
+        self.state_id() == Some(state)
+            && (self.version.as_deref() == Some(version)
+                || self.assets().any(|asset| asset.split('/').any(is_versioned)))
+    }
+
+    /// All ESP-relative assets referenced by this entry, without a leading `/`
+    pub fn assets(&self) -> impl Iterator<Item = &str> {
+        self.linux
+            .iter()
+            .chain(self.initrd.iter())
+            .chain(self.devicetree.iter())
+            .map(|asset| asset.trim_start_matches('/'))
+    }
+}
+
+/// Load all loader entries stored beneath the `esp` root, sorted by path
+pub fn load_all(esp: &Path) -> io::Result<Vec<LoaderEntry>> {
+    let dir = esp.join("loader").join("entries");
+
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut paths = fs::read_dir(&dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "conf") && path.is_file());
+    paths.sort();
+
+    paths.into_iter().map(LoaderEntry::load).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_entry() {
+        let entry = LoaderEntry::parse(
+            "loader/entries/aerynos-6.12.9-1-12.conf",
+            r#"# Generated by moss
+title AerynOS (6.12.9-1)
+linux /EFI/aerynos/6.12.9-1/vmlinuz
+initrd /EFI/aerynos/6.12.9-1/10-default.initrd
+initrd /EFI/aerynos/6.12.9-1/99-extra.initrd
+options root=UUID=abcd rw quiet
+options moss.fstx=12
+"#,
+        );
+
+        assert_eq!(entry.title.as_deref(), Some("AerynOS (6.12.9-1)"));
+        assert_eq!(entry.initrd.len(), 2);
+        assert_eq!(entry.cmdline(), "root=UUID=abcd rw quiet moss.fstx=12");
+        assert_eq!(entry.state_id(), Some(state::Id::from(12)));
+        assert_eq!(
+            entry.assets().collect::<Vec<_>>(),
+            vec![
+                "EFI/aerynos/6.12.9-1/vmlinuz",
+                "EFI/aerynos/6.12.9-1/10-default.initrd",
+                "EFI/aerynos/6.12.9-1/99-extra.initrd"
+            ]
+        );
+    }
+
+    #[test]
+    fn match_versions() {
+        let entry = LoaderEntry::parse(
+            "loader/entries/os-6.12.9-1-4.conf",
+            "linux /EFI/os/6.12.9-1/vmlinuz\ninitrd /EFI/os/initrd-6.12.9-1\noptions moss.fstx=4\n",
+        );
+
+        assert!(entry.matches(state::Id::from(4), "6.12.9-1"));
+        assert!(!entry.matches(state::Id::from(4), "6.1"));
+        assert!(!entry.matches(state::Id::from(4), "12.9-1"));
+        assert!(!entry.matches(state::Id::from(5), "6.12.9-1"));
+    }
+
+    #[test]
+    fn foreign_entry() {
+        let entry = LoaderEntry::parse("windows.conf", "title Windows\nefi /EFI/Microsoft/Boot/bootmgfw.efi\n");
+
+        assert_eq!(entry.state_id(), None);
+        assert_eq!(entry.assets().count(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Discovery of the mounted EFI System Partition and XBOOTLDR trees
+
+use std::{
+    io,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use nix::{
+    mount::{mount, umount2, MntFlags, MsFlags},
+    sys::statvfs::statvfs,
+};
+use serde::Serialize;
+
+/// Well known ESP mountpoints, relative to the installation root, in order of preference
+const MOUNTPOINTS: &[&str] = &["efi", "boot/efi", "boot"];
+
+/// Mountpoint of an explicitly configured image ESP, relative to the installation root
+const IMAGE_MOUNTPOINT: &str = "efi";
+
+/// XBOOTLDR mountpoint relative to the installation root, only used when the ESP is at `/efi`
+const XBOOTLDR_MOUNTPOINT: &str = "boot";
+
+/// Locate the mounted ESP beneath `root`
+///
+/// Only trees already managed by a BLS bootloader (i.e. containing a `loader`
+/// directory) are considered, so an empty, unmounted mountpoint is never returned.
+pub fn locate(root: &Path) -> Option<PathBuf> {
+    MOUNTPOINTS
+        .iter()
+        .map(|mountpoint| root.join(mountpoint))
+        .find(|path| path.join("loader").is_dir())
+}
+
+/// Locate the mounted XBOOTLDR partition beneath `root`, given the located `esp`
+///
+/// Per the Boot Loader Specification XBOOTLDR lives at `/boot` with the ESP at
+/// `/efi`, and must be a distinct mount from both the root and the ESP.
+pub fn locate_xbootldr(root: &Path, esp: &Path) -> Option<PathBuf> {
+    if esp != root.join("efi") {
+        return None;
+    }
+
+    let path = root.join(XBOOTLDR_MOUNTPOINT);
+    let device = |path: &Path| path.metadata().ok().map(|meta| meta.dev());
+    let xbootldr = device(&path)?;
+
+    (Some(xbootldr) != device(root) && Some(xbootldr) != device(esp)).then_some(path)
+}
+
+/// The ESP of an image build, unmounted on drop if mounted by moss
+#[derive(Debug)]
+pub struct ImageEsp {
+    pub path: PathBuf,
+    mounted: bool,
+}
+
+impl Drop for ImageEsp {
+    fn drop(&mut self) {
+        if self.mounted {
+            if let Err(e) = umount2(&self.path, MntFlags::MNT_DETACH) {
+                log::warn!("Failed to unmount {}: {e}", self.path.display());
+            }
+        }
+    }
+}
+
+/// Prepare the ESP of the image `root`
+///
+/// An explicit `source` block device is mounted as vfat and a `source` directory is
+/// bind mounted, both at `<root>/efi`. Without a `source`, a pre-mounted `<root>/efi`
+/// or `<root>/boot/efi` is used. The `loader` tree is created so that a fresh ESP
+/// is picked up by [`locate`].
+pub fn prepare_image(root: &Path, source: Option<&Path>) -> io::Result<Option<ImageEsp>> {
+    let esp = match source {
+        Some(source) => {
+            let target = root.join(IMAGE_MOUNTPOINT);
+            let (fstype, flags) = if fs::metadata(source)?.file_type().is_block_device() {
+                (Some("vfat"), MsFlags::empty())
+            } else {
+                (None, MsFlags::MS_BIND)
+            };
+
+            fs::create_dir_all(&target)?;
+            mount(Some(source), &target, fstype, flags, None::<&str>).map_err(io::Error::from)?;
+
+            ImageEsp {
+                path: target,
+                mounted: true,
+            }
+        }
+        None => {
+            let device = |path: &Path| path.metadata().ok().map(|meta| meta.dev());
+            let Some(path) = MOUNTPOINTS[..2]
+                .iter()
+                .map(|mountpoint| root.join(mountpoint))
+                .find(|path| device(path).is_some() && device(path) != device(root))
+            else {
+                return Ok(None);
+            };
+
+            ImageEsp { path, mounted: false }
+        }
+    };
+
+    fs::create_dir_all(esp.path.join("loader").join("entries"))?;
+
+    Ok(Some(esp))
+}
+
+/// The mounted boot partition trees of an installation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Partitions {
+    pub esp: Option<PathBuf>,
+    pub xbootldr: Option<PathBuf>,
+}
+
+impl Partitions {
+    /// Locate all mounted boot partitions beneath `root`
+    ///
+    /// XBOOTLDR is only considered when `probed` confirms the partition type exists.
+    pub fn locate(root: &Path, probed: bool) -> Self {
+        let esp = locate(root);
+        let xbootldr = esp
+            .as_deref()
+            .filter(|_| probed)
+            .and_then(|esp| locate_xbootldr(root, esp));
+
+        Self { esp, xbootldr }
+    }
+
+    /// Tree receiving kernels, initrds and UKIs: XBOOTLDR if present, otherwise the ESP
+    pub fn kernels(&self) -> Option<&Path> {
+        self.xbootldr.as_deref().or(self.esp.as_deref())
+    }
+
+    /// All located partition trees
+    pub fn all(&self) -> impl Iterator<Item = &Path> {
+        self.esp.as_deref().into_iter().chain(self.xbootldr.as_deref())
+    }
+}
+
+/// Capacity of a mounted filesystem, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Space {
+    pub total: u64,
+    pub available: u64,
+}
+
+impl Space {
+    /// Query the capacity of the filesystem mounted at `path`
+    pub fn of(path: &Path) -> Option<Self> {
+        let stat = statvfs(path).ok()?;
+        let fragment = stat.fragment_size() as u64;
+
+        Some(Self {
+            total: stat.blocks() as u64 * fragment,
+            available: stat.blocks_available() as u64 * fragment,
+        })
+    }
+}
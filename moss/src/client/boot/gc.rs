@@ -0,0 +1,116 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Detection of moss generated loader entries which can no longer be booted
+//!
+//! Interrupted syncs and manual edits of the boot partitions can leave entries
+//! behind for removed states or with missing kernel & initrd assets. Entries
+//! without a `moss.fstx` marker are never considered stale.
+
+use std::{collections::BTreeSet, fmt, path::PathBuf};
+
+use serde::Serialize;
+
+use super::entry::LoaderEntry;
+use crate::state;
+
+/// A moss generated loader entry which can no longer be booted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StaleEntry {
+    pub path: PathBuf,
+    pub state: state::Id,
+    pub version: Option<String>,
+    pub reason: StaleReason,
+}
+
+/// Why a [`StaleEntry`] can no longer be booted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "kind", content = "value")]
+pub enum StaleReason {
+    /// The referenced state no longer exists
+    RemovedState(state::Id),
+    /// A referenced asset is missing from the boot partition
+    MissingAsset(String),
+}
+
+impl fmt::Display for StaleReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StaleReason::RemovedState(id) => write!(f, "state #{id} was removed"),
+            StaleReason::MissingAsset(asset) => write!(f, "missing {asset}"),
+        }
+    }
+}
+
+/// All moss generated `entries` belonging to a state outside of `known`, or
+/// referencing an asset missing from their partition tree
+pub fn stale(entries: &[LoaderEntry], known: &BTreeSet<state::Id>) -> Vec<StaleEntry> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let (Some(id), Some(tree)) = (entry.state_id(), entry.tree()) else {
+                return None;
+            };
+
+            let reason = if !known.contains(&id) {
+                Some(StaleReason::RemovedState(id))
+            } else {
+                entry
+                    .assets()
+                    .find(|asset| !tree.join(asset).exists())
+                    .map(|asset| StaleReason::MissingAsset(asset.to_owned()))
+            }?;
+
+            Some(StaleEntry {
+                path: entry.path.clone(),
+                state: id,
+                version: entry.version.clone(),
+                reason,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::boot::entry;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn detect_stale_entries() {
+        let esp = Scratch::new(&[
+            (
+                "loader/entries/os-6.1-1.conf",
+                "linux /EFI/os/6.1/vmlinuz\ninitrd /EFI/os/6.1/initrd\noptions moss.fstx=1\n",
+            ),
+            (
+                "loader/entries/os-6.2-2.conf",
+                "version 6.2\nlinux /EFI/os/6.2/vmlinuz\noptions moss.fstx=2\n",
+            ),
+            (
+                "loader/entries/os-6.1-3.conf",
+                "linux /EFI/os/6.1/vmlinuz\ninitrd /EFI/os/6.1/initrd\noptions moss.fstx=3\n",
+            ),
+            (
+                "loader/entries/windows.conf",
+                "title Windows\nefi /EFI/Microsoft/bootmgfw.efi\n",
+            ),
+            ("EFI/os/6.1/vmlinuz", ""),
+            ("EFI/os/6.1/initrd", ""),
+        ]);
+
+        let known = [1, 2].into_iter().map(state::Id::from).collect();
+        let stale = stale(&entry::load_all(&esp).unwrap(), &known);
+
+        assert_eq!(
+            stale.iter().map(|entry| &entry.reason).collect::<Vec<_>>(),
+            vec![
+                &StaleReason::RemovedState(state::Id::from(3)),
+                &StaleReason::MissingAsset("EFI/os/6.2/vmlinuz".to_owned())
+            ]
+        );
+        assert_eq!(stale[1].version.as_deref(), Some("6.2"));
+    }
+}
@@ -0,0 +1,190 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! GRUB backend, emitting a `grub.d` snippet consumed by `grub-mkconfig`
+//!
+//! Kernels are booted straight from the (archived) state roots, so GRUB must
+//! be able to read the root filesystem. The snippet defers device resolution to
+//! `grub-mkconfig_lib`, in the same manner as GRUB's own `10_linux`.
+
+use std::{
+    fmt::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use fs_err as fs;
+
+use super::Error;
+use crate::{state, Installation};
+
+/// Location of the generated snippet, relative to the installation root
+pub const SNIPPET: &str = "etc/grub.d/10_moss";
+
+/// Location of the generated GRUB configuration, relative to the installation root
+const GRUB_CFG: &str = "boot/grub/grub.cfg";
+
+/// Candidate `grub-mkconfig` binaries, relative to the installation root
+const MKCONFIG: &[&str] = &["usr/bin/grub-mkconfig", "usr/bin/grub2-mkconfig"];
+
+/// A single GRUB menu entry for a state's kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuEntry {
+    pub title: String,
+    pub id: String,
+    /// Absolute kernel image path, as seen from the running system
+    pub kernel: PathBuf,
+    /// Absolute initrd paths, as seen from the running system
+    pub initrds: Vec<PathBuf>,
+    /// Additional kernel command line, appended after the `GRUB_CMDLINE_LINUX` defaults
+    pub cmdline: String,
+}
+
+/// Menu entry id for kernel `version` of `state`
+pub fn entry_id(state: state::Id, version: &str) -> String {
+    format!("moss-{state}-{version}")
+}
+
+/// Render the `grub.d` snippet for all `entries`, optionally overriding the
+/// default menu entry with the `default` entry id
+pub fn render(entries: &[MenuEntry], default: Option<&str>) -> String {
+    let mut script = String::from(
+        r#"#!/bin/sh
+# Generated by moss. Do not edit, changes are lost on the next boot synchronization.
+set -e
+
+. "$pkgdatadir/grub-mkconfig_lib"
+
+if [ -n "${GRUB_DEVICE_UUID}" ]; then
+	moss_root="UUID=${GRUB_DEVICE_UUID}"
+else
+	moss_root="${GRUB_DEVICE}"
+fi
+
+moss_entry() {
+	title="$1"; id="$2"; kernel="$3"; cmdline="$4"
+	shift 4
+
+	echo "menuentry '${title}' --class moss --id '${id}' {"
+	prepare_grub_to_access_device "${GRUB_DEVICE}" | grub_add_tab
+	echo "	linux $(make_system_path_relative_to_its_root "${kernel}") root=${moss_root} ${GRUB_CMDLINE_LINUX} ${GRUB_CMDLINE_LINUX_DEFAULT} ${cmdline}"
+	initrds=""
+	for initrd in "$@"; do
+		initrds="${initrds} $(make_system_path_relative_to_its_root "${initrd}")"
+	done
+	if [ -n "${initrds}" ]; then
+		echo "	initrd${initrds}"
+	fi
+	echo "}"
+}
+
+"#,
+    );
+
+    for entry in entries {
+        let _ = write!(
+            script,
+            "moss_entry {} {} {} {}",
+            quote(&entry.title),
+            quote(&entry.id),
+            quote(&entry.kernel.to_string_lossy()),
+            quote(&entry.cmdline)
+        );
+        for initrd in &entry.initrds {
+            let _ = write!(script, " {}", quote(&initrd.to_string_lossy()));
+        }
+        script.push('\n');
+    }
+
+    if let Some(default) = default {
+        let _ = writeln!(script, "\necho \"set default={}\"", quote(default));
+    }
+
+    script
+}
+
+/// Write the snippet for `entries` into the installation, regenerating the GRUB
+/// configuration on native runs where `grub-mkconfig` is available
+pub fn synchronize(install: &Installation, entries: &[MenuEntry], default: Option<&str>) -> Result<(), Error> {
+    let snippet = install.root.join(SNIPPET);
+
+    if let Some(parent) = snippet.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&snippet, render(entries, default))?;
+    fs::set_permissions(&snippet, std::fs::Permissions::from_mode(0o755))?;
+
+    if !super::is_native(install) {
+        return Ok(());
+    }
+
+    let Some(mkconfig) = MKCONFIG
+        .iter()
+        .map(|bin| install.root.join(bin))
+        .find(|bin| bin.exists())
+    else {
+        log::warn!("grub-mkconfig not found, {SNIPPET} will be picked up on the next GRUB configuration update");
+        return Ok(());
+    };
+
+    let status = Command::new(&mkconfig)
+        .arg("-o")
+        .arg(install.root.join(GRUB_CFG))
+        .status()?;
+    if !status.success() {
+        log::warn!("{} exited with {status}", mkconfig.display());
+    }
+
+    Ok(())
+}
+
+/// Display name of the OS from the os-release file in `root`
+pub fn os_name(root: &Path) -> String {
+    super::os_release_path(root)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| {
+            contents
+                .lines()
+                .find_map(|line| line.strip_prefix("NAME=").map(|name| name.trim_matches('"').to_owned()))
+        })
+        .unwrap_or_else(|| "Linux".to_owned())
+}
+
+/// Single-quote `value` for the shell
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_entries() {
+        let entries = [MenuEntry {
+            title: "Serpent OS 6.12.9-1 (state 12)".to_owned(),
+            id: "moss-12-6.12.9-1".to_owned(),
+            kernel: "/.moss/root/12/usr/lib/kernel/6.12.9-1/vmlinuz".into(),
+            initrds: vec!["/.moss/root/12/usr/lib/kernel/6.12.9-1/10-default.initrd".into()],
+            cmdline: "moss.fstx=12".to_owned(),
+        }];
+        let script = render(&entries, None);
+
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.ends_with(
+            "moss_entry 'Serpent OS 6.12.9-1 (state 12)' 'moss-12-6.12.9-1' \
+             '/.moss/root/12/usr/lib/kernel/6.12.9-1/vmlinuz' 'moss.fstx=12' \
+             '/.moss/root/12/usr/lib/kernel/6.12.9-1/10-default.initrd'\n"
+        ));
+
+        let script = render(&entries, Some("moss-12-6.12.9-1"));
+        assert!(script.ends_with("\necho \"set default='moss-12-6.12.9-1'\"\n"));
+    }
+
+    #[test]
+    fn quote_title() {
+        assert_eq!(quote("Bob's OS"), r"'Bob'\''s OS'");
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Site-specific hooks run around boot synchronization
+//!
+//! Executables within `/etc/moss/boot.d/pre` and `/etc/moss/boot.d/post` of the
+//! installation are run in file name order, with the changed boot entries as JSON on
+//! stdin. A failing pre-hook aborts the sync before anything is written, whereas a
+//! failing post-hook is only reported. Hooks are skipped for image roots unless
+//! enabled via `image_hooks`.
+
+use std::{
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+use fs_err as fs;
+use serde::Serialize;
+use thiserror::Error;
+
+use super::plan::PlannedEntry;
+use crate::state;
+
+/// Hook directory, relative to the installation root, holding a directory per [`Phase`]
+pub const DIR: &str = "etc/moss/boot.d";
+
+/// When a hook runs, relative to the sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Phase {
+    /// Before anything is written, aborting the sync on failure
+    Pre,
+    /// After a successful sync
+    Post,
+}
+
+/// An executable hook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hook {
+    pub phase: Phase,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Error)]
+pub enum Failure {
+    #[error("spawn: {0}")]
+    Spawn(#[source] io::Error),
+    #[error("exited with {0}")]
+    Exit(ExitStatus),
+    #[error("encode changed entries: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("io: {0}")]
+    IO(#[from] io::Error),
+}
+
+/// All executable hooks of `phase` within the installation `root`, in file name order
+pub fn discover(root: &Path, phase: Phase) -> io::Result<Vec<Hook>> {
+    let dir = root.join(DIR).join(phase.to_string());
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut hooks = vec![];
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        let executable = fs::metadata(&path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0);
+
+        if !hidden && executable {
+            hooks.push(Hook { phase, path });
+        }
+    }
+    hooks.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(hooks)
+}
+
+/// Run the `hook` for the sync of `state` into the installation `root`
+///
+/// The `changed` entries are written to the hook's stdin as a JSON array.
+pub fn run(hook: &Hook, root: &Path, state: state::Id, changed: &[&PlannedEntry]) -> Result<(), Failure> {
+    let input = serde_json::to_vec(changed)?;
+
+    let mut child = Command::new(&hook.path)
+        .current_dir(root)
+        .env("MOSS_ROOT", root)
+        .env("MOSS_STATE_ID", state.to_string())
+        .env("MOSS_HOOK_PHASE", hook.phase.to_string())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(Failure::Spawn)?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(&input) {
+            // The hook isn't interested in the changed entries
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            result => result?,
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Failure::Exit(status));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::boot::plan::Action;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn discover_and_run_hooks() {
+        let root = Scratch::new(&[]);
+        let dir = root.join(DIR).join("pre");
+        fs::create_dir_all(&dir).unwrap();
+
+        let script = |name: &str, contents: &str, mode: u32| {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        script("20-fail", "#!/bin/sh\nexit 3\n", 0o755);
+        script(
+            "10-record",
+            "#!/bin/sh\ncat > \"$MOSS_ROOT/changed-$MOSS_STATE_ID.json\"\n",
+            0o755,
+        );
+        script("30-disabled", "#!/bin/sh\nexit 1\n", 0o644);
+
+        let hooks = discover(&root, Phase::Pre).unwrap();
+        assert_eq!(
+            hooks.iter().map(|hook| hook.path.clone()).collect::<Vec<_>>(),
+            vec![dir.join("10-record"), dir.join("20-fail")]
+        );
+        assert!(discover(&root, Phase::Post).unwrap().is_empty());
+
+        let entry = PlannedEntry {
+            action: Action::Create,
+            state: state::Id::from(4),
+            version: "6.12.9-1".to_owned(),
+            path: None,
+        };
+        run(&hooks[0], &root, state::Id::from(4), &[&entry]).unwrap();
+        let changed = fs::read_to_string(root.join("changed-4.json")).unwrap();
+        assert!(changed.contains("\"version\":\"6.12.9-1\""));
+
+        assert!(matches!(
+            run(&hooks[1], &root, state::Id::from(4), &[]),
+            Err(Failure::Exit(status)) if status.code() == Some(3)
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Grouping of discovered kernel files into per-version kernel trees, and
+//! validation of those trees before they're made bootable
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use super::KernelCandidate;
+use crate::{package, state};
+
+/// Kernel image file names, in order of preference
+const IMAGES: &[&str] = &["vmlinuz", "bzImage", "Image", "vmlinux"];
+
+/// Kernel module directory relative to the state's sysroot, holding a directory per version
+const MODULES_DIR: &str = "usr/lib/modules";
+
+/// All assets shipped for a single kernel version within a state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KernelTree {
+    pub version: String,
+    /// Kernel image, relative to the state's sysroot
+    pub image: Option<PathBuf>,
+    /// Initrds relative to the state's sysroot, in load order
+    pub initrds: Vec<PathBuf>,
+}
+
+/// Group the kernel `candidates` of a state by version, sorted by version
+pub(super) fn trees(candidates: &[KernelCandidate]) -> Vec<KernelTree> {
+    let mut trees = BTreeMap::<&str, KernelTree>::new();
+
+    for candidate in candidates {
+        let tree = trees.entry(&candidate.version).or_insert_with(|| KernelTree {
+            version: candidate.version.clone(),
+            ..Default::default()
+        });
+        let Some(name) = candidate.path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        if is_initrd(name) {
+            tree.initrds.push(candidate.path.clone());
+        } else if let Some(rank) = IMAGES.iter().position(|image| *image == name) {
+            let current = tree
+                .image
+                .as_ref()
+                .and_then(|image| image.file_name())
+                .and_then(|name| IMAGES.iter().position(|image| *image == name));
+
+            if current.map_or(true, |current| rank < current) {
+                tree.image = Some(candidate.path.clone());
+            }
+        }
+    }
+
+    trees
+        .into_values()
+        .map(|mut tree| {
+            tree.initrds.sort();
+            tree
+        })
+        .collect()
+}
+
+/// An expected asset of a kernel tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+pub enum Component {
+    #[strum(serialize = "kernel image")]
+    Image,
+    #[strum(serialize = "initrd")]
+    Initrd,
+    #[strum(serialize = "modules.dep")]
+    ModulesDep,
+    #[strum(serialize = "config")]
+    Config,
+    #[strum(serialize = "System.map")]
+    SystemMap,
+}
+
+impl Component {
+    /// Returns true if a kernel can't be booted without this component
+    pub fn is_required(&self) -> bool {
+        matches!(self, Component::Image | Component::Initrd | Component::ModulesDep)
+    }
+}
+
+/// The components missing from a kernel tree of a state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub state: state::Id,
+    /// Package shipping the kernel
+    pub package: Option<package::Id>,
+    pub version: String,
+    pub missing: Vec<Component>,
+}
+
+impl Diagnosis {
+    /// Returns true if no required component is missing
+    pub fn is_bootable(&self) -> bool {
+        !self.missing.iter().any(Component::is_required)
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "kernel {} in state {}", self.version, self.state)?;
+        if let Some(package) = &self.package {
+            write!(f, " (package {package})")?;
+        }
+
+        let missing = self
+            .missing
+            .iter()
+            .map(|component| component.to_string())
+            .collect::<Vec<_>>();
+        write!(f, " is missing {}", missing.join(", "))
+    }
+}
+
+/// Diagnose the kernel `tree` of `state`, discovered from `candidates` within `sysroot`
+pub(super) fn diagnose(
+    state: state::Id,
+    sysroot: &Path,
+    tree: &KernelTree,
+    candidates: &[KernelCandidate],
+) -> Diagnosis {
+    let files = candidates
+        .iter()
+        .filter(|candidate| candidate.version == tree.version)
+        .collect::<Vec<_>>();
+    let has_file = |matches: fn(&str) -> bool| {
+        files.iter().any(|candidate| {
+            candidate
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(matches)
+        })
+    };
+
+    let mut missing = vec![];
+    if tree.image.is_none() {
+        missing.push(Component::Image);
+    }
+    if tree.initrds.is_empty() {
+        missing.push(Component::Initrd);
+    }
+    if !sysroot
+        .join(MODULES_DIR)
+        .join(&tree.version)
+        .join("modules.dep")
+        .exists()
+    {
+        missing.push(Component::ModulesDep);
+    }
+    if !has_file(|name| name == "config" || name.starts_with("config-")) {
+        missing.push(Component::Config);
+    }
+    if !has_file(|name| name == "System.map" || name.starts_with("System.map-")) {
+        missing.push(Component::SystemMap);
+    }
+
+    Diagnosis {
+        state,
+        package: files.first().map(|candidate| candidate.package.clone()),
+        version: tree.version.clone(),
+        missing,
+    }
+}
+
+/// Returns true if the file name denotes an initrd
+fn is_initrd(name: &str) -> bool {
+    name.ends_with(".initrd") || name.starts_with("initrd") || name.starts_with("initramfs")
+}
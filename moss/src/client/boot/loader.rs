@@ -0,0 +1,90 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Management of the `default` key in systemd-boot's `loader.conf`
+//!
+//! Only the `default` line is ever touched, any other user configuration
+//! within `loader.conf` is preserved as-is.
+
+use std::{io, path::Path};
+
+use fs_err as fs;
+
+/// Location of `loader.conf`, relative to the ESP
+pub const LOADER_CONF: &str = "loader/loader.conf";
+
+/// Set the `default` key within `loader.conf` `contents` to `entry`
+pub fn with_default(contents: &str, entry: &str) -> String {
+    let line = format!("default {entry}");
+    let mut replaced = false;
+
+    let mut lines = contents
+        .lines()
+        .filter_map(|existing| {
+            let is_default = existing
+                .split_once(char::is_whitespace)
+                .map_or(existing, |(key, _)| key)
+                .eq("default");
+
+            match (is_default, replaced) {
+                (false, _) => Some(existing.to_owned()),
+                // Drop any duplicate keys
+                (true, true) => None,
+                (true, false) => {
+                    replaced = true;
+                    Some(line.clone())
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if !replaced {
+        lines.push(line);
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+/// Point the default entry of the loader on `esp` at `entry`, returning true if changed
+pub fn set_default(esp: &Path, entry: &str) -> io::Result<bool> {
+    let path = esp.join(LOADER_CONF);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let updated = with_default(&contents, entry);
+    if updated == contents {
+        return Ok(false);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, updated)?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replace_default() {
+        assert_eq!(
+            with_default("timeout 3\ndefault old.conf\neditor no\n", "new.conf"),
+            "timeout 3\ndefault new.conf\neditor no\n"
+        );
+        assert_eq!(with_default("timeout 3\n", "new.conf"), "timeout 3\ndefault new.conf\n");
+        assert_eq!(with_default("", "new.conf"), "default new.conf\n");
+        assert_eq!(
+            with_default("default a.conf\n#default b.conf\ndefault c.conf\n", "new.conf"),
+            "default new.conf\n#default b.conf\n"
+        );
+    }
+}
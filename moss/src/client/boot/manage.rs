@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Whether boot management applies to an installation at all
+//!
+//! Containers, chroots and some image types must never touch boot partitions, even
+//! when a kernel package happens to be installed. With [`Manage::Auto`] native roots
+//! are skipped when running in a container or, for the systemd-boot backend, without
+//! EFI firmware. Image roots are always managed, as they're explicitly targeted.
+
+use std::path::Path;
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use super::Backend;
+
+/// Marker file written by container managers, per the systemd container interface
+const CONTAINER: &str = "/run/systemd/container";
+
+/// Present when booted via EFI firmware
+const EFI_FIRMWARE: &str = "/sys/firmware/efi";
+
+/// Boot management policy of an installation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, strum::Display, strum::EnumString)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Manage {
+    /// Manage unless the environment can't be booted from
+    #[default]
+    Auto,
+    /// Always manage, e.g. for containers building bootable images in place
+    Always,
+    /// Never manage
+    Never,
+}
+
+/// The environment moss runs in, as relevant to [`Manage::Auto`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Environment {
+    /// Container manager, e.g. `docker` or `systemd-nspawn`
+    pub container: Option<String>,
+    /// Whether EFI firmware is available
+    pub efi: bool,
+}
+
+impl Environment {
+    /// Detect the environment of the running system
+    pub fn detect() -> Self {
+        Self {
+            container: fs::read_to_string(CONTAINER)
+                .ok()
+                .map(|manager| manager.trim().to_owned())
+                .map(|manager| {
+                    if manager.is_empty() {
+                        "unknown".to_owned()
+                    } else {
+                        manager
+                    }
+                }),
+            efi: Path::new(EFI_FIRMWARE).is_dir(),
+        }
+    }
+}
+
+/// Decide whether `backend` is managed for an installation, returning why not otherwise
+pub fn check(manage: Manage, is_native: bool, backend: Backend, environment: &Environment) -> Result<(), String> {
+    match manage {
+        Manage::Always => Ok(()),
+        Manage::Never => Err("boot management disabled via `manage: never`".to_owned()),
+        Manage::Auto if !is_native => Ok(()),
+        Manage::Auto => {
+            if let Some(container) = &environment.container {
+                return Err(format!("running in a {container} container"));
+            }
+            if backend == Backend::SystemdBoot && !environment.efi {
+                return Err("no EFI firmware found".to_owned());
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_environment() {
+        let container = Environment {
+            container: Some("docker".to_owned()),
+            efi: true,
+        };
+        let bios = Environment {
+            container: None,
+            efi: false,
+        };
+
+        assert_eq!(
+            check(Manage::Auto, true, Backend::SystemdBoot, &container),
+            Err("running in a docker container".to_owned())
+        );
+        assert!(check(Manage::Auto, true, Backend::SystemdBoot, &bios).is_err());
+        // GRUB boots fine from BIOS, and images are built from anywhere
+        assert!(check(Manage::Auto, true, Backend::Grub, &bios).is_ok());
+        assert!(check(Manage::Auto, false, Backend::SystemdBoot, &container).is_ok());
+
+        assert!(check(Manage::Always, true, Backend::SystemdBoot, &container).is_ok());
+        assert!(check(Manage::Never, false, Backend::Grub, &Environment::default()).is_err());
+        assert_eq!("never".parse::<Manage>(), Ok(Manage::Never));
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Early CPU microcode images, loaded ahead of every kernel's initrds
+//!
+//! Microcode is shipped by separate packages outside of the versioned kernel
+//! tree, so it is discovered per state and attached to each kernel of that state.
+
+use std::path::{Path, PathBuf};
+
+use fnmatch::Pattern;
+use stone::payload::layout::{self, Layout};
+
+use super::copy;
+use crate::package::Id;
+
+/// Default microcode image patterns, relative to `/usr`
+pub const PATTERNS: &[&str] = &["lib/firmware/*-ucode.img"];
+
+/// Installed microcode directory, relative to the boot partition
+pub const DIR: &str = "EFI/moss/ucode";
+
+/// A microcode image within a state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Microcode {
+    /// Image path, relative to the state's sysroot
+    pub path: PathBuf,
+    /// Content hash, from the layout
+    pub hash: u128,
+}
+
+impl Microcode {
+    /// Installed file name, unique per content so states shipping different
+    /// microcode revisions never clobber each other
+    pub fn file_name(&self) -> String {
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        format!("{:032x}-{name}", self.hash)
+    }
+
+    /// Boot partition relative path of the installed image, with a leading `/`
+    pub fn asset(&self) -> String {
+        format!("/{DIR}/{}", self.file_name())
+    }
+}
+
+/// Find all microcode images in `layouts` matching any of `patterns`, sorted by path
+pub fn from_layouts(layouts: &[(Id, Layout)], patterns: &[Pattern]) -> Vec<Microcode> {
+    let mut images = layouts
+        .iter()
+        .filter_map(|(_, layout)| match &layout.entry {
+            layout::Entry::Regular(hash, target) if patterns.iter().any(|p| p.match_path(target).is_some()) => {
+                Some(Microcode {
+                    path: PathBuf::from("usr").join(target),
+                    hash: *hash,
+                })
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    images.sort_by(|a, b| a.path.cmp(&b.path));
+
+    images
+}
+
+/// The installation of `image` from `sysroot` into the boot partition `tree`
+pub fn job(tree: &Path, sysroot: &Path, image: &Microcode) -> copy::Job {
+    copy::Job {
+        source: sysroot.join(&image.path),
+        destination: tree.join(DIR).join(image.file_name()),
+    }
+}
+
+/// Prepend `initrds` to the loader entry `contents`, ahead of its existing initrds
+///
+/// Lines already present are left in place, so injection is idempotent.
+pub fn inject(contents: &str, initrds: &[String]) -> String {
+    let missing = initrds
+        .iter()
+        .filter(|initrd| {
+            !contents
+                .lines()
+                .any(|line| line.trim().strip_prefix("initrd").map(str::trim) == Some(initrd.as_str()))
+        })
+        .map(|initrd| format!("initrd {initrd}"))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        return contents.to_owned();
+    }
+
+    let lines = contents.lines().collect::<Vec<_>>();
+    // Ahead of the first initrd, otherwise directly after the kernel
+    let position = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with("initrd"))
+        .or_else(|| {
+            lines
+                .iter()
+                .position(|line| line.trim_start().starts_with("linux"))
+                .map(|idx| idx + 1)
+        })
+        .unwrap_or(lines.len());
+
+    let mut output = lines[..position]
+        .iter()
+        .map(|line| (*line).to_owned())
+        .chain(missing)
+        .chain(lines[position..].iter().map(|line| (*line).to_owned()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    output.push('\n');
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn inject_microcode_first() {
+        let entry = "title Serpent OS\nlinux /EFI/os/vmlinuz\ninitrd /EFI/os/initrd\noptions moss.fstx=1\n";
+        let ucode = vec!["/EFI/moss/ucode/00-intel-ucode.img".to_owned()];
+
+        let injected = inject(entry, &ucode);
+        assert_eq!(
+            injected,
+            "title Serpent OS\nlinux /EFI/os/vmlinuz\ninitrd /EFI/moss/ucode/00-intel-ucode.img\n\
+             initrd /EFI/os/initrd\noptions moss.fstx=1\n"
+        );
+        assert_eq!(inject(&injected, &ucode), injected);
+
+        assert_eq!(
+            inject("linux /EFI/os/vmlinuz\noptions quiet\n", &ucode),
+            "linux /EFI/os/vmlinuz\ninitrd /EFI/moss/ucode/00-intel-ucode.img\noptions quiet\n"
+        );
+    }
+
+    #[test]
+    fn microcode_from_layouts() {
+        let layouts = crate::client::boot::backend::test::layouts(&[
+            "lib/firmware/intel-ucode.img",
+            "lib/firmware/amd-ucode.img",
+            "lib/firmware/iwlwifi-ty-a0-gf-a0-77.ucode",
+        ]);
+        let patterns = PATTERNS
+            .iter()
+            .map(|pattern| pattern.parse::<Pattern>().unwrap())
+            .collect::<Vec<_>>();
+
+        let images = from_layouts(&layouts, &patterns);
+
+        assert_eq!(
+            images.iter().map(|image| image.path.clone()).collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("usr/lib/firmware/amd-ucode.img"),
+                PathBuf::from("usr/lib/firmware/intel-ucode.img")
+            ]
+        );
+        assert_eq!(
+            images[0].asset(),
+            "/EFI/moss/ucode/00000000000000000000000000000000-amd-ucode.img"
+        );
+    }
+}
@@ -0,0 +1,317 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Additional ESPs mirroring the primary one, as used by redundant (e.g. RAID1) setups
+//!
+//! Only the primary boot partitions are synchronized via blsforme. Each mirror is then
+//! reconciled against them: the `loader` and `EFI` trees are copied over where they
+//! differ, and files gone from the primary partitions are removed from the mirror, so
+//! every disk remains bootable on its own. A failing mirror is reported as a warning
+//! and never fails the transaction.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    fmt, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use fs_err as fs;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use serde::{Deserialize, Serialize};
+
+use super::copy;
+
+/// Trees of the primary boot partitions mirrored onto every mirror ESP
+const TREES: &[&str] = &["loader", "EFI"];
+
+/// Files owned by each ESP individually, which are never mirrored nor removed
+const EXCLUDED: &[&str] = &["loader/random-seed"];
+
+/// Symlinks to the partitions by their GPT partition UUID
+const BY_PARTUUID: &str = "/dev/disk/by-partuuid";
+
+/// Directory in which unmounted mirrors are mounted for the duration of a sync
+const MOUNT_DIR: &str = "/run/moss/esp";
+
+/// A configured mirror ESP
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum Target {
+    /// A partition by its GPT partition UUID, e.g. `PARTUUID=0f3a…`
+    PartUuid(String),
+    /// An already mounted ESP, relative to the installation root
+    Path(PathBuf),
+}
+
+impl FromStr for Target {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix("PARTUUID=") {
+            Some(uuid) => Target::PartUuid(uuid.to_lowercase()),
+            None => Target::Path(PathBuf::from(s)),
+        })
+    }
+}
+
+impl From<String> for Target {
+    fn from(value: String) -> Self {
+        value.parse().unwrap_or_else(|e: Infallible| match e {})
+    }
+}
+
+impl From<Target> for String {
+    fn from(value: Target) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::PartUuid(uuid) => write!(f, "PARTUUID={uuid}"),
+            Target::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A mounted mirror ESP, unmounted on drop if mounted by moss
+#[derive(Debug)]
+pub struct Mirror {
+    pub target: Target,
+    pub path: PathBuf,
+    mounted: bool,
+}
+
+impl Drop for Mirror {
+    fn drop(&mut self) {
+        if self.mounted {
+            if let Err(e) = umount2(&self.path, MntFlags::MNT_DETACH) {
+                log::warn!("Failed to unmount {}: {e}", self.path.display());
+            }
+        }
+    }
+}
+
+/// The synchronization of a single mirror ESP
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Outcome {
+    /// The configured mirror
+    pub target: String,
+    /// Mountpoint of the mirror, if it could be opened
+    pub path: Option<PathBuf>,
+    /// Files copied to the mirror or skipped as up to date
+    pub transfer: copy::Stats,
+    /// Files removed from the mirror, as they're gone from the primary partitions
+    pub removed: Vec<PathBuf>,
+    /// Why the mirror couldn't be synchronized
+    pub error: Option<String>,
+}
+
+/// Open the mirror `target` of the installation `root`
+///
+/// Partitions by UUID which aren't mounted yet are mounted beneath [`MOUNT_DIR`],
+/// whereas paths must already be a mounted filesystem.
+pub fn open(root: &Path, target: &Target) -> io::Result<Mirror> {
+    match target {
+        Target::Path(path) => {
+            let path = root.join(path.strip_prefix("/").unwrap_or(path));
+            let device = |path: &Path| fs::metadata(path).map(|meta| meta.dev());
+
+            if device(&path)? == device(root)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not a mounted partition", path.display()),
+                ));
+            }
+
+            Ok(Mirror {
+                target: target.clone(),
+                path,
+                mounted: false,
+            })
+        }
+        Target::PartUuid(uuid) => {
+            let device = fs::canonicalize(Path::new(BY_PARTUUID).join(uuid))?;
+            let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+            if let Some(path) = mountpoint(&mountinfo, &device) {
+                return Ok(Mirror {
+                    target: target.clone(),
+                    path,
+                    mounted: false,
+                });
+            }
+
+            let path = Path::new(MOUNT_DIR).join(uuid);
+            fs::create_dir_all(&path)?;
+            mount(Some(&device), &path, Some("vfat"), MsFlags::empty(), None::<&str>).map_err(io::Error::from)?;
+
+            Ok(Mirror {
+                target: target.clone(),
+                path,
+                mounted: true,
+            })
+        }
+    }
+}
+
+/// Reconcile the `mirror` ESP with the primary boot partition `trees`
+///
+/// Files present in multiple trees are taken from the first one, i.e. the ESP.
+/// Returns the copy stats and all files removed from the mirror.
+pub fn reconcile<'a>(
+    trees: impl IntoIterator<Item = &'a Path>,
+    mirror: &Path,
+) -> Result<(copy::Stats, Vec<PathBuf>), copy::Failure> {
+    let mirror_device = fs::metadata(mirror)?.dev();
+    let mut files = BTreeMap::new();
+
+    for tree in trees {
+        if fs::metadata(tree)?.dev() == mirror_device {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a primary boot partition", mirror.display()),
+            )
+            .into());
+        }
+
+        for file in mirrored(tree)? {
+            files.entry(file).or_insert_with_key(|file| tree.join(file));
+        }
+    }
+
+    let jobs = files
+        .iter()
+        .map(|(file, source)| copy::Job {
+            source: source.clone(),
+            destination: mirror.join(file),
+        })
+        .collect::<Vec<_>>();
+    let stats = copy::install(&jobs)?;
+
+    let expected = files.into_keys().collect::<BTreeSet<_>>();
+    let mut removed = vec![];
+
+    for file in mirrored(mirror)? {
+        if expected.contains(&file) {
+            continue;
+        }
+
+        let path = mirror.join(&file);
+        fs::remove_file(&path)?;
+        if let Some(parent) = path.parent() {
+            super::remove_empty_dirs(parent, mirror)?;
+        }
+        removed.push(path);
+    }
+
+    Ok((stats, removed))
+}
+
+/// All mirrored files of the boot partition `tree`, relative to it
+fn mirrored(tree: &Path) -> io::Result<Vec<PathBuf>> {
+    fn recurse(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                recurse(&entry.path(), files)?;
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = vec![];
+    for dir in TREES {
+        recurse(&tree.join(dir), &mut files)?;
+    }
+
+    Ok(files
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(tree).ok().map(Path::to_path_buf))
+        .filter(|file| !EXCLUDED.iter().any(|excluded| file == Path::new(excluded)))
+        .collect())
+}
+
+/// Mountpoint of the block `device` within the `mountinfo` table, per proc_pid_mountinfo(5)
+fn mountpoint(mountinfo: &str, device: &Path) -> Option<PathBuf> {
+    mountinfo.lines().find_map(|line| {
+        let (mount, source) = line.split_once(" - ")?;
+        let mountpoint = mount.split_whitespace().nth(4)?;
+        let source = source.split_whitespace().nth(1)?;
+
+        (Path::new(source) == device).then(|| PathBuf::from(unescape(mountpoint)))
+    })
+}
+
+/// Decode the octal escapes (e.g. `\040` for a space) of a mountinfo field
+fn unescape(field: &str) -> String {
+    let mut output = String::with_capacity(field.len());
+    let mut rest = field;
+
+    while let Some(idx) = rest.find('\\') {
+        output.push_str(&rest[..idx]);
+        let escaped = rest
+            .get(idx + 1..idx + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+
+        match escaped {
+            Some(byte) => {
+                output.push(byte as char);
+                rest = &rest[idx + 4..];
+            }
+            None => {
+                output.push('\\');
+                rest = &rest[idx + 1..];
+            }
+        }
+    }
+    output.push_str(rest);
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_targets() {
+        assert_eq!(
+            "PARTUUID=0F3A-11".parse::<Target>().unwrap(),
+            Target::PartUuid("0f3a-11".to_owned())
+        );
+        assert_eq!("/efi2".parse::<Target>().unwrap(), Target::Path("/efi2".into()));
+        assert_eq!(Target::PartUuid("0f3a-11".to_owned()).to_string(), "PARTUUID=0f3a-11");
+    }
+
+    #[test]
+    fn mountinfo_mountpoint() {
+        let mountinfo = "22 1 259:2 / / rw,relatime shared:1 - btrfs /dev/nvme0n1p2 rw\n\
+                         41 22 259:1 / /efi rw,relatime shared:20 - vfat /dev/nvme0n1p1 rw,fmask=0077\n\
+                         42 22 8:1 / /mnt/second\\040esp rw,relatime shared:21 - vfat /dev/sda1 rw\n";
+
+        assert_eq!(
+            mountpoint(mountinfo, Path::new("/dev/nvme0n1p1")),
+            Some(PathBuf::from("/efi"))
+        );
+        assert_eq!(
+            mountpoint(mountinfo, Path::new("/dev/sda1")),
+            Some(PathBuf::from("/mnt/second esp"))
+        );
+        assert_eq!(mountpoint(mountinfo, Path::new("/dev/sdb1")), None);
+    }
+}
@@ -0,0 +1,2096 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Boot management integration in moss
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    str::FromStr,
+    vec,
+};
+
+use blsforme::{
+    os_release::{self, OsRelease},
+    CmdlineEntry, Entry, Schema,
+};
+use fnmatch::Pattern;
+use fs_err as fs;
+use itertools::Itertools;
+use stone::payload::layout::{self, Layout};
+use thiserror::{self, Error};
+
+use crate::{db, package::Id, state, Installation, State};
+
+use self::entry::LoaderEntry;
+use super::Client;
+
+pub use self::backend::Backend;
+pub use self::manage::Manage;
+pub use self::plan::{Plan, SkipReason, SyncOutcome};
+pub use self::settings::{DefaultEntry, Mode, Settings};
+pub use self::status::{status, Status};
+
+pub mod arch;
+pub mod backend;
+pub mod bootloader;
+pub mod cmdline;
+pub mod copy;
+pub mod dtb;
+pub mod entry;
+pub mod esp;
+pub mod gc;
+pub mod grub;
+pub mod hooks;
+pub mod kernel;
+pub mod loader;
+pub mod manage;
+pub mod microcode;
+pub mod mirror;
+pub mod mount;
+pub mod owner;
+pub mod plan;
+pub mod rescue;
+pub mod running;
+pub mod settings;
+pub mod sign;
+pub mod space;
+pub mod status;
+pub mod title;
+pub mod uki;
+pub mod validate;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("blsforme: {0}")]
+    Blsforme(#[from] blsforme::Error),
+
+    #[error("layoutdb: {0}")]
+    Client(#[from] db::layout::Error),
+
+    #[error("io: {0}")]
+    IO(#[from] io::Error),
+
+    #[error("os_release: {0}")]
+    OsRelease(#[from] os_release::Error),
+
+    #[error("failed to read os-release {}: {1}", .0.display())]
+    OsReleaseFile(PathBuf, #[source] io::Error),
+
+    /// fnmatch pattern compilation for boot, etc.
+    #[error("fnmatch pattern: {0}")]
+    Pattern(#[from] fnmatch::Error),
+
+    #[error("{0}")]
+    InvalidPattern(#[from] settings::InvalidPattern),
+
+    #[error("incomplete {0}")]
+    IncompleteKernel(kernel::Diagnosis),
+
+    #[error("no systemd-stub found in the active state")]
+    MissingStub,
+
+    #[error("ukify not found in the active state")]
+    MissingUkify,
+
+    #[error("ukify failed to build {}: {1}", .0.display())]
+    Ukify(PathBuf, ExitStatus),
+
+    #[error("copy: {0}")]
+    Copy(#[from] copy::Failure),
+
+    #[error("insufficient space: {0}")]
+    InsufficientSpace(space::Shortfall),
+
+    #[error("failed to sign {}: {1}", .0.display())]
+    Signing(PathBuf, #[source] sign::Failure),
+
+    #[error("failed to remount {} read-write: {1}", .0.display())]
+    Remount(PathBuf, #[source] io::Error),
+
+    #[error("{0} hook {} failed: {2}", .1.display())]
+    Hook(hooks::Phase, PathBuf, #[source] hooks::Failure),
+}
+
+/// Returns true if the installation is the running system
+fn is_native(install: &Installation) -> bool {
+    install.root.to_string_lossy() == "/"
+}
+
+/// The packaged architecture of `state`, namespacing its boot entries and assets
+pub fn architecture(install_db: &db::meta::Database, state: &State) -> Option<arch::Arch> {
+    arch::Arch::of_packages(
+        state
+            .selections
+            .iter()
+            .filter_map(|selection| install_db.get(&selection.package).ok())
+            .map(|meta| meta.architecture),
+    )
+}
+
+/// The packaged architecture of the active state
+fn active_architecture(client: &Client) -> Option<arch::Arch> {
+    let state = client.state_db.get(client.installation.active_state?).ok()?;
+
+    architecture(&client.install_db, &state)
+}
+
+/// All loader entries of the `tree` owned by `arch`, never those of other
+/// architectures sharing the partition
+fn own_entries(tree: &Path, arch: Option<arch::Arch>) -> io::Result<Vec<LoaderEntry>> {
+    Ok(entry::load_all(tree)?
+        .into_iter()
+        .filter(|entry| arch.map_or(true, |arch| arch.owns(entry)))
+        .collect())
+}
+
+/// Construct the blsforme configuration for the installation
+fn configuration(install: &Installation) -> blsforme::Configuration {
+    let root = install.root.clone();
+
+    blsforme::Configuration {
+        root: if is_native(install) {
+            blsforme::Root::Native(root)
+        } else {
+            blsforme::Root::Image(root)
+        },
+        vfs: "/".into(),
+    }
+}
+
+/// Simple mapping type for kernel discovery paths, retaining the layout reference
+#[derive(Debug)]
+struct KernelCandidate {
+    path: PathBuf,
+    version: String,
+    /// Package shipping the file
+    package: Id,
+    _layout: Layout,
+}
+
+impl AsRef<Path> for KernelCandidate {
+    fn as_ref(&self) -> &Path {
+        self.path.as_path()
+    }
+}
+
+/// From a given set of input paths, produce a set of match pairs
+/// This is applied against the given system root
+fn kernel_files_from_state(layouts: &[(Id, Layout)], patterns: &[Pattern]) -> Vec<KernelCandidate> {
+    let mut kernel_entries = vec![];
+
+    for (package, path) in layouts.iter() {
+        let (layout::Entry::Regular(_, target) | layout::Entry::Symlink(_, target)) = &path.entry else {
+            continue;
+        };
+
+        if let Some(m) = patterns.iter().find_map(|pattern| pattern.match_path(target)) {
+            kernel_entries.push(KernelCandidate {
+                path: PathBuf::from("usr").join(target),
+                version: m.variables.get("version").cloned().unwrap_or_default(),
+                package: package.clone(),
+                _layout: path.to_owned(),
+            });
+        }
+    }
+
+    kernel_entries
+}
+
+/// Find the bootloader assets matching `patterns` in the new state
+fn boot_files_from_new_state(install: &Installation, layouts: &[(Id, Layout)], patterns: &[Pattern]) -> Vec<PathBuf> {
+    backend::assets(layouts, patterns)
+        .into_iter()
+        .map(|target| install.root.join("usr").join(target))
+        .collect()
+}
+
+/// Candidate os-release files relative to the installation root, in order of preference
+const OS_RELEASE: &[&str] = &["usr/lib/os-release", "etc/os-release"];
+
+/// Minimal os-release for installations providing none, per the os-release(5) defaults
+const FALLBACK_OS_RELEASE: &str = "NAME=\"Linux\"\nID=linux\nPRETTY_NAME=\"Linux\"\n";
+
+/// Read the os-release file we created
+// TODO: This needs per-state generation for the VERSION bits!
+fn read_os_release(root: &Path, strict: bool) -> Result<OsRelease, Error> {
+    Ok(OsRelease::from_str(&os_release_contents(root, strict)?)?)
+}
+
+/// The os-release `ID` of `root`, naming its directory on the boot partitions
+fn os_id(root: &Path) -> Option<String> {
+    fs::read_to_string(os_release_path(root)?)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("ID="))
+        .map(|id| id.trim().trim_matches('"').to_owned())
+}
+
+/// The first os-release file present in `root`
+fn os_release_path(root: &Path) -> Option<PathBuf> {
+    OS_RELEASE.iter().map(|path| root.join(path)).find(|path| path.exists())
+}
+
+/// Contents of the os-release file in `root`
+///
+/// Partially constructed images may lack an os-release file entirely, in which case
+/// a minimal one is synthesized unless `strict`.
+fn os_release_contents(root: &Path, strict: bool) -> Result<String, Error> {
+    match os_release_path(root) {
+        Some(path) => fs::read_to_string(&path).map_err(|e| Error::OsReleaseFile(path, e)),
+        None if strict => Err(Error::OsReleaseFile(
+            root.join(OS_RELEASE[0]),
+            io::ErrorKind::NotFound.into(),
+        )),
+        None => {
+            log::warn!("No os-release found in {}, using defaults", root.display());
+            Ok(FALLBACK_OS_RELEASE.to_owned())
+        }
+    }
+}
+
+/// Grab all layouts for the provided state, mapped to package id
+fn layouts_for_state(client: &Client, state: &State) -> Result<Vec<(Id, Layout)>, db::Error> {
+    client.layout_db.query(state.selections.iter().map(|s| &s.package))
+}
+
+/// Return an additional 4 older states excluding the current state
+fn states_except_new(client: &Client, state: &State) -> Result<Vec<State>, db::Error> {
+    let states = client
+        .state_db
+        .list_complete_ids()?
+        .into_iter()
+        .filter_map(|(id, whence)| {
+            // All states with older ID and not the current state
+            if id != state.id && state.id > id {
+                Some((id, whence))
+            } else {
+                None
+            }
+        })
+        .sorted_by_key(|(_, whence)| whence.to_owned())
+        .rev()
+        .take(4)
+        .filter_map(|(id, _)| client.state_db.get(id).ok())
+        .collect::<Vec<_>>();
+    Ok(states)
+}
+
+/// Resolve the cmdline fragments of every state
+///
+/// States boot with the command line persisted when they were first synchronized,
+/// only re-resolved on request via [`refresh_cmdline`]. States lacking a persisted
+/// command line pick up the current fragments, which are recorded for them unless
+/// `persist` is false.
+fn state_cmdlines(
+    client: &Client,
+    states: &[State],
+    persist: bool,
+) -> Result<BTreeMap<state::Id, Vec<cmdline::Fragment>>, Error> {
+    let current = cmdline::load(&client.installation.root);
+    let mut cmdlines = BTreeMap::new();
+
+    for state in states {
+        let fragments = match client.state_db.cmdline(state.id)? {
+            Some(stored) => cmdline::stored(&stored),
+            None => {
+                if persist {
+                    client.state_db.set_cmdline(state.id, &cmdline::join(&current))?;
+                }
+                current.clone()
+            }
+        };
+
+        cmdlines.insert(state.id, fragments);
+    }
+
+    Ok(cmdlines)
+}
+
+/// Re-resolve the persisted cmdline of `state` from the current fragments
+///
+/// Takes effect on the next synchronization.
+pub fn refresh_cmdline(client: &Client, state: state::Id) -> Result<(), Error> {
+    let fragments = cmdline::load(&client.installation.root);
+    client.state_db.set_cmdline(state, &cmdline::join(&fragments))?;
+
+    Ok(())
+}
+
+/// Return the `state` followed by up to 4 older states, as synchronized by a transaction
+pub fn transaction_states(client: &Client, state: &State) -> Result<Vec<State>, Error> {
+    let mut states = states_except_new(client, state)?;
+    states.insert(0, state.clone());
+
+    Ok(states)
+}
+
+/// Return all retained states for boot synchronization, with the active state first
+/// followed by the remaining states from newest to oldest
+pub fn retained_states(client: &Client) -> Result<Vec<State>, Error> {
+    let active = client.installation.active_state;
+
+    let states = client
+        .state_db
+        .list_complete_ids()?
+        .into_iter()
+        .sorted_by_key(|(id, whence)| (Some(*id) == active, whence.to_owned()))
+        .rev()
+        .map(|(id, _)| client.state_db.get(id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(states)
+}
+
+/// Synchronize boot entries for the new `state`, retaining entries for up to
+/// 4 older states so they remain bootable rollback targets
+///
+/// A skipped sync is reported as a warning, as the new state won't be bootable
+/// from the boot menu.
+pub fn synchronize(client: &Client, state: &State) -> Result<SyncOutcome, Error> {
+    let all_states = transaction_states(client, state)?;
+
+    let outcome = synchronize_all(client, &all_states, false)?;
+
+    match &outcome {
+        // Expected, so not worth a warning on every transaction
+        SyncOutcome::Skipped(reason @ SkipReason::Unmanaged(_)) => {
+            log::info!("Skipped boot synchronization: {reason}");
+        }
+        SyncOutcome::Skipped(reason) => {
+            log::warn!("Skipped boot synchronization: {reason}");
+        }
+        _ => {}
+    }
+
+    Ok(outcome)
+}
+
+/// Synchronize boot entries for every provided state in a single pass, returning
+/// the executed plan
+///
+/// The first state is treated as the head state: it provides the bootloader assets
+/// and lives in the installation root, whereas all other states are resolved via
+/// their archived root. States without an archived root are skipped.
+///
+/// With `dry_run` the plan is returned without mounting or writing anything.
+pub fn synchronize_all(client: &Client, states: &[State], dry_run: bool) -> Result<SyncOutcome, Error> {
+    let Some(state) = states.first() else {
+        return Ok(SyncOutcome::Skipped(SkipReason::NoKernels));
+    };
+
+    let settings = Settings::load(&client.config);
+    let head_layouts = layouts_for_state(client, state)?;
+
+    // no fun times without a bootloder
+    let backend = match settings.backend {
+        Some(backend) => backend,
+        None => match Backend::detect(&head_layouts)? {
+            Some(backend) => backend,
+            None => return Ok(SyncOutcome::Skipped(SkipReason::NoBootloader)),
+        },
+    };
+    if let Err(reason) = managed(client, &settings, backend) {
+        return Ok(SyncOutcome::Skipped(SkipReason::Unmanaged(reason)));
+    }
+    let mode = settings.mode.unwrap_or_default();
+
+    let (kernels, incomplete) = discover_kernels(client, &settings, states)?;
+    for diagnosis in incomplete {
+        log::warn!("Skipping {}", Error::IncompleteKernel(diagnosis));
+    }
+    let pass = Pass {
+        settings,
+        states,
+        kernels,
+    };
+    let settings = &pass.settings;
+
+    let mut plan = plan(client, &pass, &head_layouts, backend, mode)?;
+    if plan.entries.iter().all(|entry| entry.action == plan::Action::Delete) {
+        return Ok(SyncOutcome::Skipped(SkipReason::NoKernels));
+    }
+    plan.hooks = sync_hooks(&client.installation, settings)?;
+    if dry_run {
+        return Ok(SyncOutcome::Planned(plan));
+    }
+
+    // Abort before writing anything if the assets won't fit
+    space::check(&plan.required, settings.space_margin.unwrap_or(space::DEFAULT_MARGIN))
+        .map_err(Error::InsufficientSpace)?;
+
+    let changed = plan.pending().collect::<Vec<_>>();
+    for hook in plan.hooks.iter().filter(|hook| hook.phase == hooks::Phase::Pre) {
+        hooks::run(hook, &client.installation.root, state.id, &changed)
+            .map_err(|e| Error::Hook(hook.phase, hook.path.clone(), e))?;
+    }
+
+    // Reconcile first, so entries with missing assets are regenerated by the sync
+    if (backend, mode) == (Backend::SystemdBoot, Mode::Entries) {
+        collect_garbage(client, settings, backend, false)?;
+    }
+
+    let mut transfer = copy::Stats::default();
+    let mut invalid = vec![];
+    let skipped = match (backend, mode) {
+        (Backend::SystemdBoot, Mode::Entries) => {
+            synchronize_systemd_boot(client, &pass, &head_layouts, &mut transfer, &mut invalid)?
+        }
+        (Backend::SystemdBoot, Mode::Uki) => synchronize_uki(client, &pass, &head_layouts, &mut transfer)?,
+        (Backend::Grub, _) => synchronize_grub(client, &pass)?,
+    };
+
+    match skipped {
+        Some(reason) => {
+            log::warn!("Skipped boot synchronization: {reason}");
+            Ok(SyncOutcome::Skipped(reason))
+        }
+        None => {
+            let mirrors = match backend {
+                Backend::SystemdBoot => with_partitions(&client.installation, settings, |partitions| {
+                    Ok(synchronize_mirrors(&client.installation, settings, partitions))
+                })?,
+                Backend::Grub => vec![],
+            };
+
+            let changed = plan.pending().collect::<Vec<_>>();
+            for hook in plan.hooks.iter().filter(|hook| hook.phase == hooks::Phase::Post) {
+                if let Err(e) = hooks::run(hook, &client.installation.root, state.id, &changed) {
+                    let error = Error::Hook(hook.phase, hook.path.clone(), e);
+                    log::warn!("{error}");
+                }
+            }
+
+            Ok(SyncOutcome::Synced(Plan {
+                transfer,
+                mirrors,
+                invalid,
+                ..plan
+            }))
+        }
+    }
+}
+
+/// The effective boot management policy of the client, preferring its one-off override
+pub fn manage_policy(client: &Client, settings: &Settings) -> Manage {
+    client.boot_manage().or(settings.manage).unwrap_or_default()
+}
+
+/// Decide whether `backend` is managed for the client's installation, returning why
+/// not otherwise
+fn managed(client: &Client, settings: &Settings, backend: Backend) -> Result<(), String> {
+    manage::check(
+        manage_policy(client, settings),
+        is_native(&client.installation),
+        backend,
+        &manage::Environment::detect(),
+    )
+}
+
+/// The pre- and post-sync hooks of the installation, if enabled for it
+fn sync_hooks(install: &Installation, settings: &Settings) -> Result<Vec<hooks::Hook>, Error> {
+    if !is_native(install) && !settings.image_hooks.unwrap_or_default() {
+        return Ok(vec![]);
+    }
+
+    let mut hooks = hooks::discover(&install.root, hooks::Phase::Pre)?;
+    hooks.extend(hooks::discover(&install.root, hooks::Phase::Post)?);
+
+    Ok(hooks)
+}
+
+/// Classify a failure to probe the boot topology
+///
+/// Missing EFI support, devices or an unsupported partition layout are expected on
+/// some systems and result in a skip, whereas any other I/O failure (permissions,
+/// unreadable partition tables) is unexpected and propagated.
+fn classify(error: blsforme::Error) -> Result<SkipReason, Error> {
+    let mut unexpected = false;
+    let mut source: Option<&dyn std::error::Error> = Some(&error);
+
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            unexpected |= io.kind() != io::ErrorKind::NotFound;
+        }
+        source = e.source();
+    }
+
+    if unexpected {
+        Err(Error::Blsforme(error))
+    } else {
+        Ok(SkipReason::Topology(error.to_string()))
+    }
+}
+
+/// Plan the synchronization `pass` without mutating the system
+fn plan(
+    client: &Client,
+    pass: &Pass<'_>,
+    head_layouts: &[(Id, Layout)],
+    backend: Backend,
+    mode: Mode,
+) -> Result<Plan, Error> {
+    let (settings, states, kernels) = (&pass.settings, pass.states, &pass.kernels);
+    let install = &client.installation;
+    let manager = blsforme::Manager::new(&configuration(install)).ok();
+    let partitions = locate_partitions(install, manager.as_ref());
+    let arch = states.first().and_then(|state| architecture(&client.install_db, state));
+    let existing = partitions
+        .all()
+        .map(|tree| own_entries(tree, arch))
+        .flatten_ok()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut plan = Plan {
+        backend: Some(backend),
+        mode: (backend == Backend::SystemdBoot).then_some(mode),
+        ..Default::default()
+    };
+
+    if backend == Backend::SystemdBoot && is_native(install) {
+        if let Some(manager) = &manager {
+            let env = manager.boot_environment();
+            plan.mounts = env
+                .esp()
+                .into_iter()
+                .chain(env.xbootldr())
+                .map(|path| path.to_path_buf())
+                .collect();
+        }
+    }
+
+    let assets = boot_files_from_new_state(install, head_layouts, &settings.asset_patterns(backend)?);
+
+    if let (Backend::SystemdBoot, Some(esp)) = (backend, &partitions.esp) {
+        plan.loader = bootloader::plan(esp, &assets, settings.loader_update.unwrap_or_default())?;
+    }
+
+    match (backend, mode) {
+        (Backend::SystemdBoot, Mode::Entries) => {
+            plan.copies = assets
+                .into_iter()
+                .filter(|source| !bootloader::is_loader(source))
+                .map(|source| {
+                    Ok(plan::AssetCopy {
+                        size: copy::pending(&source, None)?,
+                        source,
+                        destination: None,
+                    })
+                })
+                .collect::<Result<_, io::Error>>()?;
+
+            for kernel in kernels {
+                let entry = existing
+                    .iter()
+                    .filter(|entry| !rescue::is_rescue(entry))
+                    .find(|entry| entry.matches(kernel.state.id, &kernel.tree.version));
+                let destinations = entry
+                    .map(|entry| entry.assets().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                let sources = [&kernel.image].into_iter().chain(kernel.initrds());
+
+                for (idx, source) in sources.enumerate() {
+                    let source = kernel.sysroot.join(source);
+                    let destination = entry
+                        .and_then(LoaderEntry::tree)
+                        .zip(destinations.get(idx))
+                        .map(|(tree, asset)| tree.join(asset));
+
+                    plan.copies.push(plan::AssetCopy {
+                        size: copy::pending(&source, destination.as_deref())?,
+                        source,
+                        destination,
+                    });
+                }
+
+                if let Some(tree) = entry.and_then(LoaderEntry::tree).or(partitions.kernels()) {
+                    let version = &kernel.tree.version;
+
+                    for job in dtb::jobs(tree, &kernel.sysroot, version, &kernel.dtbs, settings.board_dtb()) {
+                        plan.copies.push(plan::AssetCopy {
+                            size: copy::pending(&job.source, Some(&job.destination))?,
+                            source: job.source,
+                            destination: Some(job.destination),
+                        });
+                    }
+                }
+
+                plan.entries.push(plan::PlannedEntry {
+                    action: if entry.is_some() {
+                        plan::Action::Update
+                    } else {
+                        plan::Action::Create
+                    },
+                    state: kernel.state.id,
+                    version: kernel.tree.version.clone(),
+                    path: entry.map(|entry| entry.path.clone()),
+                });
+            }
+        }
+        (Backend::SystemdBoot, Mode::Uki) => {
+            let cmdlines = state_cmdlines(client, states, false)?;
+
+            for kernel in kernels {
+                let image = uki_image(&install.root, &cmdlines[&kernel.state.id], kernel, arch);
+                let path = partitions
+                    .kernels()
+                    .map(|tree| tree.join(uki::DIR).join(image.file_name()));
+                let exists = path.as_ref().is_some_and(|path| path.exists());
+
+                if !exists {
+                    // The assembled UKI embeds the kernel and all initrds
+                    let size = [&image.kernel]
+                        .into_iter()
+                        .chain(&image.initrds)
+                        .map(|asset| fs::metadata(asset).map(|meta| meta.len()))
+                        .sum::<io::Result<u64>>()?;
+
+                    plan.copies.push(plan::AssetCopy {
+                        source: image.kernel.clone(),
+                        destination: path.clone(),
+                        size,
+                    });
+                }
+
+                plan.entries.push(plan::PlannedEntry {
+                    action: if exists {
+                        plan::Action::Unchanged
+                    } else {
+                        plan::Action::Create
+                    },
+                    state: kernel.state.id,
+                    version: kernel.tree.version.clone(),
+                    path,
+                });
+            }
+        }
+        (Backend::Grub, _) => {
+            let snippet = install.root.join(grub::SNIPPET);
+            let current = fs::read_to_string(&snippet).unwrap_or_default();
+
+            for kernel in kernels {
+                let id = format!("'{}'", grub::entry_id(kernel.state.id, &kernel.tree.version));
+
+                plan.entries.push(plan::PlannedEntry {
+                    action: if current.contains(&id) {
+                        plan::Action::Update
+                    } else {
+                        plan::Action::Create
+                    },
+                    state: kernel.state.id,
+                    version: kernel.tree.version.clone(),
+                    path: Some(snippet.clone()),
+                });
+            }
+        }
+    }
+
+    if backend == Backend::SystemdBoot {
+        plan.entries.extend(
+            gc::stale(&existing, &known_states(client)?)
+                .into_iter()
+                .map(stale_to_planned),
+        );
+    }
+
+    plan.default = default_entry(client, pass, backend, mode, &existing);
+    plan.required = space::requirements(&plan, &partitions);
+
+    Ok(plan)
+}
+
+/// Ids of all states in the state db
+fn known_states(client: &Client) -> Result<BTreeSet<state::Id>, Error> {
+    Ok(client.state_db.list_ids()?.into_iter().map(|(id, _)| id).collect())
+}
+
+/// The planned deletion of a `stale` entry
+fn stale_to_planned(stale: gc::StaleEntry) -> plan::PlannedEntry {
+    plan::PlannedEntry {
+        action: plan::Action::Delete,
+        state: stale.state,
+        version: stale.version.unwrap_or_default(),
+        path: Some(stale.path),
+    }
+}
+
+/// Remove moss generated loader entries belonging to removed states or referencing
+/// missing assets, returning the (planned) deletions
+///
+/// Files owned by moss per the partition's [manifest](owner::Manifest) which are no
+/// longer in use, e.g. those of previous naming schemes, are removed as well.
+///
+/// Assets only referenced by removed entries are removed alongside them. Entries
+/// not generated by moss are never touched, nor is the entry of the running kernel.
+/// With `dry_run` nothing is removed. Skipped, as a sync would be, when boot
+/// management doesn't apply to the installation.
+pub fn gc(client: &Client, dry_run: bool) -> Result<SyncOutcome, Error> {
+    let settings = Settings::load(&client.config);
+
+    let backend = match settings.backend {
+        Some(backend) => backend,
+        None => {
+            let Some(id) = client.installation.active_state else {
+                return Ok(SyncOutcome::Skipped(SkipReason::NoBootloader));
+            };
+            let active = client.state_db.get(id)?;
+            match Backend::detect(&layouts_for_state(client, &active)?)? {
+                Some(backend) => backend,
+                None => return Ok(SyncOutcome::Skipped(SkipReason::NoBootloader)),
+            }
+        }
+    };
+
+    collect_garbage(client, &settings, backend, dry_run)
+}
+
+/// Collect the garbage of the boot partitions of `backend` as [`gc`] does, per the
+/// loaded `settings`
+fn collect_garbage(
+    client: &Client,
+    settings: &Settings,
+    backend: Backend,
+    dry_run: bool,
+) -> Result<SyncOutcome, Error> {
+    if let Err(reason) = managed(client, settings, backend) {
+        return Ok(SyncOutcome::Skipped(SkipReason::Unmanaged(reason)));
+    }
+
+    let known = known_states(client)?;
+    let booted = booted(&client.installation);
+    let arch = active_architecture(client);
+
+    let plan = with_partitions(&client.installation, settings, |partitions| {
+        let mut plan = Plan::default();
+
+        for tree in partitions.all() {
+            // States of other architectures sharing the partition are unknown to us
+            let entries = own_entries(tree, arch)?;
+            let stale = gc::stale(&entries, &known)
+                .into_iter()
+                .filter(|stale| {
+                    !entries
+                        .iter()
+                        .any(|entry| entry.path == stale.path && is_booted(booted.as_ref(), entry))
+                })
+                .collect::<Vec<_>>();
+            let paths = stale.iter().map(|entry| entry.path.clone()).collect::<BTreeSet<_>>();
+
+            if !dry_run && !paths.is_empty() {
+                remove_entries(tree, None, |entry| paths.contains(&entry.path))?;
+            }
+
+            plan.entries.extend(stale.into_iter().map(stale_to_planned));
+
+            // Files of previous naming schemes are only known to be ours via the manifest
+            let manifest = owner::Manifest::load_or_adopt(tree, os_id(&client.installation.root).as_deref())?;
+            let entries = entry::load_all(tree)?;
+            let orphaned = manifest
+                .orphaned(tree, &entries)
+                .into_iter()
+                .filter(|path| {
+                    !entries
+                        .iter()
+                        .any(|entry| entry.path == *path && is_booted(booted.as_ref(), entry))
+                })
+                .collect::<Vec<_>>();
+
+            if !dry_run {
+                for path in &orphaned {
+                    fs::remove_file(path)?;
+                    if let Some(parent) = path.parent() {
+                        remove_empty_dirs(parent, tree)?;
+                    }
+                }
+                manifest.save(tree)?;
+            }
+
+            plan.orphaned.extend(orphaned);
+        }
+
+        // Mirrors are reconciled with the primary partitions, rather than collected on their own
+        if !dry_run && (!plan.entries.is_empty() || !plan.orphaned.is_empty()) {
+            plan.mirrors = synchronize_mirrors(&client.installation, settings, partitions);
+        }
+
+        Ok(plan)
+    })?;
+
+    Ok(if dry_run {
+        SyncOutcome::Planned(plan)
+    } else {
+        SyncOutcome::Synced(plan)
+    })
+}
+
+/// Id of the entry the bootloader should default to, if managed by moss
+///
+/// This is the newest kernel of the head state, i.e. the newly created or rolled
+/// back to state. `existing` entries are only used for BLS type #1 entries, whose
+/// file names are chosen by blsforme.
+fn default_entry(
+    client: &Client,
+    pass: &Pass<'_>,
+    backend: Backend,
+    mode: Mode,
+    existing: &[LoaderEntry],
+) -> Option<String> {
+    let head = pass.states.first()?;
+
+    if pass.settings.set_default.unwrap_or_default() == DefaultEntry::Keep {
+        return None;
+    }
+
+    let newest = pass.kernels.iter().find(|kernel| kernel.state.id == head.id);
+    let arch = architecture(&client.install_db, head);
+    let head = head.id;
+
+    match (backend, mode) {
+        (Backend::SystemdBoot, Mode::Entries) => existing
+            .iter()
+            .filter(|entry| entry.state_id() == Some(head) && !rescue::is_rescue(entry))
+            .max_by(|a, b| a.version.cmp(&b.version))
+            .and_then(|entry| entry.path.file_name())
+            .map(|name| name.to_string_lossy().into_owned()),
+        (Backend::SystemdBoot, Mode::Uki) => newest.map(|kernel| uki::file_name(head, &kernel.tree.version, arch)),
+        (Backend::Grub, _) => newest.map(|kernel| grub::entry_id(head, &kernel.tree.version)),
+    }
+}
+
+/// Synchronize BLS entries and bootloader assets to the ESP via blsforme
+fn synchronize_systemd_boot(
+    client: &Client,
+    pass: &Pass<'_>,
+    head_layouts: &[(Id, Layout)],
+    transfer: &mut copy::Stats,
+    invalid: &mut Vec<validate::Invalid>,
+) -> Result<Option<SkipReason>, Error> {
+    let (settings, states, kernels) = (&pass.settings, pass.states, &pass.kernels);
+    let state = &states[0];
+    let root = client.installation.root.clone();
+    let is_native = is_native(&client.installation);
+    // Create an appropriate configuration
+    let config = configuration(&client.installation);
+
+    // For the new/active state
+    let kernel_patterns = settings.kernel_patterns()?;
+    let asset_patterns = settings.asset_patterns(Backend::SystemdBoot)?;
+    let booty_bits = boot_files_from_new_state(&client.installation, head_layouts, &asset_patterns);
+
+    if booty_bits.is_empty() {
+        return Ok(Some(SkipReason::NoBootloader));
+    }
+
+    let os_release = read_os_release(&root, settings.strict_os_release.unwrap_or_default())?;
+    let schema = Schema::Blsforme {
+        os_release: &os_release,
+    };
+
+    let arch = architecture(&client.install_db, state);
+
+    // Grab the entries for every state
+    let mut all_kernels = vec![];
+    for state in states.iter() {
+        let layouts = layouts_for_state(client, state)?;
+        // Incomplete kernels are never handed to blsforme
+        let local_kernels = kernel_files_from_state(&layouts, &kernel_patterns)
+            .into_iter()
+            .filter(|candidate| {
+                kernels
+                    .iter()
+                    .any(|kernel| kernel.state.id == state.id && kernel.tree.version == candidate.version)
+            });
+        let mapped = schema.discover_system_kernels(local_kernels)?;
+        all_kernels.push((mapped, state.id));
+    }
+
+    // Assemble the full cmdline of each state up front, rather than leaving snippet
+    // ordering and duplicates to blsforme
+    let cmdlines = state_cmdlines(client, states, true)?
+        .into_iter()
+        .map(|(id, fragments)| {
+            let sysroot = if id == state.id {
+                root.clone()
+            } else {
+                client.installation.root_path(id.to_string())
+            };
+            let (assembled, overrides) = cmdline::assemble(
+                cmdline::system(&sysroot)
+                    .into_iter()
+                    .chain(fragments)
+                    .chain([cmdline::state_fragment(id)]),
+            );
+
+            for r#override in overrides {
+                log::warn!("Kernel command line of state {id}: {override}");
+            }
+
+            (id, assembled)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    // pipe all of our entries into blsforme
+    let entries = all_kernels
+        .iter()
+        .flat_map(|(kernels, state_id)| {
+            kernels
+                .iter()
+                .filter_map(|k| {
+                    let sysroot = if state.id == *state_id {
+                        root.clone()
+                    } else {
+                        client.installation.root_path(state_id.to_string()).to_owned()
+                    };
+
+                    if !sysroot.exists() {
+                        return None;
+                    }
+
+                    let entry = Entry::new(k).with_state_id(i32::from(*state_id)).with_sysroot(sysroot);
+
+                    Some(cmdlines[state_id].iter().fold(entry, |entry, fragment| {
+                        entry.with_cmdline(CmdlineEntry {
+                            name: fragment.name.clone(),
+                            snippet: fragment.snippet.clone(),
+                        })
+                    }))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // no usable entries, lets get out of here.
+    if entries.is_empty() {
+        return Ok(Some(SkipReason::NoKernels));
+    }
+
+    let microcode = kernels
+        .iter()
+        .filter(|kernel| !kernel.microcode.is_empty())
+        .collect::<Vec<_>>();
+
+    // The loader itself is installed by moss, so it is only replaced when outdated
+    let (loaders, booty_bits): (Vec<_>, Vec<_>) =
+        booty_bits.into_iter().partition(|asset| bootloader::is_loader(asset));
+
+    let _image_esp = prepare_image_esp(&client.installation, settings)?;
+    let manager = match blsforme::Manager::new(&config) {
+        Ok(m) => m.with_entries(entries.into_iter()).with_bootloader_assets(booty_bits),
+        Err(e) => return classify(e).map(Some),
+    };
+
+    // Only allow mounting pre-sync for a native run
+    let _mounts = if is_native {
+        match manager.mount_partitions() {
+            Ok(mounts) => Some(mounts),
+            Err(e) => {
+                reuse_mounts(&root, e)?;
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let _writable = writable(is_native, &locate_partitions(&client.installation, Some(&manager)))?;
+    manager.sync(&schema)?;
+
+    let partitions = locate_partitions(&client.installation, Some(&manager));
+    namespace_entries(arch, &partitions)?;
+    *transfer = *transfer + update_loader(settings, &partitions, &loaders)?;
+    *transfer = *transfer + attach_microcode(&microcode, arch, &partitions)?;
+    *transfer = *transfer + attach_devicetrees(settings.board_dtb(), kernels, arch, &partitions)?;
+    // Signed kernels intentionally differ from their packaged source
+    if settings.signing.is_none() {
+        *transfer = *transfer + verify_kernels(kernels, arch, &partitions)?;
+    }
+    retitle(settings, &root, states, arch, &partitions)?;
+    write_rescue(
+        settings.rescue_entry.unwrap_or_default(),
+        state.id,
+        kernels,
+        arch,
+        &partitions,
+    )?;
+    if let Some(signing) = &settings.signing {
+        sign_partitions(signing, arch, &partitions)?;
+    }
+
+    // Entry file names are only known once blsforme has written them
+    let written = partitions
+        .all()
+        .map(|tree| own_entries(tree, arch))
+        .flatten_ok()
+        .collect::<Result<Vec<_>, _>>()?;
+    if let (Some(esp), Some(default)) = (
+        &partitions.esp,
+        default_entry(client, pass, Backend::SystemdBoot, Mode::Entries, &written),
+    ) {
+        loader::set_default(esp, &default)?;
+    }
+
+    // Read everything back, as systemd-boot silently skips malformed entries
+    let expected = |id| {
+        cmdlines.get(&id).map(|fragments: &Vec<cmdline::Fragment>| {
+            fragments
+                .iter()
+                .flat_map(|fragment| fragment.snippet.split_whitespace().map(str::to_owned))
+                .collect()
+        })
+    };
+    for tree in partitions.all() {
+        for entry in validate::entries(tree, arch, expected)? {
+            log::warn!("Invalid boot entry {entry}");
+            invalid.push(entry);
+        }
+    }
+
+    record_ownership(&root, arch, &partitions)?;
+
+    Ok(None)
+}
+
+/// Record all moss generated entries, kernel assets and UKIs of `arch` in the manifest
+/// of each boot partition, adopting legacy files of partitions without one
+fn record_ownership(root: &Path, arch: Option<arch::Arch>, partitions: &esp::Partitions) -> Result<(), Error> {
+    let os_id = os_id(root);
+
+    for tree in partitions.all() {
+        let mut manifest = owner::Manifest::load_or_adopt(tree, os_id.as_deref())?;
+
+        for entry in own_entries(tree, arch)?
+            .into_iter()
+            .filter(|entry| entry.state_id().is_some())
+        {
+            if let Some(file) = owner::relative(tree, &entry.path) {
+                manifest.record(file, owner::Kind::Entry);
+            }
+            for asset in entry.assets().filter(|asset| !asset.starts_with("EFI/moss/")) {
+                manifest.record(asset, owner::Kind::Asset);
+            }
+        }
+
+        let ukis = tree.join(uki::DIR);
+        if ukis.is_dir() {
+            for file in fs::read_dir(&ukis)? {
+                let path = file?.path();
+                let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+                let owned = arch.map_or(true, |arch| {
+                    arch::Arch::of_file_name(name).map_or(true, |other| other == arch)
+                });
+
+                if owned && uki::state_id(name).is_some() {
+                    manifest.record(format!("{}/{name}", uki::DIR), owner::Kind::Uki);
+                }
+            }
+        }
+
+        manifest.save(tree)?;
+    }
+
+    Ok(())
+}
+
+/// Namespace the moss generated entries written by blsforme with `arch`, moving their
+/// kernel assets into per-architecture directories
+///
+/// This also migrates entries predating namespacing.
+fn namespace_entries(arch: Option<arch::Arch>, partitions: &esp::Partitions) -> Result<(), Error> {
+    let Some(arch) = arch else {
+        return Ok(());
+    };
+
+    for tree in partitions.all() {
+        for entry in own_entries(tree, Some(arch))?
+            .into_iter()
+            .filter(|entry| entry.state_id().is_some() && !rescue::is_rescue(entry))
+        {
+            let contents = fs::read_to_string(&entry.path)?;
+            let (namespaced, moves) = arch.namespace(&contents);
+
+            // Assets shared by multiple entries are moved by the first one
+            for (from, to) in moves {
+                let (from, to) = (tree.join(from), tree.join(to));
+                if from.exists() {
+                    if let Some(parent) = to.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::rename(&from, &to)?;
+                }
+            }
+
+            let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+            let path = entry.path.with_file_name(arch.file_name(&name));
+            if path != entry.path || namespaced != contents {
+                fs::write(&path, namespaced)?;
+            }
+            if path != entry.path {
+                fs::remove_file(&entry.path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Install the packaged `loaders` to the ESP, replacing outdated ones per the configured policy
+fn update_loader(settings: &Settings, partitions: &esp::Partitions, loaders: &[PathBuf]) -> Result<copy::Stats, Error> {
+    let Some(esp) = &partitions.esp else {
+        return Ok(copy::Stats::default());
+    };
+
+    let updates = bootloader::plan(esp, loaders, settings.loader_update.unwrap_or_default())?;
+
+    Ok(bootloader::apply(esp, &updates)?)
+}
+
+/// Verify the kernel & initrd assets written by blsforme against their source,
+/// rewriting any that were corrupted on the boot partitions
+///
+/// Assets are matched to their source by file name.
+fn verify_kernels(
+    kernels: &[StateKernel<'_>],
+    arch: Option<arch::Arch>,
+    partitions: &esp::Partitions,
+) -> Result<copy::Stats, Error> {
+    let mut jobs = vec![];
+
+    for tree in partitions.all() {
+        for entry in own_entries(tree, arch)? {
+            let Some(kernel) = kernels
+                .iter()
+                .find(|kernel| entry.matches(kernel.state.id, &kernel.tree.version))
+            else {
+                continue;
+            };
+
+            let sources = [&kernel.image]
+                .into_iter()
+                .chain(&kernel.tree.initrds)
+                .map(|source| kernel.sysroot.join(source))
+                .collect::<Vec<_>>();
+
+            for asset in entry.assets() {
+                let destination = tree.join(asset);
+                if let Some(source) = sources
+                    .iter()
+                    .find(|source| source.file_name() == destination.file_name())
+                {
+                    jobs.push(copy::Job {
+                        source: source.clone(),
+                        destination,
+                    });
+                }
+            }
+        }
+    }
+
+    jobs.sort_by(|a, b| a.destination.cmp(&b.destination));
+    jobs.dedup_by(|a, b| a.destination == b.destination);
+
+    Ok(copy::install(&jobs)?)
+}
+
+/// Sign the bootloader and every moss managed kernel image on the boot partitions
+fn sign_partitions(
+    signing: &sign::Signing,
+    arch: Option<arch::Arch>,
+    partitions: &esp::Partitions,
+) -> Result<(), Error> {
+    for tree in partitions.all() {
+        for binary in sign::binaries(tree, &own_entries(tree, arch)?)? {
+            sign_binary(signing, &binary)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sign the installed `binary` in place, unless already signed
+fn sign_binary(signing: &sign::Signing, binary: &Path) -> Result<(), Error> {
+    if signing
+        .sign(binary)
+        .map_err(|e| Error::Signing(binary.to_path_buf(), e))?
+    {
+        log::info!("Signed {}", binary.display());
+    }
+
+    Ok(())
+}
+
+/// Title every moss generated loader entry on the boot partitions with the configured
+/// template
+///
+/// Sort keys are left as written by blsforme, which already orders entries by state.
+fn retitle(
+    settings: &Settings,
+    root: &Path,
+    states: &[State],
+    arch: Option<arch::Arch>,
+    partitions: &esp::Partitions,
+) -> Result<(), Error> {
+    let template = settings.title.as_deref().unwrap_or(title::DEFAULT_TEMPLATE);
+    let os = grub::os_name(root);
+
+    for tree in partitions.all() {
+        for entry in own_entries(tree, arch)?
+            .into_iter()
+            .filter(|entry| !rescue::is_rescue(entry))
+        {
+            let Some(state) = entry
+                .state_id()
+                .and_then(|id| states.iter().find(|state| state.id == id))
+            else {
+                continue;
+            };
+            let Some(version) = &entry.version else {
+                continue;
+            };
+
+            let contents = fs::read_to_string(&entry.path)?;
+            let titled = title::with_title(&contents, &title::render(template, &os, version, state));
+            if titled != contents {
+                fs::write(&entry.path, titled)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Regenerate the rescue entry of `arch` from the entry of the `head` state's newest
+/// kernel, or remove it when not `enabled`
+fn write_rescue(
+    enabled: bool,
+    head: state::Id,
+    kernels: &[StateKernel<'_>],
+    arch: Option<arch::Arch>,
+    partitions: &esp::Partitions,
+) -> Result<(), Error> {
+    let newest = kernels
+        .iter()
+        .find(|kernel| kernel.state.id == head)
+        .filter(|_| enabled);
+
+    for tree in partitions.all() {
+        let dir = tree.join("loader").join("entries");
+        let path = dir.join(rescue::file_name(arch));
+        let entries = own_entries(tree, arch)?;
+
+        // The rescue entry predating architecture namespacing
+        let legacy = dir.join(rescue::FILE_NAME);
+        if legacy != path && legacy.exists() {
+            fs::remove_file(&legacy)?;
+        }
+
+        let source = newest.and_then(|kernel| {
+            entries
+                .iter()
+                .filter(|entry| !rescue::is_rescue(entry))
+                .find(|entry| entry.matches(head, &kernel.tree.version))
+        });
+
+        match source {
+            Some(source) => {
+                let contents = rescue::render(&fs::read_to_string(&source.path)?);
+                if fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+                    fs::write(&path, contents)?;
+                }
+            }
+            None if path.exists() => fs::remove_file(&path)?,
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Install each state's microcode alongside its loader entries, loading it ahead of
+/// the existing initrds
+fn attach_microcode(
+    kernels: &[&StateKernel<'_>],
+    arch: Option<arch::Arch>,
+    partitions: &esp::Partitions,
+) -> Result<copy::Stats, Error> {
+    let mut transfer = copy::Stats::default();
+
+    for tree in partitions.all() {
+        let mut jobs = vec![];
+        let mut injections = vec![];
+
+        for entry in own_entries(tree, arch)? {
+            let Some(kernel) = entry
+                .state_id()
+                .and_then(|id| kernels.iter().find(|k| k.state.id == id))
+            else {
+                continue;
+            };
+
+            jobs.extend(
+                kernel
+                    .microcode
+                    .iter()
+                    .map(|image| microcode::job(tree, &kernel.sysroot, image)),
+            );
+            injections.push((
+                entry,
+                kernel.microcode.iter().map(|image| image.asset()).collect::<Vec<_>>(),
+            ));
+        }
+
+        jobs.sort_by(|a, b| a.destination.cmp(&b.destination));
+        jobs.dedup_by(|a, b| a.destination == b.destination);
+        transfer = transfer + copy::install(&jobs)?;
+
+        // Only reference the microcode once it's safely installed
+        for (entry, assets) in injections {
+            let contents = fs::read_to_string(&entry.path)?;
+            let injected = microcode::inject(&contents, &assets);
+            if injected != contents {
+                fs::write(&entry.path, injected)?;
+            }
+        }
+    }
+
+    Ok(transfer)
+}
+
+/// Install the dtbs of all `kernels` next to their entries, pointing each entry at
+/// the `board` dtb when configured
+fn attach_devicetrees(
+    board: Option<&Path>,
+    kernels: &[StateKernel<'_>],
+    arch: Option<arch::Arch>,
+    partitions: &esp::Partitions,
+) -> Result<copy::Stats, Error> {
+    let mut transfer = copy::Stats::default();
+
+    for tree in partitions.all() {
+        let mut jobs = vec![];
+        let mut injections = vec![];
+
+        for entry in own_entries(tree, arch)? {
+            let Some(kernel) = kernels
+                .iter()
+                .filter(|kernel| !kernel.dtbs.is_empty())
+                .find(|kernel| entry.matches(kernel.state.id, &kernel.tree.version))
+            else {
+                continue;
+            };
+            let version = &kernel.tree.version;
+
+            jobs.extend(dtb::jobs(tree, &kernel.sysroot, version, &kernel.dtbs, board));
+
+            match (dtb::select(&kernel.dtbs, board), board) {
+                (Some(dtb), _) => injections.push((entry, dtb.asset(version))),
+                (None, Some(board)) => log::warn!(
+                    "Kernel {version} of state {} doesn't ship the configured dtb {}",
+                    kernel.state.id,
+                    board.display(),
+                ),
+                (None, None) => {}
+            }
+        }
+
+        jobs.sort_by(|a, b| a.destination.cmp(&b.destination));
+        jobs.dedup_by(|a, b| a.destination == b.destination);
+        transfer = transfer + copy::install(&jobs)?;
+
+        // Only reference the dtb once it's safely installed
+        for (entry, asset) in injections {
+            let contents = fs::read_to_string(&entry.path)?;
+            let injected = dtb::inject(&contents, &asset);
+            if injected != contents {
+                fs::write(&entry.path, injected)?;
+            }
+        }
+    }
+
+    Ok(transfer)
+}
+
+/// A kernel within a state, alongside the root the state lives in
+struct StateKernel<'a> {
+    state: &'a State,
+    sysroot: PathBuf,
+    tree: kernel::KernelTree,
+    /// Kernel image, relative to the sysroot
+    image: PathBuf,
+    /// Microcode images of the state, loaded ahead of the initrds
+    microcode: Vec<microcode::Microcode>,
+    /// Device-tree blobs shipped with the kernel
+    dtbs: Vec<dtb::Dtb>,
+}
+
+impl StateKernel<'_> {
+    /// All initrds relative to the sysroot, in load order
+    fn initrds(&self) -> impl Iterator<Item = &PathBuf> {
+        self.microcode.iter().map(|image| &image.path).chain(&self.tree.initrds)
+    }
+}
+
+/// The settings and kernels of a single synchronization pass over `states`, resolved
+/// once up front
+struct Pass<'a> {
+    settings: Settings,
+    /// States to synchronize, the head state first
+    states: &'a [State],
+    /// Bootable kernels of all states, newest version first
+    kernels: Vec<StateKernel<'a>>,
+}
+
+/// Discover the kernels of all `states`, split into bootable kernels (newest version
+/// first) and diagnoses of those missing a required component
+///
+/// The first state lives in the installation root, whereas all other states are
+/// resolved via their archived root. States without a root are skipped.
+fn discover_kernels<'a>(
+    client: &Client,
+    settings: &Settings,
+    states: &'a [State],
+) -> Result<(Vec<StateKernel<'a>>, Vec<kernel::Diagnosis>), Error> {
+    let install = &client.installation;
+    let kernel_patterns = settings.kernel_patterns()?;
+    let microcode_patterns = settings.microcode_patterns()?;
+
+    let mut kernels = vec![];
+    let mut incomplete = vec![];
+
+    for (idx, state) in states.iter().enumerate() {
+        let sysroot = if idx == 0 {
+            install.root.clone()
+        } else {
+            install.root_path(state.id.to_string())
+        };
+
+        if !sysroot.exists() {
+            continue;
+        }
+
+        let layouts = layouts_for_state(client, state)?;
+        let candidates = kernel_files_from_state(&layouts, &kernel_patterns);
+        let microcode = microcode::from_layouts(&layouts, &microcode_patterns);
+
+        for tree in kernel::trees(&candidates).into_iter().rev() {
+            let diagnosis = kernel::diagnose(state.id, &sysroot, &tree, &candidates);
+            let (Some(image), true) = (tree.image.clone(), diagnosis.is_bootable()) else {
+                incomplete.push(diagnosis);
+                continue;
+            };
+            if !diagnosis.missing.is_empty() {
+                log::debug!("Optional components missing: {diagnosis}");
+            }
+
+            kernels.push(StateKernel {
+                state,
+                sysroot: sysroot.clone(),
+                image,
+                microcode: microcode.clone(),
+                dtbs: dtb::from_layouts(&layouts, &tree.version),
+                tree,
+            });
+        }
+    }
+
+    Ok((kernels, incomplete))
+}
+
+/// Assemble a UKI for every kernel of each state into the ESP
+fn synchronize_uki(
+    client: &Client,
+    pass: &Pass<'_>,
+    head_layouts: &[(Id, Layout)],
+    transfer: &mut copy::Stats,
+) -> Result<Option<SkipReason>, Error> {
+    let (settings, states) = (&pass.settings, pass.states);
+    let install = &client.installation;
+    let stub_pattern = [Pattern::from_str(uki::STUB_PATTERN)?];
+
+    let stub = backend::assets(head_layouts, &stub_pattern)
+        .first()
+        .map(|stub| install.root.join("usr").join(stub))
+        .ok_or(Error::MissingStub)?;
+    let ukify = uki::ukify(&install.root).ok_or(Error::MissingUkify)?;
+    let loaders = boot_files_from_new_state(install, head_layouts, &settings.asset_patterns(Backend::SystemdBoot)?);
+    let os_release = match os_release_path(&install.root) {
+        Some(path) => Some(path),
+        None => {
+            // Only warns, ukify embeds its own defaults
+            os_release_contents(&install.root, settings.strict_os_release.unwrap_or_default())?;
+            None
+        }
+    };
+    let cmdlines = state_cmdlines(client, states, true)?;
+    let arch = architecture(&client.install_db, &states[0]);
+
+    let images = pass
+        .kernels
+        .iter()
+        .map(|kernel| uki_image(&install.root, &cmdlines[&kernel.state.id], kernel, arch))
+        .collect::<Vec<_>>();
+    let default = default_entry(client, pass, Backend::SystemdBoot, Mode::Uki, &[]);
+
+    if images.is_empty() {
+        return Ok(Some(SkipReason::NoKernels));
+    }
+
+    with_partitions(install, settings, |partitions| {
+        let Some(tree) = partitions.kernels() else {
+            return Ok(Some(SkipReason::Topology("no mounted ESP found".to_owned())));
+        };
+
+        *transfer = *transfer + update_loader(settings, partitions, &loaders)?;
+
+        for image in &images {
+            let output = uki::build(&ukify, &stub, os_release.as_deref(), image, tree)?;
+            if let Some(signing) = &settings.signing {
+                sign_binary(signing, &output)?;
+            }
+        }
+        if let Some(signing) = &settings.signing {
+            sign_partitions(signing, arch, partitions)?;
+        }
+
+        if let (Some(esp), Some(default)) = (&partitions.esp, &default) {
+            loader::set_default(esp, default)?;
+        }
+
+        record_ownership(&install.root, arch, partitions)?;
+
+        Ok(None)
+    })
+}
+
+/// The UKI to assemble for `kernel`
+fn uki_image(
+    root: &Path,
+    fragments: &[cmdline::Fragment],
+    kernel: &StateKernel<'_>,
+    arch: Option<arch::Arch>,
+) -> uki::Image {
+    uki::Image {
+        state: kernel.state.id,
+        version: kernel.tree.version.clone(),
+        kernel: kernel.sysroot.join(&kernel.image),
+        initrds: kernel.initrds().map(|initrd| kernel.sysroot.join(initrd)).collect(),
+        cmdline: uki::cmdline(root, fragments, kernel.state.id),
+        arch,
+    }
+}
+
+/// Emit GRUB menu entries for every kernel of each state
+fn synchronize_grub(client: &Client, pass: &Pass<'_>) -> Result<Option<SkipReason>, Error> {
+    let install = &client.installation;
+    let entries = grub_entries(client, pass)?;
+
+    if entries.is_empty() {
+        return Ok(Some(SkipReason::NoKernels));
+    }
+
+    let default = default_entry(client, pass, Backend::Grub, Mode::default(), &[]);
+
+    grub::synchronize(install, &entries, default.as_deref())?;
+
+    Ok(None)
+}
+
+/// The GRUB menu entries for every kernel of each state
+fn grub_entries(client: &Client, pass: &Pass<'_>) -> Result<Vec<grub::MenuEntry>, Error> {
+    let install = &client.installation;
+    let os_name = grub::os_name(&install.root);
+    let template = pass
+        .settings
+        .title
+        .clone()
+        .unwrap_or_else(|| title::DEFAULT_TEMPLATE.to_owned());
+    let cmdlines = state_cmdlines(client, pass.states, true)?;
+
+    Ok(pass
+        .kernels
+        .iter()
+        .map(|kernel| {
+            // Paths as seen from the booted system, i.e. relative to the installation root
+            let system = Path::new("/").join(kernel.sysroot.strip_prefix(&install.root).unwrap_or(&kernel.sysroot));
+            let state = kernel.state.id;
+
+            grub::MenuEntry {
+                title: title::render(&template, &os_name, &kernel.tree.version, kernel.state),
+                id: grub::entry_id(state, &kernel.tree.version),
+                kernel: system.join(&kernel.image),
+                initrds: kernel.initrds().map(|initrd| system.join(initrd)).collect(),
+                cmdline: format!(
+                    "{} {}={state}",
+                    cmdline::join(&cmdlines[&state]),
+                    entry::STATE_PARAMETER
+                )
+                .trim_start()
+                .to_owned(),
+            }
+        })
+        .collect())
+}
+
+/// Boot entries and assets removed by [`cleanup`]
+#[derive(Debug, Default)]
+pub struct Cleanup {
+    /// Removed loader entry files
+    pub entries: Vec<PathBuf>,
+    /// Removed kernel & initrd assets, including UKIs
+    pub assets: Vec<PathBuf>,
+    /// Mirror ESPs reconciled after the cleanup
+    pub mirrors: Vec<mirror::Outcome>,
+}
+
+/// Remove the loader entries and ESP assets belonging to the `removed` states
+///
+/// Assets are reference counted across all remaining loader entries of the same
+/// partition, so a kernel shared with a retained state is never removed. Both the
+/// ESP and XBOOTLDR are cleaned. A missing or unmounted ESP is skipped with a warning.
+///
+/// On native roots the entry of the running kernel is kept, even if its state is removed.
+/// Only entries & UKIs of `arch` are removed, as other architectures sharing the
+/// partitions have states of their own. Nothing is removed when boot management
+/// doesn't apply to the installation per the client's [policy](manage_policy).
+pub fn cleanup(client: &Client, removed: &[state::Id], arch: Option<arch::Arch>) -> Result<Cleanup, Error> {
+    if removed.is_empty() {
+        return Ok(Cleanup::default());
+    }
+
+    let install = &client.installation;
+    let settings = Settings::load(&client.config);
+    if let Err(reason) = managed(client, &settings, Backend::SystemdBoot) {
+        log::info!("Skipped boot entry cleanup: {reason}");
+        return Ok(Cleanup::default());
+    }
+
+    let removed = removed.iter().copied().collect();
+    let booted = booted(install);
+
+    with_partitions(install, &settings, |partitions| {
+        if partitions.esp.is_none() {
+            log::warn!("No mounted ESP found, skipping boot entry cleanup");
+        }
+
+        let mut cleanup = Cleanup::default();
+        for tree in partitions.all() {
+            let Cleanup { entries, assets, .. } = cleanup_esp(tree, arch, &removed, booted.as_ref())?;
+            cleanup.entries.extend(entries);
+            cleanup.assets.extend(assets);
+        }
+
+        if !cleanup.entries.is_empty() || !cleanup.assets.is_empty() {
+            cleanup.mirrors = synchronize_mirrors(install, &settings, partitions);
+        }
+
+        Ok(cleanup)
+    })
+}
+
+/// Run `f` against the located boot partition trees
+///
+/// For native runs the boot partitions are mounted for the duration of `f`,
+/// tolerating topology failures. Image builds use the configured image ESP.
+fn with_partitions<T>(
+    install: &Installation,
+    settings: &Settings,
+    f: impl FnOnce(&esp::Partitions) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let _image_esp = prepare_image_esp(install, settings)?;
+    let config = configuration(install);
+    let manager = match blsforme::Manager::new(&config) {
+        Ok(manager) => Some(manager),
+        Err(e) => {
+            log::warn!("Unable to probe boot partitions: {}", classify(e)?);
+            None
+        }
+    };
+    let _mounts = match &manager {
+        Some(manager) if is_native(install) => match manager.mount_partitions() {
+            Ok(mounts) => Some(mounts),
+            Err(e) => {
+                reuse_mounts(&install.root, e)?;
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let partitions = locate_partitions(install, manager.as_ref());
+    let _writable = writable(is_native(install), &partitions)?;
+
+    f(&partitions)
+}
+
+/// Tolerate a failure to mount the boot partitions if they're already mounted
+/// elsewhere, e.g. by a systemd automount, and the ESP can be located beneath `root`
+fn reuse_mounts(root: &Path, error: blsforme::Error) -> Result<(), Error> {
+    match esp::locate(root) {
+        Some(esp) if mount::is_already_mounted(&error) => {
+            log::info!("Using boot partitions already mounted at {}", esp.display());
+            Ok(())
+        }
+        _ => Err(error.into()),
+    }
+}
+
+/// Remount the read-only boot `partitions` of a native run read-write, restoring
+/// them once the returned guard is dropped
+fn writable(is_native: bool, partitions: &esp::Partitions) -> Result<Option<mount::Writable<'static>>, Error> {
+    if !is_native {
+        return Ok(None);
+    }
+
+    mount::writable(&mount::System, partitions.all())
+        .map(Some)
+        .map_err(|(partition, e)| Error::Remount(partition, e))
+}
+
+/// Reconcile every configured mirror ESP with the primary boot `partitions`
+///
+/// Failing mirrors are reported as warnings, so they never fail the transaction.
+fn synchronize_mirrors(
+    install: &Installation,
+    settings: &Settings,
+    partitions: &esp::Partitions,
+) -> Vec<mirror::Outcome> {
+    let Some(targets) = settings.mirrors.as_deref().filter(|targets| !targets.is_empty()) else {
+        return vec![];
+    };
+    // Reconciling against nothing would wipe the mirrors
+    if partitions.esp.is_none() {
+        log::warn!("No mounted ESP found, skipping synchronization of mirror ESPs");
+        return vec![];
+    }
+
+    targets
+        .iter()
+        .map(|target| {
+            let mut outcome = mirror::Outcome {
+                target: target.to_string(),
+                ..Default::default()
+            };
+
+            let result = mirror::open(&install.root, target)
+                .map_err(copy::Failure::from)
+                .and_then(|mirror| {
+                    outcome.path = Some(mirror.path.clone());
+                    let _writable = if is_native(install) {
+                        Some(mount::writable(&mount::System, [mirror.path.as_path()]).map_err(|(_, e)| e)?)
+                    } else {
+                        None
+                    };
+
+                    mirror::reconcile(partitions.all(), &mirror.path)
+                });
+
+            match result {
+                Ok((transfer, removed)) => {
+                    outcome.transfer = transfer;
+                    outcome.removed = removed;
+                }
+                Err(e) => {
+                    log::warn!("Failed to synchronize mirror ESP {target}: {e}");
+                    outcome.error = Some(e.to_string());
+                }
+            }
+
+            outcome
+        })
+        .collect()
+}
+
+/// Mount (or locate a pre-mounted) ESP for an image build, kept mounted until dropped
+///
+/// Native runs mount their partitions via blsforme instead.
+fn prepare_image_esp(install: &Installation, settings: &Settings) -> Result<Option<esp::ImageEsp>, Error> {
+    if is_native(install) {
+        return Ok(None);
+    }
+
+    Ok(esp::prepare_image(&install.root, settings.esp.as_deref())?)
+}
+
+/// Locate the mounted boot partition trees, with XBOOTLDR only considered when the
+/// `manager` probed one
+fn locate_partitions(install: &Installation, manager: Option<&blsforme::Manager<'_>>) -> esp::Partitions {
+    let probed = manager.is_some_and(|manager| manager.boot_environment().xbootldr().is_some());
+
+    esp::Partitions::locate(&install.root, probed)
+}
+
+/// Remove loader entries and UKIs for the `removed` states from the `esp` tree, along
+/// with any assets no longer referenced by a remaining entry
+///
+/// Entries and UKIs of the `booted` state's running kernel are kept, as are those of
+/// architectures other than `arch`.
+fn cleanup_esp(
+    esp: &Path,
+    arch: Option<arch::Arch>,
+    removed: &BTreeSet<state::Id>,
+    booted: Option<&running::Booted>,
+) -> Result<Cleanup, Error> {
+    let mut cleanup = remove_entries(esp, booted, |entry| {
+        arch.map_or(true, |arch| arch.owns(entry)) && entry.state_id().is_some_and(|id| removed.contains(&id))
+    })?;
+    // UKIs are protected by state, as they embed their kernel
+    let booted_state = booted.and_then(|booted| booted.state);
+    cleanup.assets.extend(uki::remove(esp, arch, |id| {
+        removed.contains(&id) && booted_state != Some(id)
+    })?);
+
+    Ok(cleanup)
+}
+
+/// Remove all loader entries of the `esp` tree matching `is_stale`, along with any
+/// assets no longer referenced by a remaining entry
+///
+/// The entry of the `booted` kernel is always kept.
+fn remove_entries(
+    esp: &Path,
+    booted: Option<&running::Booted>,
+    is_stale: impl Fn(&LoaderEntry) -> bool,
+) -> Result<Cleanup, Error> {
+    let (stale, retained): (Vec<_>, Vec<_>) = entry::load_all(esp)?
+        .into_iter()
+        .partition(|entry| is_stale(entry) && !is_booted(booted, entry));
+
+    let referenced = retained
+        .iter()
+        .flat_map(|entry| entry.assets())
+        .collect::<BTreeSet<_>>();
+    let unreferenced = stale
+        .iter()
+        .flat_map(|entry| entry.assets())
+        .filter(|asset| !referenced.contains(asset))
+        .collect::<BTreeSet<_>>();
+
+    let mut cleanup = Cleanup::default();
+
+    for entry in &stale {
+        fs::remove_file(&entry.path)?;
+        cleanup.entries.push(entry.path.clone());
+    }
+
+    for asset in unreferenced {
+        let path = esp.join(asset);
+
+        if path.exists() {
+            fs::remove_file(&path)?;
+            if let Some(parent) = path.parent() {
+                remove_empty_dirs(parent, esp)?;
+            }
+            cleanup.assets.push(path);
+        }
+    }
+
+    cleanup.assets.extend(dtb::remove_unreferenced(esp, &retained)?);
+
+    Ok(cleanup)
+}
+
+/// The booted state & kernel on native roots, whose entries must never be removed
+fn booted(install: &Installation) -> Option<running::Booted> {
+    is_native(install).then(|| running::Booted::detect(&running::System))
+}
+
+/// The state the installation is currently booted into, if native
+pub fn booted_state(install: &Installation) -> Option<state::Id> {
+    booted(install).and_then(|booted| booted.state)
+}
+
+/// Returns true if `entry` boots the `booted` kernel, warning that it is kept
+fn is_booted(booted: Option<&running::Booted>, entry: &LoaderEntry) -> bool {
+    let protected = booted.is_some_and(|booted| booted.protects(entry));
+
+    if protected {
+        log::warn!("Keeping {} as it boots the running kernel", entry.path.display());
+    }
+
+    protected
+}
+
+/// Remove `dir` and its ancestors, stopping at `root` or the first non-empty directory
+fn remove_empty_dirs(dir: &Path, root: &Path) -> io::Result<()> {
+    for dir in dir.ancestors().take_while(|dir| *dir != root && dir.starts_with(root)) {
+        if fs::read_dir(dir)?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(dir)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn kernel_trees_from_layouts() {
+        let layouts = backend::test::layouts(&[
+            "lib/kernel/6.12.9-1/vmlinuz",
+            "lib/kernel/6.12.9-1/System.map",
+            "lib/kernel/6.12.9-1/99-extra.initrd",
+            "lib/kernel/6.12.9-1/10-default.initrd",
+            "lib/kernel/6.6.70-1/vmlinuz",
+            "lib/systemd/boot/efi/systemd-bootx64.efi",
+        ]);
+        let patterns = Settings::default().kernel_patterns().unwrap();
+        let trees = kernel::trees(&kernel_files_from_state(&layouts, &patterns));
+
+        assert_eq!(
+            trees,
+            vec![
+                kernel::KernelTree {
+                    version: "6.12.9-1".to_owned(),
+                    image: Some("usr/lib/kernel/6.12.9-1/vmlinuz".into()),
+                    initrds: vec![
+                        "usr/lib/kernel/6.12.9-1/10-default.initrd".into(),
+                        "usr/lib/kernel/6.12.9-1/99-extra.initrd".into()
+                    ],
+                },
+                kernel::KernelTree {
+                    version: "6.6.70-1".to_owned(),
+                    image: Some("usr/lib/kernel/6.6.70-1/vmlinuz".into()),
+                    initrds: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn kernel_trees_from_custom_pattern() {
+        let layouts = backend::test::layouts(&[
+            "lib/modules/6.13.0-rc1/vmlinuz",
+            "lib/modules/6.13.0-rc1/initramfs.img",
+            "lib/modules/6.13.0-rc1/modules.dep",
+            "lib/kernel/6.12.9-1/vmlinuz",
+        ]);
+        let settings = Settings {
+            kernels: Some(vec!["lib/modules/(version:*)/*".to_owned()]),
+            ..Default::default()
+        };
+        let trees = kernel::trees(&kernel_files_from_state(&layouts, &settings.kernel_patterns().unwrap()));
+
+        assert_eq!(
+            trees,
+            vec![kernel::KernelTree {
+                version: "6.13.0-rc1".to_owned(),
+                image: Some("usr/lib/modules/6.13.0-rc1/vmlinuz".into()),
+                initrds: vec!["usr/lib/modules/6.13.0-rc1/initramfs.img".into()],
+            }]
+        );
+
+        let invalid = Settings {
+            kernels: Some(vec!["lib/modules/(version:*/*".to_owned()]),
+            ..Default::default()
+        };
+        assert!(invalid.kernel_patterns().is_err());
+    }
+
+    #[test]
+    fn os_release_from_usr() {
+        let root = Scratch::new(&[
+            ("usr/lib/os-release", "NAME=\"AerynOS\"\n"),
+            ("etc/os-release", "NAME=\"Other\"\n"),
+        ]);
+
+        assert_eq!(os_release_contents(&root, true).unwrap(), "NAME=\"AerynOS\"\n");
+    }
+
+    #[test]
+    fn os_release_from_etc() {
+        let root = Scratch::new(&[("etc/os-release", "NAME=\"AerynOS\"\n")]);
+
+        assert_eq!(os_release_contents(&root, true).unwrap(), "NAME=\"AerynOS\"\n");
+        assert_eq!(grub::os_name(&root), "AerynOS");
+    }
+
+    #[test]
+    fn os_release_missing() {
+        let root = Scratch::new(&[]);
+
+        assert_eq!(os_release_contents(&root, false).unwrap(), FALLBACK_OS_RELEASE);
+        assert!(matches!(
+            os_release_contents(&root, true),
+            Err(Error::OsReleaseFile(path, _)) if path == root.join("usr/lib/os-release")
+        ));
+    }
+
+    #[test]
+    fn cleanup_refcounts_assets() {
+        let esp = Scratch::new(&[
+            (
+                "loader/entries/os-6.1-1.conf",
+                "linux /EFI/os/6.1/vmlinuz\ninitrd /EFI/os/6.1/initrd\noptions moss.fstx=1\n",
+            ),
+            (
+                "loader/entries/os-6.1-2.conf",
+                "linux /EFI/os/6.1/vmlinuz\ninitrd /EFI/os/6.1/initrd\noptions moss.fstx=2\n",
+            ),
+            (
+                "loader/entries/os-6.2-3.conf",
+                "linux /EFI/os/6.2/vmlinuz\ninitrd /EFI/os/6.2/initrd\noptions moss.fstx=3\n",
+            ),
+            ("loader/entries/windows.conf", "title Windows\n"),
+            ("EFI/os/6.1/vmlinuz", "kernel"),
+            ("EFI/os/6.1/initrd", "initrd"),
+            ("EFI/os/6.2/vmlinuz", "kernel"),
+            ("EFI/os/6.2/initrd", "initrd"),
+            ("EFI/Linux/moss-2-6.1.efi", "uki"),
+            ("EFI/Linux/moss-3-6.2.efi", "uki"),
+        ]);
+
+        let removed = [state::Id::from(1), state::Id::from(3)].into_iter().collect();
+        let cleanup = cleanup_esp(&esp, None, &removed, None).unwrap();
+
+        assert_eq!(cleanup.entries.len(), 2);
+        assert_eq!(cleanup.assets.len(), 3);
+
+        // Shared with the retained state 2
+        assert!(esp.join("EFI/os/6.1/vmlinuz").exists());
+        assert!(esp.join("EFI/os/6.1/initrd").exists());
+        assert!(esp.join("loader/entries/os-6.1-2.conf").exists());
+        // Foreign entries are never touched
+        assert!(esp.join("loader/entries/windows.conf").exists());
+        // Only referenced by state 3
+        assert!(!esp.join("EFI/os/6.2").exists());
+        assert!(!esp.join("EFI/Linux/moss-3-6.2.efi").exists());
+        assert!(esp.join("EFI/Linux/moss-2-6.1.efi").exists());
+    }
+
+    #[test]
+    fn cleanup_keeps_booted_kernel() {
+        let esp = Scratch::new(&[
+            (
+                "loader/entries/os-6.1-1.conf",
+                "version 6.1\nlinux /EFI/os/6.1/vmlinuz\ninitrd /EFI/os/6.1/initrd\noptions moss.fstx=1\n",
+            ),
+            (
+                "loader/entries/os-6.2-1.conf",
+                "version 6.2\nlinux /EFI/os/6.2/vmlinuz\noptions moss.fstx=1\n",
+            ),
+            ("EFI/os/6.1/vmlinuz", "kernel"),
+            ("EFI/os/6.1/initrd", "initrd"),
+            ("EFI/os/6.2/vmlinuz", "kernel"),
+            ("EFI/Linux/moss-1-6.1.efi", "uki"),
+        ]);
+        let booted = running::Booted {
+            state: Some(state::Id::from(1)),
+            version: Some("6.1".to_owned()),
+        };
+
+        let removed = [state::Id::from(1)].into_iter().collect();
+        let cleanup = cleanup_esp(&esp, None, &removed, Some(&booted)).unwrap();
+
+        assert_eq!(cleanup.entries, vec![esp.join("loader/entries/os-6.2-1.conf")]);
+        assert!(esp.join("loader/entries/os-6.1-1.conf").exists());
+        assert!(esp.join("EFI/os/6.1/vmlinuz").exists());
+        assert!(esp.join("EFI/os/6.1/initrd").exists());
+        assert!(esp.join("EFI/Linux/moss-1-6.1.efi").exists());
+        assert!(!esp.join("EFI/os/6.2").exists());
+    }
+}
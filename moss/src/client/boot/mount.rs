@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Making the boot partitions of a native root writable for the duration of a sync
+//!
+//! Some systems mount the ESP read-only by default, or have it automounted by
+//! systemd before blsforme gets to it. Read-only partitions are remounted read-write
+//! and restored once the [`Writable`] guard is dropped, whereas partitions already
+//! mounted elsewhere are used as they are.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    errno::Errno,
+    mount::{mount, MsFlags},
+    sys::statvfs::{statvfs, FsFlags},
+};
+
+/// The mount operations needed to toggle a partition between read-only and read-write
+pub trait Mounter {
+    /// Returns true if the filesystem at `path` is mounted read-only
+    fn is_read_only(&self, path: &Path) -> io::Result<bool>;
+    /// Remount the filesystem at `path`, read-only or read-write
+    fn remount(&self, path: &Path, read_only: bool) -> io::Result<()>;
+}
+
+/// The mount layer of the running system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct System;
+
+impl Mounter for System {
+    fn is_read_only(&self, path: &Path) -> io::Result<bool> {
+        let stat = statvfs(path).map_err(io::Error::from)?;
+        Ok(stat.flags().contains(FsFlags::ST_RDONLY))
+    }
+
+    fn remount(&self, path: &Path, read_only: bool) -> io::Result<()> {
+        let flags = if read_only {
+            MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY
+        } else {
+            MsFlags::MS_REMOUNT
+        };
+
+        mount(None::<&str>, path, None::<&str>, flags, None::<&str>).map_err(io::Error::from)
+    }
+}
+
+/// Partitions remounted read-write, restored to read-only on drop
+pub struct Writable<'a> {
+    mounter: &'a dyn Mounter,
+    remounted: Vec<PathBuf>,
+}
+
+impl Drop for Writable<'_> {
+    fn drop(&mut self) {
+        for path in self.remounted.drain(..) {
+            match self.mounter.remount(&path, true) {
+                Ok(()) => log::info!("Restored read-only mount of {}", path.display()),
+                Err(e) => log::warn!("Failed to restore read-only mount of {}: {e}", path.display()),
+            }
+        }
+    }
+}
+
+/// Remount all read-only `partitions` read-write until the returned guard is dropped
+///
+/// On failure, partitions remounted so far are restored.
+pub fn writable<'a, 'p>(
+    mounter: &'a dyn Mounter,
+    partitions: impl IntoIterator<Item = &'p Path>,
+) -> Result<Writable<'a>, (PathBuf, io::Error)> {
+    let mut guard = Writable {
+        mounter,
+        remounted: vec![],
+    };
+
+    for path in partitions {
+        let read_only = mounter.is_read_only(path).map_err(|e| (path.to_path_buf(), e))?;
+        if !read_only {
+            continue;
+        }
+
+        log::info!("Remounting read-only {} read-write", path.display());
+        mounter.remount(path, false).map_err(|e| (path.to_path_buf(), e))?;
+        guard.remounted.push(path.to_path_buf());
+    }
+
+    Ok(guard)
+}
+
+/// Returns true if `error` was caused by a partition already being mounted
+pub fn is_already_mounted(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            if io.raw_os_error() == Some(Errno::EBUSY as i32) {
+                return true;
+            }
+        }
+        if e.to_string().contains("already mounted") {
+            return true;
+        }
+        source = e.source();
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// Records all remounts, failing those of `failing`
+    #[derive(Default)]
+    struct Mock {
+        read_only: Vec<PathBuf>,
+        failing: Option<PathBuf>,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl Mounter for Mock {
+        fn is_read_only(&self, path: &Path) -> io::Result<bool> {
+            Ok(self.read_only.iter().any(|ro| ro == path))
+        }
+
+        fn remount(&self, path: &Path, read_only: bool) -> io::Result<()> {
+            if self.failing.as_deref() == Some(path) {
+                return Err(io::Error::from_raw_os_error(Errno::EROFS as i32));
+            }
+            let mode = if read_only { "ro" } else { "rw" };
+            self.calls.borrow_mut().push(format!("{mode} {}", path.display()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn remount_and_restore() {
+        let mock = Mock {
+            read_only: vec!["/efi".into()],
+            ..Default::default()
+        };
+
+        let guard = writable(&mock, [Path::new("/efi"), Path::new("/boot")]).unwrap();
+        assert_eq!(*mock.calls.borrow(), vec!["rw /efi"]);
+        drop(guard);
+        assert_eq!(*mock.calls.borrow(), vec!["rw /efi", "ro /efi"]);
+    }
+
+    #[test]
+    fn remount_failure_restores_previous() {
+        let mock = Mock {
+            read_only: vec!["/efi".into(), "/boot".into()],
+            failing: Some("/boot".into()),
+            ..Default::default()
+        };
+
+        let Err((path, error)) = writable(&mock, [Path::new("/efi"), Path::new("/boot")]) else {
+            panic!("remount of /boot must fail");
+        };
+        assert_eq!(path, Path::new("/boot"));
+        assert_eq!(error.raw_os_error(), Some(Errno::EROFS as i32));
+        // The already remounted ESP is restored
+        assert_eq!(*mock.calls.borrow(), vec!["rw /efi", "ro /efi"]);
+
+        assert!(is_already_mounted(&io::Error::from_raw_os_error(Errno::EBUSY as i32)));
+        assert!(!is_already_mounted(&io::Error::from_raw_os_error(Errno::ENOENT as i32)));
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Ownership of the files moss manages on the boot partitions
+//!
+//! Entry and asset names have changed between moss versions, so names alone can't
+//! tell which files moss manages. Every sync therefore records its entries, kernel
+//! assets and UKIs in a manifest on each partition, alongside the moss version
+//! writing them. Partitions without a manifest have their legacy moss files adopted:
+//! entries carrying the state parameter or booting from the OS's own `EFI/<os-id>`
+//! directory, the kernel assets these entries reference and UKIs named by moss.
+//! Any other file, even within the OS's directory, may have been placed there by
+//! the user or a vendor and is never adopted.
+//!
+//! Shared assets installed beneath `EFI/moss` are managed by their own modules.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use super::{entry::LoaderEntry, uki};
+
+/// Manifest location within each boot partition, ignored by systemd-boot
+pub const MANIFEST: &str = "loader/entries/.moss-manifest.json";
+
+/// Version recorded for files written by this moss
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Version recorded for adopted files, whose writing version is unknown
+const LEGACY: &str = "legacy";
+
+/// What a managed file is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// A loader entry
+    Entry,
+    /// A kernel, initrd or device-tree referenced by entries
+    Asset,
+    /// A unified kernel image
+    Uki,
+}
+
+/// A managed file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Record {
+    pub kind: Kind,
+    /// moss version which last wrote the file
+    pub version: String,
+}
+
+/// All managed files of a boot partition, by their partition-relative path
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, Record>,
+}
+
+impl Manifest {
+    /// Load the manifest of the partition `tree`, if it has one
+    pub fn load(tree: &Path) -> io::Result<Option<Self>> {
+        let path = tree.join(MANIFEST);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        serde_json::from_slice(&fs::read(&path)?)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display())))
+    }
+
+    /// Load the manifest of the partition `tree`, adopting the legacy moss files of a
+    /// partition without one
+    ///
+    /// Adopted files are only persisted once [saved](Self::save).
+    pub fn load_or_adopt(tree: &Path, os_id: Option<&str>) -> io::Result<Self> {
+        match Self::load(tree)? {
+            Some(manifest) => Ok(manifest),
+            None => adopt(tree, os_id),
+        }
+    }
+
+    /// Write the manifest to the partition `tree`, forgetting files gone from it
+    pub fn save(mut self, tree: &Path) -> io::Result<()> {
+        self.files.retain(|file, _| tree.join(file).exists());
+
+        let path = tree.join(MANIFEST);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Never leave a truncated manifest behind
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec_pretty(&self).map_err(io::Error::from)?)?;
+        fs::rename(&partial, &path)
+    }
+
+    /// Record the partition-relative `file` as written by this moss
+    pub fn record(&mut self, file: impl Into<String>, kind: Kind) {
+        self.files.insert(
+            file.into(),
+            Record {
+                kind,
+                version: VERSION.to_owned(),
+            },
+        );
+    }
+
+    /// Returns true if the partition-relative `file` is managed by moss
+    pub fn owns(&self, file: &str) -> bool {
+        self.files.contains_key(file)
+    }
+
+    /// Managed files of the partition `tree` no longer in use, given all of its `entries`
+    ///
+    /// These are entries of previous naming schemes which can't be tied to a state, and
+    /// assets no longer referenced by any remaining entry, including those of other
+    /// installations sharing the partition.
+    pub fn orphaned(&self, tree: &Path, entries: &[LoaderEntry]) -> Vec<PathBuf> {
+        let is_orphan = |entry: &LoaderEntry| {
+            entry.state_id().is_none() && self.owns(&relative(tree, &entry.path).unwrap_or_default())
+        };
+
+        let referenced = entries
+            .iter()
+            .filter(|entry| !is_orphan(entry))
+            .flat_map(|entry| entry.assets())
+            .collect::<BTreeSet<_>>();
+
+        let orphaned_entries = entries
+            .iter()
+            .filter(|entry| is_orphan(entry))
+            .map(|entry| entry.path.clone());
+        let orphaned_assets = self
+            .files
+            .iter()
+            .filter(|(file, record)| record.kind == Kind::Asset && !referenced.contains(file.as_str()))
+            .map(|(file, _)| tree.join(file))
+            .filter(|path| path.exists());
+
+        orphaned_entries.chain(orphaned_assets).collect()
+    }
+}
+
+/// Path of `path` relative to the partition `tree`, as recorded in the manifest
+pub fn relative(tree: &Path, path: &Path) -> Option<String> {
+    path.strip_prefix(tree)
+        .ok()
+        .map(|relative| relative.to_string_lossy().into_owned())
+}
+
+/// Adopt the legacy moss files of the partition `tree` into a new manifest
+fn adopt(tree: &Path, os_id: Option<&str>) -> io::Result<Manifest> {
+    let os_dir = os_id
+        .filter(|id| !id.is_empty() && *id != "moss")
+        .map(|id| format!("EFI/{id}/"));
+    let is_moss = |entry: &LoaderEntry| {
+        entry.state_id().is_some()
+            || os_dir
+                .as_deref()
+                .is_some_and(|dir| entry.assets().any(|asset| asset.starts_with(dir)))
+    };
+
+    let mut manifest = Manifest::default();
+    let mut adopt = |file: String, kind| {
+        manifest.files.insert(
+            file,
+            Record {
+                kind,
+                version: LEGACY.to_owned(),
+            },
+        );
+    };
+
+    for entry in super::entry::load_all(tree)?.into_iter().filter(is_moss) {
+        if let Some(file) = relative(tree, &entry.path) {
+            adopt(file, Kind::Entry);
+        }
+        for asset in entry.assets().filter(|asset| tree.join(asset).exists()) {
+            adopt(asset.to_owned(), Kind::Asset);
+        }
+    }
+
+    for file in files(&tree.join(uki::DIR))? {
+        let is_uki = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(uki::state_id)
+            .is_some();
+
+        if let Some(file) = relative(tree, &file).filter(|_| is_uki) {
+            adopt(file, Kind::Uki);
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// All files beneath `dir`, recursively
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.extend(self::files(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::boot::entry;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn adopt_and_orphan() {
+        let tree = Scratch::new(&[
+            (
+                "loader/entries/os-6.12.9-1-4-x64.conf",
+                "linux /EFI/os/6.12.9-1/x64/vmlinuz\noptions moss.fstx=4\narchitecture x64\n",
+            ),
+            // Written before entries carried the state parameter
+            ("loader/entries/os-6.6.1-1.conf", "linux /EFI/os/6.6.1-1/vmlinuz\n"),
+            ("loader/entries/windows.conf", "efi /EFI/Microsoft/bootmgfw.efi\n"),
+            ("EFI/os/6.12.9-1/x64/vmlinuz", ""),
+            // Not referenced by any entry, so not necessarily written by moss
+            ("EFI/os/6.12.9-1/vmlinuz", ""),
+            ("EFI/os/6.6.1-1/vmlinuz", ""),
+            ("EFI/Microsoft/bootmgfw.efi", ""),
+            ("EFI/Linux/moss-4-6.12.9-1-x64.efi", ""),
+        ]);
+
+        let mut manifest = Manifest::load_or_adopt(&tree, Some("os")).unwrap();
+        assert!(manifest.owns("loader/entries/os-6.12.9-1-4-x64.conf"));
+        assert!(manifest.owns("loader/entries/os-6.6.1-1.conf"));
+        assert!(manifest.owns("EFI/os/6.6.1-1/vmlinuz"));
+        assert!(manifest.owns("EFI/os/6.12.9-1/x64/vmlinuz"));
+        assert!(!manifest.owns("EFI/os/6.12.9-1/vmlinuz"));
+        assert!(manifest.owns("EFI/Linux/moss-4-6.12.9-1-x64.efi"));
+        assert!(!manifest.owns("EFI/Microsoft/bootmgfw.efi"));
+        assert!(!manifest.owns("loader/entries/windows.conf"));
+
+        assert_eq!(
+            manifest.orphaned(&tree, &entry::load_all(&tree).unwrap()),
+            vec![
+                tree.join("loader/entries/os-6.6.1-1.conf"),
+                tree.join("EFI/os/6.6.1-1/vmlinuz"),
+            ]
+        );
+
+        fs::remove_file(tree.join("EFI/os/6.6.1-1/vmlinuz")).unwrap();
+        manifest.record("EFI/Linux/moss-4-6.12.9-1-x64.efi", Kind::Uki);
+        manifest.save(&tree).unwrap();
+        let saved = Manifest::load(&tree).unwrap().unwrap();
+        assert!(!saved.owns("EFI/os/6.6.1-1/vmlinuz"));
+        assert_eq!(saved.files["loader/entries/os-6.6.1-1.conf"].version, LEGACY);
+        assert_eq!(saved.files["EFI/Linux/moss-4-6.12.9-1-x64.efi"].version, VERSION);
+    }
+}
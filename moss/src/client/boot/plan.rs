@@ -0,0 +1,221 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! The planned changes of a boot synchronization, as previewed by a dry run
+
+use std::{fmt, path::PathBuf};
+
+use serde::Serialize;
+
+use super::{bootloader, copy, hooks, mirror, space, validate, Backend, Mode};
+use crate::state;
+
+/// The result of a boot synchronization
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", content = "value", rename_all = "kebab-case")]
+pub enum SyncOutcome {
+    /// The plan was executed
+    Synced(Plan),
+    /// The plan was computed without modifying the system
+    Planned(Plan),
+    /// Nothing could be synchronized
+    Skipped(SkipReason),
+}
+
+impl SyncOutcome {
+    /// The plan of a synced or planned outcome
+    pub fn into_plan(self) -> Option<Plan> {
+        match self {
+            SyncOutcome::Synced(plan) | SyncOutcome::Planned(plan) => Some(plan),
+            SyncOutcome::Skipped(_) => None,
+        }
+    }
+}
+
+/// A one-line summary for the end of a transaction, e.g. `boot: 2 entries updated, 1 asset copied`
+impl fmt::Display for SyncOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |n: usize, singular: &str, plural: &str| format!("{n} {}", if n == 1 { singular } else { plural });
+
+        match self {
+            SyncOutcome::Synced(plan) => {
+                let (written, copied) = (plan.entries_written(), plan.transfer.copied);
+                if written == 0 && copied == 0 {
+                    write!(f, "boot: up to date")
+                } else {
+                    write!(
+                        f,
+                        "boot: {} updated, {} copied",
+                        count(written, "entry", "entries"),
+                        count(copied, "asset", "assets")
+                    )
+                }
+            }
+            SyncOutcome::Planned(plan) => {
+                write!(f, "boot: {} pending", count(plan.entries_written(), "entry", "entries"))
+            }
+            SyncOutcome::Skipped(reason) => write!(f, "boot: skipped, {reason}"),
+        }
+    }
+}
+
+/// Why a boot synchronization was skipped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// No bootloader is configured or installed
+    NoBootloader,
+    /// None of the states contain a bootable kernel
+    NoKernels,
+    /// The boot topology is unsupported, such as a system without EFI or an ESP
+    Topology(String),
+    /// Boot management doesn't apply to the installation, e.g. within a container
+    Unmanaged(String),
+    /// System triggers, and with them boot synchronization, were skipped on request
+    TriggersSkipped,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::NoBootloader => write!(f, "no bootloader installed"),
+            SkipReason::NoKernels => write!(f, "no kernels installed"),
+            SkipReason::Topology(error) => write!(f, "unsupported boot topology: {error}"),
+            SkipReason::Unmanaged(reason) => write!(f, "{reason}"),
+            SkipReason::TriggersSkipped => write!(f, "system triggers skipped"),
+        }
+    }
+}
+
+/// Everything a boot synchronization would mount, copy and write
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    /// Bootloader backend being synchronized
+    pub backend: Option<Backend>,
+    /// Entry generation mode, for backends which support it
+    pub mode: Option<Mode>,
+    /// Boot partitions mounted for the duration of the sync
+    pub mounts: Vec<PathBuf>,
+    /// Assets copied into the boot partitions
+    pub copies: Vec<AssetCopy>,
+    /// Boot entries written or removed
+    pub entries: Vec<PlannedEntry>,
+    /// Entry the bootloader will default to, when managed and known ahead of the sync
+    pub default: Option<String>,
+    /// Installation of the packaged loader binaries into the ESP
+    pub loader: Vec<bootloader::Update>,
+    /// Assets copied, skipped as unchanged and verified by an executed sync
+    pub transfer: copy::Stats,
+    /// Bytes written into each boot partition
+    pub required: Vec<space::Requirement>,
+    /// Hooks run before and after the sync
+    pub hooks: Vec<hooks::Hook>,
+    /// Mirror ESPs reconciled by an executed sync
+    pub mirrors: Vec<mirror::Outcome>,
+    /// Entries failing validation after an executed sync
+    pub invalid: Vec<validate::Invalid>,
+    /// Files owned by moss but no longer in use, removed by gc
+    pub orphaned: Vec<PathBuf>,
+}
+
+impl Plan {
+    /// Entries the sync would create or delete, i.e. those out of sync with the states
+    pub fn pending(&self) -> impl Iterator<Item = &PlannedEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.action, Action::Create | Action::Delete))
+    }
+
+    /// Number of entries created, updated or deleted
+    pub fn entries_written(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.action != Action::Unchanged)
+            .count()
+    }
+}
+
+/// An asset copied into a boot partition
+#[derive(Debug, Serialize)]
+pub struct AssetCopy {
+    pub source: PathBuf,
+    /// Destination, when it is known ahead of the sync
+    pub destination: Option<PathBuf>,
+    /// Bytes written by the copy, zero if the destination is up to date
+    pub size: u64,
+}
+
+/// A boot entry for a single kernel of a state
+#[derive(Debug, Serialize)]
+pub struct PlannedEntry {
+    pub action: Action,
+    pub state: state::Id,
+    pub version: String,
+    /// Location of the entry, when it is known ahead of the sync
+    pub path: Option<PathBuf>,
+}
+
+/// The change made to a boot entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+    Unchanged,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(action: Action) -> PlannedEntry {
+        PlannedEntry {
+            action,
+            state: state::Id::from(3),
+            version: "6.12.9-1".to_owned(),
+            path: None,
+        }
+    }
+
+    #[test]
+    fn summarize_outcomes() {
+        let synced = SyncOutcome::Synced(Plan {
+            entries: vec![entry(Action::Create), entry(Action::Delete), entry(Action::Unchanged)],
+            transfer: copy::Stats {
+                copied: 1,
+                skipped: 4,
+                verified: 1,
+            },
+            ..Default::default()
+        });
+        assert_eq!(synced.to_string(), "boot: 2 entries updated, 1 asset copied");
+
+        let unchanged = SyncOutcome::Synced(Plan {
+            entries: vec![entry(Action::Unchanged)],
+            ..Default::default()
+        });
+        assert_eq!(unchanged.to_string(), "boot: up to date");
+
+        let planned = SyncOutcome::Planned(Plan {
+            entries: vec![entry(Action::Update)],
+            ..Default::default()
+        });
+        assert_eq!(planned.to_string(), "boot: 1 entry pending");
+
+        assert_eq!(
+            SyncOutcome::Skipped(SkipReason::NoKernels).to_string(),
+            "boot: skipped, no kernels installed"
+        );
+        assert_eq!(
+            SyncOutcome::Skipped(SkipReason::NoBootloader).to_string(),
+            "boot: skipped, no bootloader installed"
+        );
+        assert_eq!(
+            SyncOutcome::Skipped(SkipReason::Topology("no ESP found".to_owned())).to_string(),
+            "boot: skipped, unsupported boot topology: no ESP found"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Rescue entry booting the newest kernel with a conservative command line
+//!
+//! The rescue entry is derived from the regular entry of the newest kernel, sharing
+//! its kernel, initrds and device tree. Its command line only retains the root
+//! specification and the state parameter, dropping all user fragments, and boots
+//! into the rescue target. It is rewritten on every sync and never made the default.
+
+use super::{
+    arch::Arch,
+    entry::{LoaderEntry, STATE_PARAMETER},
+};
+
+/// File name of the rescue entry within `loader/entries`, suffixed per [`Arch`]
+pub const FILE_NAME: &str = "moss-rescue.conf";
+
+/// Parameters of the regular entry retained in the rescue entry
+const RETAINED: &[&str] = &["root", "rootflags", "rootfstype", "ro", "rw", STATE_PARAMETER];
+
+/// Parameters added to the rescue entry
+const RESCUE: &[&str] = &["rescue"];
+
+/// Keys of the regular entry copied verbatim into the rescue entry
+const COPIED: &[&str] = &["version", "sort-key", "linux", "initrd", "devicetree", "architecture"];
+
+/// File name of the rescue entry of `arch`
+pub fn file_name(arch: Option<Arch>) -> String {
+    arch.map_or_else(|| FILE_NAME.to_owned(), |arch| arch.file_name(FILE_NAME))
+}
+
+/// Returns true if `entry` is the rescue entry of any architecture
+pub fn is_rescue(entry: &LoaderEntry) -> bool {
+    entry
+        .path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| {
+            name == FILE_NAME || Arch::of_file_name(name).is_some_and(|arch| name == file_name(Some(arch)))
+        })
+}
+
+/// Derive the rescue entry from the `contents` of a regular entry
+pub fn render(contents: &str) -> String {
+    let key = |line: &str| line.split_whitespace().next().unwrap_or_default().to_owned();
+
+    let title = contents
+        .lines()
+        .find(|line| key(line) == "title")
+        .map(|line| format!("title {} (rescue)", value(line)))
+        .unwrap_or_else(|| "title Rescue".to_owned());
+
+    let params = contents
+        .lines()
+        .filter(|line| key(line) == "options")
+        .flat_map(|line| value(line).split_whitespace().map(str::to_owned).collect::<Vec<_>>())
+        .filter(|param| RETAINED.contains(&param.split_once('=').map_or(param.as_str(), |(key, _)| key)))
+        .chain(RESCUE.iter().map(|param| (*param).to_owned()))
+        .collect::<Vec<_>>();
+
+    let mut lines = vec![title];
+    lines.extend(
+        contents
+            .lines()
+            .filter(|line| COPIED.contains(&key(line).as_str()))
+            .map(|line| line.trim().to_owned()),
+    );
+    lines.push(format!("options {}", params.join(" ")));
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+/// Value of a `key value` entry line
+fn value(line: &str) -> &str {
+    line.trim()
+        .split_once(char::is_whitespace)
+        .map_or("", |(_, value)| value.trim())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_rescue_entry() {
+        let entry = "title AerynOS 6.12.9-1 (state 4)\nversion 6.12.9-1\nlinux /EFI/os/6.12.9-1/vmlinuz\n\
+                     initrd /EFI/moss/ucode/intel-ucode.img\ninitrd /EFI/os/6.12.9-1/initrd\n\
+                     options root=UUID=abcd rootflags=subvol=@ rw quiet splash nvidia-drm.modeset=1\n\
+                     options moss.fstx=4\n";
+
+        assert_eq!(
+            render(entry),
+            "title AerynOS 6.12.9-1 (state 4) (rescue)\nversion 6.12.9-1\nlinux /EFI/os/6.12.9-1/vmlinuz\n\
+             initrd /EFI/moss/ucode/intel-ucode.img\ninitrd /EFI/os/6.12.9-1/initrd\n\
+             options root=UUID=abcd rootflags=subvol=@ rw moss.fstx=4 rescue\n"
+        );
+        assert!(is_rescue(&LoaderEntry::parse(
+            "/efi/loader/entries/moss-rescue.conf",
+            ""
+        )));
+        assert!(is_rescue(&LoaderEntry::parse(
+            "/efi/loader/entries/moss-rescue-aa64.conf",
+            ""
+        )));
+    }
+}
@@ -0,0 +1,131 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Detection of the kernel and state the system is currently booted into
+//!
+//! The entry of the running kernel is never removed by a cleanup, regardless of
+//! state retention, so a failing new kernel always leaves a known good fallback.
+//! This only applies to native roots.
+
+use std::io;
+
+use fs_err as fs;
+
+use super::entry::{LoaderEntry, STATE_PARAMETER};
+use crate::state;
+
+/// Source of the running kernel's command line and release
+pub trait Provider {
+    /// The kernel command line, as in `/proc/cmdline`
+    fn cmdline(&self) -> io::Result<String>;
+    /// The kernel release, as reported by `uname -r`
+    fn release(&self) -> io::Result<String>;
+}
+
+/// The running system, via procfs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct System;
+
+impl Provider for System {
+    fn cmdline(&self) -> io::Result<String> {
+        fs::read_to_string("/proc/cmdline")
+    }
+
+    fn release(&self) -> io::Result<String> {
+        fs::read_to_string("/proc/sys/kernel/osrelease")
+    }
+}
+
+/// The state and kernel the system is booted into
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Booted {
+    pub state: Option<state::Id>,
+    pub version: Option<String>,
+}
+
+impl Booted {
+    /// Detect the booted state & kernel from `provider`, leaving unknown parts unset
+    pub fn detect(provider: &impl Provider) -> Self {
+        let state = provider.cmdline().ok().and_then(|cmdline| {
+            cmdline
+                .split_whitespace()
+                .find_map(|param| param.strip_prefix(STATE_PARAMETER)?.strip_prefix('='))
+                .and_then(|id| id.parse::<i32>().ok())
+                .map(state::Id::from)
+        });
+        let version = provider
+            .release()
+            .ok()
+            .map(|release| release.trim().to_owned())
+            .filter(|release| !release.is_empty());
+
+        Self { state, version }
+    }
+
+    /// Returns true if `entry` boots the running kernel of the booted state
+    ///
+    /// With an unknown kernel release every entry of the booted state is protected.
+    pub fn protects(&self, entry: &LoaderEntry) -> bool {
+        let Some(state) = self.state else {
+            return false;
+        };
+
+        match &self.version {
+            Some(version) => entry.matches(state, version),
+            None => entry.state_id() == Some(state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Fake {
+        cmdline: &'static str,
+        release: &'static str,
+    }
+
+    impl Provider for Fake {
+        fn cmdline(&self) -> io::Result<String> {
+            Ok(self.cmdline.to_owned())
+        }
+
+        fn release(&self) -> io::Result<String> {
+            Ok(self.release.to_owned())
+        }
+    }
+
+    #[test]
+    fn protect_booted_entry() {
+        let booted = Booted::detect(&Fake {
+            cmdline: "root=UUID=abcd rw quiet moss.fstx=7\n",
+            release: "6.12.9-1\n",
+        });
+        assert_eq!(
+            booted,
+            Booted {
+                state: Some(state::Id::from(7)),
+                version: Some("6.12.9-1".to_owned())
+            }
+        );
+
+        let entry = |state: i32, version: &str| {
+            LoaderEntry::parse(
+                format!("loader/entries/os-{version}-{state}.conf"),
+                &format!("linux /EFI/os/{version}/vmlinuz\noptions moss.fstx={state}\n"),
+            )
+        };
+        assert!(booted.protects(&entry(7, "6.12.9-1")));
+        assert!(!booted.protects(&entry(7, "6.13.0-1")));
+        assert!(!booted.protects(&entry(6, "6.12.9-1")));
+
+        // Not booted via moss, e.g. a live session
+        let live = Booted::detect(&Fake {
+            cmdline: "boot=live quiet",
+            release: "6.12.9-1",
+        });
+        assert!(!live.protects(&entry(7, "6.12.9-1")));
+    }
+}
@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Boot management settings, loaded from `boot.yaml` and `boot.d/*.yaml`
+//! within `/usr/share/moss` and `/etc/moss`
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use fnmatch::Pattern;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{bootloader, manage::Manage, microcode, mirror, sign::Signing, space, title, Backend};
+
+/// Default kernel discovery patterns, relative to `/usr`
+pub const KERNEL_PATTERNS: &[&str] = &["lib/kernel/(version:*)/*"];
+
+/// Installation-wide boot management settings
+///
+/// All keys are optional so that multiple files can be layered, with
+/// later (`/etc`) files taking precedence.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Settings {
+    /// Whether boot management applies to the installation, defaulting to [`Manage::Auto`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manage: Option<Manage>,
+    /// Bootloader backend to synchronize, auto-detected from the installed layouts when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+    /// How systemd-boot entries are generated, defaulting to [`Mode::Entries`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+    /// Kernel discovery patterns relative to `/usr`, defaulting to [`KERNEL_PATTERNS`]
+    ///
+    /// Each pattern must capture the kernel version as `(version:*)`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernels: Option<Vec<String>>,
+    /// Bootloader asset patterns relative to `/usr`, defaulting to those of the backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bootloader_assets: Option<Vec<String>>,
+    /// Microcode image patterns relative to `/usr`, defaulting to [`microcode::PATTERNS`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub microcode: Option<Vec<String>>,
+    /// Whether the bootloader defaults to the newly synchronized state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set_default: Option<DefaultEntry>,
+    /// Boot menu title template, defaulting to [`title::DEFAULT_TEMPLATE`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Fail boot synchronization when the installation has no os-release file,
+    /// rather than synthesizing one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict_os_release: Option<bool>,
+    /// When to replace an installed systemd-boot binary, defaulting to [`bootloader::Policy::Newer`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loader_update: Option<bootloader::Policy>,
+    /// ESP block device or directory for image builds, mounted at `<root>/efi`
+    ///
+    /// Unused for native runs, where the ESP is probed and mounted via blsforme.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub esp: Option<PathBuf>,
+    /// Secure Boot signing of installed EFI binaries, disabled when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<Signing>,
+    /// Board dtb relative to the kernel's `dtbs` directory, e.g. `broadcom/bcm2711-rpi-4-b.dtb`
+    ///
+    /// When unset, all dtbs shipped with a kernel are installed and no `devicetree`
+    /// is set in its entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub devicetree: Option<PathBuf>,
+    /// Generate a rescue entry for the newest kernel, with a minimal command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rescue_entry: Option<bool>,
+    /// Free space in MiB kept on every boot partition, defaulting to [`space::DEFAULT_MARGIN`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub space_margin: Option<u64>,
+    /// Additional ESPs kept in sync with the primary one, by `PARTUUID=<uuid>` or mount path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrors: Option<Vec<mirror::Target>>,
+    /// Run the pre- and post-sync hooks for image roots too, rather than only native ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_hooks: Option<bool>,
+}
+
+/// Policy for the bootloader's default entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum DefaultEntry {
+    /// Default to the newest kernel of the synchronized (or rolled back to) state
+    #[default]
+    Latest,
+    /// Leave the default entry untouched
+    Keep,
+}
+
+/// Entry generation mode for the systemd-boot backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Mode {
+    /// BLS type #1 loader entries with separate kernel & initrd assets
+    #[default]
+    Entries,
+    /// Unified Kernel Images in `EFI/Linux`
+    Uki,
+}
+
+impl Settings {
+    /// Load and merge all boot settings visible to the config `manager`
+    pub fn load(manager: &config::Manager) -> Self {
+        manager
+            .load::<Self>()
+            .into_iter()
+            .reduce(Self::merge)
+            .unwrap_or_default()
+    }
+
+    /// Active kernel discovery patterns
+    pub fn kernel_globs(&self) -> Vec<String> {
+        globs(self.kernels.as_deref(), KERNEL_PATTERNS)
+    }
+
+    /// Active bootloader asset patterns for `backend`
+    pub fn asset_globs(&self, backend: Backend) -> Vec<String> {
+        globs(self.bootloader_assets.as_deref(), backend.asset_patterns())
+    }
+
+    /// Configured board dtb
+    pub fn board_dtb(&self) -> Option<&Path> {
+        self.devicetree.as_deref()
+    }
+
+    /// Compiled kernel discovery patterns
+    pub fn kernel_patterns(&self) -> Result<Vec<Pattern>, InvalidPattern> {
+        compile("kernel", &self.kernel_globs())
+    }
+
+    /// Compiled bootloader asset patterns for `backend`
+    pub fn asset_patterns(&self, backend: Backend) -> Result<Vec<Pattern>, InvalidPattern> {
+        compile("bootloader asset", &self.asset_globs(backend))
+    }
+
+    /// Compiled microcode image patterns
+    pub fn microcode_patterns(&self) -> Result<Vec<Pattern>, InvalidPattern> {
+        compile("microcode", &globs(self.microcode.as_deref(), microcode::PATTERNS))
+    }
+
+    /// Layer `other` on top of these settings
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            manage: other.manage.or(self.manage),
+            backend: other.backend.or(self.backend),
+            mode: other.mode.or(self.mode),
+            kernels: other.kernels.or(self.kernels),
+            bootloader_assets: other.bootloader_assets.or(self.bootloader_assets),
+            microcode: other.microcode.or(self.microcode),
+            set_default: other.set_default.or(self.set_default),
+            title: other.title.or(self.title),
+            strict_os_release: other.strict_os_release.or(self.strict_os_release),
+            loader_update: other.loader_update.or(self.loader_update),
+            esp: other.esp.or(self.esp),
+            signing: other.signing.or(self.signing),
+            devicetree: other.devicetree.or(self.devicetree),
+            rescue_entry: other.rescue_entry.or(self.rescue_entry),
+            space_margin: other.space_margin.or(self.space_margin),
+            mirrors: other.mirrors.or(self.mirrors),
+            image_hooks: other.image_hooks.or(self.image_hooks),
+        }
+    }
+}
+
+/// A configured pattern with invalid fnmatch syntax
+#[derive(Debug, Error)]
+#[error("invalid {category} pattern {pattern:?}: {source}")]
+pub struct InvalidPattern {
+    pub category: &'static str,
+    pub pattern: String,
+    #[source]
+    pub source: fnmatch::Error,
+}
+
+/// The `configured` patterns, falling back to `defaults`
+fn globs(configured: Option<&[String]>, defaults: &[&str]) -> Vec<String> {
+    match configured {
+        Some(patterns) => patterns.to_vec(),
+        None => defaults.iter().map(|pattern| (*pattern).to_owned()).collect(),
+    }
+}
+
+/// Compile all `patterns` of `category`
+fn compile(category: &'static str, patterns: &[String]) -> Result<Vec<Pattern>, InvalidPattern> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::from_str(pattern).map_err(|source| InvalidPattern {
+                category,
+                pattern: pattern.clone(),
+                source,
+            })
+        })
+        .collect()
+}
+
+impl config::Config for Settings {
+    fn domain() -> String {
+        "boot".into()
+    }
+}
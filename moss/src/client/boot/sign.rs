@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Secure Boot signing of the EFI binaries installed to the boot partitions
+//!
+//! Every binary is signed into a temporary file next to its destination, verified
+//! against the configured certificate and only then moved into place. Binaries
+//! already carrying a valid signature for the certificate are left untouched.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::entry::LoaderEntry;
+
+/// Directories holding bootloader binaries, relative to the ESP
+pub const BOOTLOADER_DIRS: &[&str] = &["EFI/systemd", "EFI/BOOT"];
+
+/// Default signing command
+const SBSIGN: &[&str] = &[
+    "sbsign", "--key", "{key}", "--cert", "{cert}", "--output", "{output}", "{input}",
+];
+
+/// Signing configuration, from the `signing` key of the boot settings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Signing {
+    /// Private key used for signing
+    pub key: PathBuf,
+    /// Certificate used for signing & verification
+    pub cert: PathBuf,
+    /// Signing command template, defaulting to `sbsign`
+    ///
+    /// `{key}`, `{cert}`, `{input}` and `{output}` are substituted in every argument.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Vec<String>>,
+}
+
+#[derive(Debug, Error)]
+pub enum Failure {
+    #[error("empty signing command")]
+    EmptyCommand,
+    #[error("spawn {0}: {1}")]
+    Spawn(String, #[source] io::Error),
+    #[error("{0} exited with {1}")]
+    Exit(String, ExitStatus),
+    #[error("signature does not verify against {}", .0.display())]
+    Unverified(PathBuf),
+    #[error("io: {0}")]
+    IO(#[from] io::Error),
+}
+
+impl Signing {
+    /// The signing command for `input` into `output`, with all placeholders substituted
+    pub fn command(&self, input: &Path, output: &Path) -> Vec<String> {
+        let template = match &self.command {
+            Some(command) => command.clone(),
+            None => SBSIGN.iter().map(|arg| (*arg).to_owned()).collect(),
+        };
+
+        template
+            .iter()
+            .map(|arg| {
+                arg.replace("{key}", &self.key.to_string_lossy())
+                    .replace("{cert}", &self.cert.to_string_lossy())
+                    .replace("{input}", &input.to_string_lossy())
+                    .replace("{output}", &output.to_string_lossy())
+            })
+            .collect()
+    }
+
+    /// Returns true if `path` carries a valid signature for our certificate
+    pub fn is_signed(&self, path: &Path) -> bool {
+        Command::new("sbverify")
+            .arg("--cert")
+            .arg(&self.cert)
+            .arg(path)
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    /// Sign the installed binary at `path` in place, returning true if it was signed
+    ///
+    /// On failure the unsigned binary is removed so it can never be booted.
+    pub fn sign(&self, path: &Path) -> Result<bool, Failure> {
+        if self.is_signed(path) {
+            return Ok(false);
+        }
+
+        let signed = temporary(path);
+        let result = self.sign_into(path, &signed);
+
+        if result.is_err() {
+            let _ = fs::remove_file(&signed);
+            let _ = fs::remove_file(path);
+        }
+
+        result.map(|_| true)
+    }
+
+    fn sign_into(&self, input: &Path, output: &Path) -> Result<(), Failure> {
+        let command = self.command(input, output);
+        let (program, args) = command.split_first().ok_or(Failure::EmptyCommand)?;
+
+        let status = Command::new(program)
+            .args(args)
+            .status()
+            .map_err(|e| Failure::Spawn(program.clone(), e))?;
+        if !status.success() {
+            return Err(Failure::Exit(program.clone(), status));
+        }
+
+        if !self.is_signed(output) {
+            return Err(Failure::Unverified(self.cert.clone()));
+        }
+
+        fs::rename(output, input)?;
+
+        Ok(())
+    }
+}
+
+/// All binaries to sign beneath the partition `tree` for the moss generated `entries`
+///
+/// This covers the bootloader itself and the kernel image of each entry. Initrds
+/// aren't executable and are verified by the kernel instead.
+pub fn binaries(tree: &Path, entries: &[LoaderEntry]) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    for dir in BOOTLOADER_DIRS.iter().map(|dir| tree.join(dir)) {
+        if !dir.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+
+            if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("efi")) && path.is_file() {
+                paths.push(path);
+            }
+        }
+    }
+
+    paths.extend(
+        entries
+            .iter()
+            .filter(|entry| entry.state_id().is_some())
+            .filter_map(|entry| entry.linux.as_deref())
+            .map(|linux| tree.join(linux.trim_start_matches('/')))
+            .filter(|path| path.exists()),
+    );
+
+    paths.sort();
+    paths.dedup();
+
+    Ok(paths)
+}
+
+/// Temporary path to sign `path` into, within the same directory
+fn temporary(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{name}.signed"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn substitute_command() {
+        let signing = Signing {
+            key: "/etc/moss/keys/db.key".into(),
+            cert: "/etc/moss/keys/db.crt".into(),
+            command: None,
+        };
+
+        assert_eq!(
+            signing.command(Path::new("/efi/EFI/Linux/moss-1-6.12.efi"), Path::new("/tmp/out")),
+            vec![
+                "sbsign",
+                "--key",
+                "/etc/moss/keys/db.key",
+                "--cert",
+                "/etc/moss/keys/db.crt",
+                "--output",
+                "/tmp/out",
+                "/efi/EFI/Linux/moss-1-6.12.efi"
+            ]
+        );
+
+        let signing = Signing {
+            command: Some(vec![
+                "my-signer".to_owned(),
+                "--in={input}".to_owned(),
+                "{output}".to_owned(),
+            ]),
+            ..signing
+        };
+        assert_eq!(
+            signing.command(Path::new("a.efi"), Path::new(".a.efi.signed")),
+            vec!["my-signer", "--in=a.efi", ".a.efi.signed"]
+        );
+        assert_eq!(
+            temporary(Path::new("/efi/EFI/BOOT/BOOTX64.EFI")),
+            Path::new("/efi/EFI/BOOT/.BOOTX64.EFI.signed")
+        );
+    }
+}
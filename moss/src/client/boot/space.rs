@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Capacity checks of the boot partitions ahead of a sync
+//!
+//! The bytes a sync will actually write (i.e. excluding assets already up to date)
+//! are totalled per partition and compared against the free space of its filesystem,
+//! minus a safety margin for VFAT allocation overhead. A sync which doesn't fit is
+//! aborted before anything is written.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use super::{esp, plan::Plan};
+
+/// Default free space kept on every boot partition, in MiB
+pub const DEFAULT_MARGIN: u64 = 4;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Bytes a sync writes into a single boot partition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Requirement {
+    pub partition: PathBuf,
+    pub required: u64,
+}
+
+/// A boot partition lacking the free space required by a sync
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortfall {
+    pub partition: PathBuf,
+    pub required: u64,
+    pub available: u64,
+    /// Safety margin, in bytes
+    pub margin: u64,
+}
+
+impl Shortfall {
+    /// Bytes missing for the sync to fit
+    pub fn missing(&self) -> u64 {
+        (self.required + self.margin).saturating_sub(self.available)
+    }
+}
+
+impl fmt::Display for Shortfall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} needs {} MiB but only {} MiB are free, short by {} MiB including a {} MiB margin; \
+             free up space with `moss boot gc` or `moss state prune`",
+            self.partition.display(),
+            mib(self.required),
+            mib(self.available),
+            mib(self.missing()),
+            mib(self.margin),
+        )
+    }
+}
+
+/// Bytes the `plan` writes into each of the `partitions`
+///
+/// Copies without a known destination land in the kernel partition, whereas loader
+/// updates always target the ESP.
+pub fn requirements(plan: &Plan, partitions: &esp::Partitions) -> Vec<Requirement> {
+    let mut required = BTreeMap::<&Path, u64>::new();
+
+    for copy in &plan.copies {
+        let partition = match &copy.destination {
+            Some(destination) => partitions.all().find(|tree| destination.starts_with(tree)),
+            None => partitions.kernels(),
+        };
+
+        if let Some(partition) = partition {
+            *required.entry(partition).or_default() += copy.size;
+        }
+    }
+
+    if let Some(esp) = partitions.esp.as_deref() {
+        for update in &plan.loader {
+            *required.entry(esp).or_default() += update.size;
+        }
+    }
+
+    required
+        .into_iter()
+        .filter(|(_, required)| *required > 0)
+        .map(|(partition, required)| Requirement {
+            partition: partition.to_path_buf(),
+            required,
+        })
+        .collect()
+}
+
+/// Ensure every partition of `requirements` has room for it, keeping `margin` MiB free
+///
+/// Partitions whose filesystem can't be queried are not checked.
+pub fn check(requirements: &[Requirement], margin: u64) -> Result<(), Shortfall> {
+    for requirement in requirements {
+        let Some(space) = esp::Space::of(&requirement.partition) else {
+            log::debug!("Unable to query free space of {}", requirement.partition.display());
+            continue;
+        };
+
+        fits(requirement, space.available, margin * MIB)?;
+    }
+
+    Ok(())
+}
+
+fn fits(requirement: &Requirement, available: u64, margin: u64) -> Result<(), Shortfall> {
+    if requirement.required + margin <= available {
+        return Ok(());
+    }
+
+    Err(Shortfall {
+        partition: requirement.partition.clone(),
+        required: requirement.required,
+        available,
+        margin,
+    })
+}
+
+/// Bytes rounded up to MiB
+fn mib(bytes: u64) -> u64 {
+    bytes.div_ceil(MIB)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::boot::plan::AssetCopy;
+
+    #[test]
+    fn requirements_per_partition() {
+        let partitions = esp::Partitions {
+            esp: Some("/efi".into()),
+            xbootldr: Some("/boot".into()),
+        };
+        let plan = Plan {
+            copies: vec![
+                AssetCopy {
+                    source: "/usr/lib/kernel/6.12/vmlinuz".into(),
+                    destination: None,
+                    size: 12 * MIB,
+                },
+                AssetCopy {
+                    source: "/usr/lib/kernel/6.12/initrd".into(),
+                    destination: Some("/boot/EFI/os/6.12/initrd".into()),
+                    size: 30 * MIB,
+                },
+                AssetCopy {
+                    source: "/usr/lib/systemd/boot/efi/addonx64.efi".into(),
+                    destination: Some("/efi/EFI/systemd/addonx64.efi".into()),
+                    size: 0,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let requirements = requirements(&plan, &partitions);
+        assert_eq!(
+            requirements,
+            vec![Requirement {
+                partition: "/boot".into(),
+                required: 42 * MIB,
+            }]
+        );
+
+        assert!(fits(&requirements[0], 64 * MIB, 4 * MIB).is_ok());
+
+        let shortfall = fits(&requirements[0], 44 * MIB, 4 * MIB).unwrap_err();
+        assert_eq!(shortfall.missing(), 2 * MIB);
+        assert!(shortfall
+            .to_string()
+            .starts_with("/boot needs 42 MiB but only 44 MiB are free"));
+    }
+}
@@ -0,0 +1,302 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Read-only reporting of the boot configuration
+
+use std::path::PathBuf;
+
+use blsforme::Schema;
+use fs_err as fs;
+use itertools::Itertools;
+use serde::Serialize;
+
+use super::{
+    active_architecture, boot_files_from_new_state, bootloader, configuration,
+    entry::{self, LoaderEntry},
+    esp::Space,
+    gc::{self, StaleEntry},
+    grub, is_native, kernel_files_from_state, known_states, layouts_for_state, locate_partitions, manage_policy,
+    managed, mirror, os_id, own_entries, owner,
+    plan::{Action, PlannedEntry},
+    read_os_release, retained_states, stale_to_planned, uki, validate, Backend, Error, Manage, Mode, Plan, Settings,
+};
+use crate::{state, Client};
+
+/// Firmware type of the boot environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum Firmware {
+    Uefi,
+    Bios,
+}
+
+/// Overall boot status of an installation
+#[derive(Debug, Serialize)]
+pub struct Status {
+    /// Firmware type, if the boot topology could be probed
+    pub firmware: Option<Firmware>,
+    /// ESP device
+    pub esp: Option<PathBuf>,
+    /// XBOOTLDR device
+    pub xbootldr: Option<PathBuf>,
+    /// Boot partition device for BIOS systems
+    pub boot_partition: Option<PathBuf>,
+    /// Location of the mounted ESP tree
+    pub mountpoint: Option<PathBuf>,
+    /// Location of the mounted XBOOTLDR tree
+    pub xbootldr_mountpoint: Option<PathBuf>,
+    /// Why the boot partitions couldn't be mounted
+    pub mount_error: Option<String>,
+    /// Capacity of the mounted ESP
+    pub esp_space: Option<Space>,
+    /// Capacity of the mounted XBOOTLDR partition
+    pub xbootldr_space: Option<Space>,
+    /// Configured or detected bootloader backend
+    pub backend: Option<Backend>,
+    /// Effective boot management policy
+    pub manage: Manage,
+    /// Why synchronization is skipped, when boot management doesn't apply
+    pub unmanaged: Option<String>,
+    /// Active kernel discovery patterns
+    pub kernel_patterns: Vec<String>,
+    /// Active bootloader asset patterns for the backend
+    pub asset_patterns: Vec<String>,
+    /// Bootloader assets in the active state
+    pub bootloader_assets: Vec<PathBuf>,
+    /// Kernels discovered in each retained state
+    pub kernels: Vec<Kernel>,
+    /// Loader entries that can no longer be booted
+    pub stale_entries: Vec<StaleEntry>,
+    /// Moss generated loader entries failing validation
+    pub invalid_entries: Vec<validate::Invalid>,
+    /// Files owned by moss per the partition manifests but no longer in use, removed by gc
+    pub orphaned: Vec<PathBuf>,
+    /// Configured mirror ESPs
+    pub mirrors: Vec<Mirror>,
+    /// Entries and loaders out of sync with the retained states, as a sync would change them
+    pub plan: Plan,
+}
+
+/// A configured mirror ESP
+#[derive(Debug, Serialize)]
+pub struct Mirror {
+    pub target: String,
+    /// Location of the mounted mirror, if it could be opened
+    pub mountpoint: Option<PathBuf>,
+    /// Capacity of the mounted mirror
+    pub space: Option<Space>,
+    /// Loader entries on the mirror that can no longer be booted
+    pub stale_entries: Vec<StaleEntry>,
+    /// Why the mirror couldn't be opened
+    pub error: Option<String>,
+}
+
+/// A kernel discovered within a retained state
+#[derive(Debug, Serialize)]
+pub struct Kernel {
+    pub state: state::Id,
+    pub version: String,
+    /// The loader entry booting this kernel, if present
+    pub entry: Option<PathBuf>,
+    /// Effective kernel command line of the loader entry
+    pub cmdline: Option<String>,
+}
+
+/// Query the boot status of the client's installation without modifying it
+pub fn status(client: &Client) -> Result<Status, Error> {
+    let install = &client.installation;
+    let config = configuration(install);
+
+    let manager = match blsforme::Manager::new(&config) {
+        Ok(manager) => Some(manager),
+        Err(e) => {
+            log::warn!("Unable to probe boot topology: {e}");
+            None
+        }
+    };
+    let (_mounts, mount_error) = match manager.as_ref().filter(|_| is_native(install)) {
+        Some(manager) => match manager.mount_partitions() {
+            Ok(mounts) => (Some(mounts), None),
+            Err(e) => (None, Some(e.to_string())),
+        },
+        None => (None, None),
+    };
+
+    let partitions = locate_partitions(install, manager.as_ref());
+
+    let mut status = Status {
+        firmware: None,
+        esp: None,
+        xbootldr: None,
+        boot_partition: None,
+        mountpoint: partitions.esp.clone(),
+        xbootldr_mountpoint: partitions.xbootldr.clone(),
+        mount_error,
+        esp_space: partitions.esp.as_deref().and_then(Space::of),
+        xbootldr_space: partitions.xbootldr.as_deref().and_then(Space::of),
+        backend: None,
+        manage: Manage::default(),
+        unmanaged: None,
+        kernel_patterns: vec![],
+        asset_patterns: vec![],
+        bootloader_assets: vec![],
+        kernels: vec![],
+        stale_entries: vec![],
+        invalid_entries: vec![],
+        orphaned: vec![],
+        mirrors: vec![],
+        plan: Plan::default(),
+    };
+
+    if let Some(manager) = &manager {
+        let env = manager.boot_environment();
+
+        match env.firmware {
+            blsforme::Firmware::UEFI => {
+                status.firmware = Some(Firmware::Uefi);
+                status.esp = env.esp().map(|path| path.to_path_buf());
+                status.xbootldr = env.xbootldr().map(|path| path.to_path_buf());
+            }
+            blsforme::Firmware::BIOS => {
+                status.firmware = Some(Firmware::Bios);
+                status.boot_partition = env.boot_partition().map(|path| path.to_path_buf());
+            }
+        }
+    }
+
+    let settings = Settings::load(&client.config);
+    status.kernel_patterns = settings.kernel_globs();
+    status.manage = manage_policy(client, &settings);
+
+    if let Some(id) = install.active_state {
+        let active = client.state_db.get(id)?;
+        let layouts = layouts_for_state(client, &active)?;
+        status.backend = match settings.backend {
+            Some(backend) => Some(backend),
+            None => Backend::detect(&layouts)?,
+        };
+        if let Some(backend) = status.backend {
+            status.unmanaged = managed(client, &settings, backend).err();
+            status.asset_patterns = settings.asset_globs(backend);
+            status.bootloader_assets = boot_files_from_new_state(install, &layouts, &settings.asset_patterns(backend)?);
+        }
+    }
+
+    let arch = active_architecture(client);
+    let entries = partitions
+        .all()
+        .map(|tree| own_entries(tree, arch))
+        .flatten_ok()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let states = retained_states(client)?;
+    let kernel_patterns = settings.kernel_patterns()?;
+    let os_release = read_os_release(&install.root, settings.strict_os_release.unwrap_or_default())?;
+    let schema = Schema::Blsforme {
+        os_release: &os_release,
+    };
+
+    for state in &states {
+        let layouts = layouts_for_state(client, state)?;
+        let kernels =
+            schema.discover_system_kernels(kernel_files_from_state(&layouts, &kernel_patterns).into_iter())?;
+
+        for kernel in kernels {
+            let entry = entries.iter().find(|entry| entry.matches(state.id, &kernel.version));
+
+            status.kernels.push(Kernel {
+                state: state.id,
+                version: kernel.version.clone(),
+                entry: entry.map(|entry| entry.path.clone()),
+                cmdline: entry.map(LoaderEntry::cmdline),
+            });
+        }
+    }
+
+    // Stale per the same states gc keeps entries for
+    let known = known_states(client)?;
+    status.stale_entries = gc::stale(&entries, &known);
+    for tree in partitions.all() {
+        status.invalid_entries.extend(validate::entries(tree, arch, |_| None)?);
+        status.orphaned.extend(
+            owner::Manifest::load_or_adopt(tree, os_id(&install.root).as_deref())?
+                .orphaned(tree, &entry::load_all(tree)?),
+        );
+    }
+
+    for target in settings.mirrors.iter().flatten() {
+        let mirror = mirror::open(&install.root, target).and_then(|mirror| {
+            let entries = own_entries(&mirror.path, arch)?;
+            Ok((mirror, entries))
+        });
+
+        status.mirrors.push(match mirror {
+            Ok((mirror, entries)) => Mirror {
+                target: target.to_string(),
+                space: Space::of(&mirror.path),
+                stale_entries: gc::stale(&entries, &known),
+                mountpoint: Some(mirror.path.clone()),
+                error: None,
+            },
+            Err(e) => Mirror {
+                target: target.to_string(),
+                mountpoint: None,
+                space: None,
+                stale_entries: vec![],
+                error: Some(e.to_string()),
+            },
+        });
+    }
+
+    if let Some(backend) = status.backend.filter(|_| status.unmanaged.is_none()) {
+        let mode = settings.mode.unwrap_or_default();
+        let snippet = match backend {
+            Backend::Grub => fs::read_to_string(install.root.join(grub::SNIPPET)).unwrap_or_default(),
+            Backend::SystemdBoot => String::new(),
+        };
+
+        // Drift is derived from the discovered entries, rather than planning a sync
+        let has_entry = |kernel: &Kernel| match (backend, mode) {
+            (Backend::SystemdBoot, Mode::Entries) => kernel.entry.is_some(),
+            (Backend::SystemdBoot, Mode::Uki) => partitions.kernels().is_some_and(|tree| {
+                tree.join(uki::DIR)
+                    .join(uki::file_name(kernel.state, &kernel.version, arch))
+                    .exists()
+            }),
+            (Backend::Grub, _) => snippet.contains(&format!("'{}'", grub::entry_id(kernel.state, &kernel.version))),
+        };
+
+        status.plan.backend = Some(backend);
+        status.plan.mode = (backend == Backend::SystemdBoot).then_some(mode);
+        status.plan.entries = status
+            .kernels
+            .iter()
+            .filter(|kernel| !has_entry(kernel))
+            .map(|kernel| PlannedEntry {
+                action: Action::Create,
+                state: kernel.state,
+                version: kernel.version.clone(),
+                path: None,
+            })
+            .collect();
+
+        if backend == Backend::SystemdBoot {
+            status
+                .plan
+                .entries
+                .extend(status.stale_entries.iter().cloned().map(stale_to_planned));
+
+            if let Some(esp) = &partitions.esp {
+                status.plan.loader = bootloader::plan(
+                    esp,
+                    &status.bootloader_assets,
+                    settings.loader_update.unwrap_or_default(),
+                )?;
+            }
+        }
+    }
+
+    Ok(status)
+}
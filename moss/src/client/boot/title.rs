@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Human readable boot menu titles, rendered from a small template
+//!
+//! Supported tokens are `{os}`, `{kernel}`, `{state}`, `{summary}`, `{description}` and `{date}`.
+
+use crate::State;
+
+/// Default title template
+pub const DEFAULT_TEMPLATE: &str = "{os} {kernel} (state {state}, {date}) {summary}";
+
+/// Maximum length of the state summary within a title, in characters
+const SUMMARY_LENGTH: usize = 40;
+
+/// Render the `template` for `kernel` of `state`
+pub fn render(template: &str, os: &str, kernel: &str, state: &State) -> String {
+    let summary = state.summary.as_deref().map(truncate).unwrap_or_default();
+    let description = state.description.as_deref().map(truncate).unwrap_or_default();
+
+    template
+        .replace("{os}", os)
+        .replace("{kernel}", kernel)
+        .replace("{state}", &state.id.to_string())
+        .replace("{summary}", &summary)
+        .replace("{description}", &description)
+        .replace("{date}", &state.created.format("%Y-%m-%d %H:%M").to_string())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Replace the `title` of the loader entry `contents`, inserting it if missing
+pub fn with_title(contents: &str, title: &str) -> String {
+    let line = format!("title {title}");
+    let mut replaced = false;
+
+    let mut lines = contents
+        .lines()
+        .map(|existing| {
+            if !replaced && existing.split_whitespace().next() == Some("title") {
+                replaced = true;
+                line.clone()
+            } else {
+                existing.to_owned()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if !replaced {
+        lines.insert(0, line);
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+/// Truncate a `summary` or description to its first line of at most [`SUMMARY_LENGTH`] characters
+fn truncate(summary: &str) -> String {
+    let line = summary.lines().next().unwrap_or_default().trim();
+
+    if line.chars().count() > SUMMARY_LENGTH {
+        format!(
+            "{}…",
+            line.chars().take(SUMMARY_LENGTH - 1).collect::<String>().trim_end()
+        )
+    } else {
+        line.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::state;
+
+    #[test]
+    fn render_title() {
+        let mut state = State {
+            id: state::Id::from(42),
+            summary: None,
+            description: None,
+            selections: vec![],
+            created: Utc.with_ymd_and_hms(2025, 3, 14, 9, 26, 53).unwrap(),
+            kind: state::Kind::Transaction,
+            pinned: false,
+            updated: None,
+            parent: None,
+        };
+
+        assert_eq!(
+            render(DEFAULT_TEMPLATE, "AerynOS", "6.12.9-1", &state),
+            "AerynOS 6.12.9-1 (state 42, 2025-03-14 09:26)"
+        );
+
+        state.summary = Some("Upgrade to GNOME 48 along with the rest of the desktop stack".to_owned());
+        assert_eq!(
+            render("{summary} [{state}]", "AerynOS", "6.12.9-1", &state),
+            "Upgrade to GNOME 48 along with the rest… [42]"
+        );
+
+        state.description = Some("last known good before NVIDIA 550\nkeep around".to_owned());
+        assert_eq!(
+            render("{os} ({description})", "AerynOS", "6.12.9-1", &state),
+            "AerynOS (last known good before NVIDIA 550)"
+        );
+    }
+
+    #[test]
+    fn replace_title() {
+        assert_eq!(
+            with_title("title AerynOS\nlinux /vmlinuz\n", "AerynOS (state 1)"),
+            "title AerynOS (state 1)\nlinux /vmlinuz\n"
+        );
+        assert_eq!(
+            with_title("linux /vmlinuz\n", "AerynOS"),
+            "title AerynOS\nlinux /vmlinuz\n"
+        );
+    }
+}
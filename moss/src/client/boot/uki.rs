@@ -0,0 +1,210 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Unified Kernel Image assembly via `ukify`
+//!
+//! UKIs are written to `EFI/Linux` on the ESP where systemd-boot discovers
+//! them automatically, so no type #1 loader entries are required.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use fs_err as fs;
+
+use super::{
+    arch::Arch,
+    cmdline::{self, Fragment},
+    Error,
+};
+use crate::state;
+
+/// systemd-stub pattern, relative to `/usr`
+pub const STUB_PATTERN: &str = "lib*/systemd/boot/efi/linux*.efi.stub";
+
+/// UKI directory, relative to the ESP
+pub const DIR: &str = "EFI/Linux";
+
+/// File name prefix for moss generated UKIs
+const PREFIX: &str = "moss-";
+
+/// Candidate `ukify` binaries, relative to the installation root
+const UKIFY: &[&str] = &["usr/bin/ukify", "usr/lib/systemd/ukify"];
+
+/// A UKI to assemble for a single kernel of a state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub state: state::Id,
+    pub version: String,
+    pub kernel: PathBuf,
+    pub initrds: Vec<PathBuf>,
+    /// Embedded kernel command line
+    pub cmdline: String,
+    /// Architecture of the installation, namespacing the file name
+    pub arch: Option<Arch>,
+}
+
+impl Image {
+    /// File name of the UKI within [`DIR`]
+    pub fn file_name(&self) -> String {
+        file_name(self.state, &self.version, self.arch)
+    }
+}
+
+/// File name of the UKI for kernel `version` of `state`, suffixed with `arch`
+pub fn file_name(state: state::Id, version: &str, arch: Option<Arch>) -> String {
+    let name = format!("{PREFIX}{state}-{version}.efi");
+
+    match arch {
+        Some(arch) => arch.file_name(&name),
+        None => name,
+    }
+}
+
+/// The state a moss generated UKI belongs to, derived from its file name
+pub fn state_id(file_name: &str) -> Option<state::Id> {
+    file_name
+        .strip_prefix(PREFIX)?
+        .strip_suffix(".efi")?
+        .split_once('-')
+        .and_then(|(id, _)| id.parse::<i32>().ok())
+        .map(state::Id::from)
+}
+
+/// Embedded command line for `state`, prefixed by `etc/kernel/cmdline` when present
+/// and followed by the user's [`cmdline`](super::cmdline) fragments
+pub fn cmdline(root: &Path, fragments: &[Fragment], state: state::Id) -> String {
+    let base = Fragment {
+        name: "cmdline".to_owned(),
+        snippet: fs::read_to_string(root.join("etc").join("kernel").join("cmdline")).unwrap_or_default(),
+    };
+
+    let (assembled, overrides) = cmdline::assemble(
+        [base]
+            .into_iter()
+            .chain(fragments.iter().cloned())
+            .chain([cmdline::state_fragment(state)]),
+    );
+
+    for r#override in overrides {
+        log::warn!("Kernel command line of state {state}: {override}");
+    }
+
+    cmdline::join(&assembled)
+}
+
+/// Locate `ukify` within the installation root
+pub fn ukify(root: &Path) -> Option<PathBuf> {
+    UKIFY.iter().map(|bin| root.join(bin)).find(|bin| bin.exists())
+}
+
+/// Assemble `image` into the `esp`, returning the UKI path
+///
+/// State roots are immutable, so an existing UKI is left as-is.
+pub fn build(
+    ukify: &Path,
+    stub: &Path,
+    os_release: Option<&Path>,
+    image: &Image,
+    esp: &Path,
+) -> Result<PathBuf, Error> {
+    let dir = esp.join(DIR);
+    let output = dir.join(image.file_name());
+
+    // UKIs predating architecture namespacing are adopted rather than rebuilt
+    let legacy = dir.join(file_name(image.state, &image.version, None));
+    if image.arch.is_some() && !output.exists() && legacy.exists() {
+        fs::rename(&legacy, &output)?;
+    }
+
+    if output.exists() {
+        return Ok(output);
+    }
+
+    fs::create_dir_all(&dir)?;
+
+    let mut command = Command::new(ukify);
+    command
+        .arg("build")
+        .arg(format!("--linux={}", image.kernel.display()))
+        .arg(format!("--stub={}", stub.display()))
+        .arg(format!("--cmdline={}", image.cmdline))
+        .arg(format!("--uname={}", image.version))
+        .arg(format!("--output={}", output.display()));
+    if let Some(os_release) = os_release {
+        command.arg(format!("--os-release=@{}", os_release.display()));
+    }
+    for initrd in &image.initrds {
+        command.arg(format!("--initrd={}", initrd.display()));
+    }
+
+    let status = command.status()?;
+    if !status.success() {
+        // Never leave a partial image behind for systemd-boot to find
+        let _ = fs::remove_file(&output);
+        return Err(Error::Ukify(output, status));
+    }
+
+    Ok(output)
+}
+
+/// Remove all moss generated UKIs of `arch` in the `esp` belonging to one of `removed`
+///
+/// UKIs of other architectures sharing the ESP are never touched.
+pub fn remove(esp: &Path, arch: Option<Arch>, removed: impl Fn(state::Id) -> bool) -> io::Result<Vec<PathBuf>> {
+    let dir = esp.join(DIR);
+
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut paths = vec![];
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let owned = arch.is_none() || Arch::of_file_name(name).map_or(true, |other| Some(other) == arch);
+
+        if owned && state_id(name).is_some_and(&removed) {
+            fs::remove_file(&path)?;
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_name_roundtrip() {
+        let image = Image {
+            state: state::Id::from(12),
+            version: "6.12.9-1".to_owned(),
+            kernel: "usr/lib/kernel/6.12.9-1/vmlinuz".into(),
+            initrds: vec![],
+            cmdline: "moss.fstx=12".to_owned(),
+            arch: None,
+        };
+
+        assert_eq!(image.file_name(), "moss-12-6.12.9-1.efi");
+        assert_eq!(state_id(&image.file_name()), Some(state::Id::from(12)));
+
+        let image = Image {
+            arch: Arch::from_package("aarch64"),
+            ..image
+        };
+        assert_eq!(image.file_name(), "moss-12-6.12.9-1-aa64.efi");
+        assert_eq!(state_id(&image.file_name()), Some(state::Id::from(12)));
+        assert_eq!(state_id("linux-6.12.9-1.efi"), None);
+    }
+}
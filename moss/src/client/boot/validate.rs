@@ -0,0 +1,216 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Validation of moss generated loader entries as written to the boot partitions
+//!
+//! systemd-boot silently ignores entries it can't make sense of, e.g. one whose
+//! command line was broken across lines by a user fragment. Entries are therefore
+//! read back after every sync and checked for the required keys, lines outside of
+//! the Boot Loader Specification, missing assets and the intended command line.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::Serialize;
+
+use super::{
+    arch::Arch,
+    entry::{LoaderEntry, STATE_PARAMETER},
+    rescue,
+};
+use crate::state;
+
+/// Keys of type #1 entries defined by the Boot Loader Specification
+const KEYS: &[&str] = &[
+    "title",
+    "version",
+    "machine-id",
+    "sort-key",
+    "linux",
+    "efi",
+    "initrd",
+    "options",
+    "devicetree",
+    "devicetree-overlay",
+    "architecture",
+];
+
+/// Keys every moss generated entry must carry
+const REQUIRED: &[&str] = &["title", "linux", "options"];
+
+/// A problem with a written loader entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "kind", content = "value")]
+pub enum Problem {
+    /// A required key is missing
+    MissingKey(String),
+    /// A line which isn't a known key, typically a fragment of a broken command line
+    UnknownLine(String),
+    /// A referenced asset is missing from the boot partition
+    MissingAsset(String),
+    /// Parameters of the intended command line missing from the `options`
+    Cmdline(Vec<String>),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::MissingKey(key) => write!(f, "missing `{key}`"),
+            Problem::UnknownLine(line) => write!(f, "unknown line {line:?}"),
+            Problem::MissingAsset(asset) => write!(f, "missing {asset}"),
+            Problem::Cmdline(missing) => write!(f, "command line lacks {}", missing.join(" ")),
+        }
+    }
+}
+
+/// A loader entry failing validation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Invalid {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.path.display())?;
+
+        for (idx, problem) in self.problems.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Validate all moss generated entries of `arch` within the boot partition `tree`
+///
+/// The command line of an entry is only checked if `expected` returns the
+/// parameters intended for its state, and never for the rescue entry.
+pub fn entries(
+    tree: &Path,
+    arch: Option<Arch>,
+    expected: impl Fn(state::Id) -> Option<Vec<String>>,
+) -> std::io::Result<Vec<Invalid>> {
+    let mut invalid = vec![];
+
+    for entry in super::entry::load_all(tree)?
+        .into_iter()
+        .filter(|entry| arch.map_or(true, |arch| arch.owns(entry)))
+    {
+        let contents = fs::read_to_string(&entry.path)?;
+        // The state parameter may have been broken out of the `options` line
+        let Some(state) = contents
+            .split_whitespace()
+            .find_map(|param| param.strip_prefix(STATE_PARAMETER)?.strip_prefix('='))
+            .and_then(|id| id.parse::<i32>().ok())
+            .map(state::Id::from)
+        else {
+            continue;
+        };
+
+        let expected = (!rescue::is_rescue(&entry)).then(|| expected(state)).flatten();
+        let problems = check(tree, &entry, &contents, expected.as_deref());
+
+        if !problems.is_empty() {
+            invalid.push(Invalid {
+                path: entry.path,
+                problems,
+            });
+        }
+    }
+
+    Ok(invalid)
+}
+
+/// Validate the parsed `entry` against its raw `contents` and the boot partition `tree`
+fn check(tree: &Path, entry: &LoaderEntry, contents: &str, expected: Option<&[String]>) -> Vec<Problem> {
+    let keys = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| (line, line.split_whitespace().next().unwrap_or_default()))
+        .collect::<Vec<_>>();
+
+    let mut problems = REQUIRED
+        .iter()
+        .filter(|required| !keys.iter().any(|(_, key)| key == *required))
+        .map(|key| Problem::MissingKey((*key).to_owned()))
+        .collect::<Vec<_>>();
+
+    problems.extend(
+        keys.iter()
+            .filter(|(_, key)| !KEYS.contains(key))
+            .map(|(line, _)| Problem::UnknownLine((*line).to_owned())),
+    );
+
+    problems.extend(
+        entry
+            .assets()
+            .filter(|asset| !tree.join(asset).exists())
+            .map(|asset| Problem::MissingAsset(asset.to_owned())),
+    );
+
+    if let Some(expected) = expected {
+        let cmdline = entry.cmdline();
+        let params = cmdline.split_whitespace().collect::<Vec<_>>();
+        let missing = expected
+            .iter()
+            .filter(|param| !params.contains(&param.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            problems.push(Problem::Cmdline(missing));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn validate_entries() {
+        let tree = Scratch::new(&[("EFI/os/6.12.9-1/vmlinuz", "")]);
+        fs::create_dir_all(tree.join("loader/entries")).unwrap();
+
+        fs::write(
+            tree.join("loader/entries/good.conf"),
+            "title AerynOS\nlinux /EFI/os/6.12.9-1/vmlinuz\noptions root=UUID=abcd rw quiet splash moss.fstx=2\n",
+        )
+        .unwrap();
+        // A user fragment carrying a newline splits the command line
+        fs::write(
+            tree.join("loader/entries/broken.conf"),
+            "title AerynOS\nlinux /EFI/os/6.12.9-1/vmlinuz\ninitrd /EFI/os/6.12.9-1/initrd\n\
+             options root=UUID=abcd rw quiet\nsplash moss.fstx=2\n",
+        )
+        .unwrap();
+        fs::write(tree.join("loader/entries/windows.conf"), "title Windows\n").unwrap();
+
+        let expected = |_| Some(vec!["root=UUID=abcd".to_owned(), "splash".to_owned()]);
+        let invalid = entries(&tree, None, expected).unwrap();
+
+        assert_eq!(
+            invalid,
+            vec![Invalid {
+                path: tree.join("loader/entries/broken.conf"),
+                problems: vec![
+                    Problem::UnknownLine("splash moss.fstx=2".to_owned()),
+                    Problem::MissingAsset("EFI/os/6.12.9-1/initrd".to_owned()),
+                    Problem::Cmdline(vec!["splash".to_owned()]),
+                ],
+            }]
+        );
+    }
+}
@@ -4,20 +4,24 @@
 
 //! Installation-specific code for several core moss operations
 
-use std::time::{Duration, Instant};
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
 use tui::{
     dialoguer::{theme::ColorfulTheme, Confirm},
     pretty::autoprint_columns,
+    Styled,
 };
 
 use crate::{
-    client::{self, Client},
+    client::{self, boot, manifest, Client},
     package::{self, Flags},
     registry::transaction,
     runtime,
-    state::Selection,
+    state::{Manifest, ManifestError, Reason, Selection},
     Package, Provider,
 };
 
@@ -108,25 +112,170 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
             Some(id) if !client.is_ephemeral() => client.state_db.get(id)?.selections,
             _ => vec![],
         };
-        let missing_selections = missing.iter().map(|p| Selection {
-            package: p.id.clone(),
-            // Package is explicit if it was one of the input
-            // packages provided by the user
-            explicit: input.iter().any(|id| *id == p.id),
-            reason: None,
+        // Package is explicit if it was one of the input
+        // packages provided by the user
+        let is_input = |id: &package::Id| input.iter().any(|i| i == id);
+        let missing_selections = missing.iter().map(|p| {
+            if is_input(&p.id) {
+                Selection::explicit(p.id.clone()).reason(Reason::Requested {
+                    by_command: "install".to_owned(),
+                })
+            } else {
+                let selection = Selection::transitive(p.id.clone());
+
+                match dependency_of(&tx, &resolved, &p.id) {
+                    Some(name) => selection.reason(Reason::DependencyOf(name)),
+                    None => selection,
+                }
+            }
+        });
+        // Requesting an installed dependency promotes it to explicit
+        let previous_selections = previous_selections.into_iter().map(|s| Selection {
+            explicit: s.explicit || is_input(&s.package),
+            ..s
         });
 
         missing_selections.chain(previous_selections).collect::<Vec<_>>()
     };
 
     // Perfect, apply state.
-    client.new_state(&new_state_pkgs, "Install")?;
+    timing.boot = client.new_state(&new_state_pkgs)?.map(|(_, outcome)| outcome);
+
+    timing.blit = instant.elapsed();
+
+    Ok(timing)
+}
+
+/// Recreate the state described by `manifest`, with its packages resolved by
+/// [`manifest::resolve`]
+///
+/// The packages of the manifest replace all selections of the active state.
+pub fn install_manifest(
+    client: &mut Client,
+    manifest: &Manifest,
+    allow_newer: bool,
+    yes: bool,
+) -> Result<Timing, Error> {
+    let mut timing = Timing::default();
+    let mut instant = Instant::now();
+
+    let resolved = manifest::resolve(client, manifest, allow_newer)?;
+
+    for replaced in &resolved {
+        if let Some(requested) = &replaced.replaces {
+            eprintln!(
+                "{} | {requested} is no longer available, using {}-{}-{}",
+                "Warning".yellow(),
+                replaced.package.meta.version_identifier,
+                replaced.package.meta.source_release,
+                replaced.package.meta.build_release
+            );
+        }
+    }
+
+    let previous_selections = match client.installation.active_state {
+        Some(id) if !client.is_ephemeral() => client.state_db.get(id)?.selections,
+        _ => vec![],
+    };
+    let previous = previous_selections
+        .iter()
+        .map(|selection| &selection.package)
+        .collect::<BTreeSet<_>>();
+    let selected = resolved
+        .iter()
+        .map(|resolved| &resolved.package.id)
+        .collect::<BTreeSet<_>>();
+
+    let missing = resolved
+        .iter()
+        .map(|resolved| &resolved.package)
+        .filter(|package| !previous.contains(&package.id))
+        .collect::<Vec<_>>();
+    let removed = previous
+        .iter()
+        .filter(|id| !selected.contains(*id))
+        .filter_map(|id| {
+            Some(Package {
+                id: (*id).clone(),
+                meta: client.install_db.get(id).ok()?,
+                flags: Flags::default(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    timing.resolve = instant.elapsed();
+
+    // Explicit flags and reasons count as well, so a manifest only changing those
+    // still records a new state
+    let by_package = |a: &&Selection, b: &&Selection| a.package.cmp(&b.package);
+    let mut previous_sorted = previous_selections.iter().collect::<Vec<_>>();
+    previous_sorted.sort_by(by_package);
+    let mut selected_sorted = resolved.iter().map(|resolved| &resolved.selection).collect::<Vec<_>>();
+    selected_sorted.sort_by(by_package);
+
+    if previous_sorted == selected_sorted {
+        println!("The active state already matches the manifest");
+        return Ok(timing);
+    }
+
+    if !missing.is_empty() {
+        println!("The following package(s) will be installed:");
+        println!();
+        autoprint_columns(&missing);
+        println!();
+    }
+    if !removed.is_empty() {
+        println!("The following package(s) will be removed:");
+        println!();
+        autoprint_columns(&removed);
+        println!();
+    }
+    if missing.is_empty() && removed.is_empty() {
+        println!("The explicit selections of the installed packages will be updated");
+        println!();
+    }
+
+    let result = if yes {
+        true
+    } else {
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(" Do you wish to continue? ")
+            .default(false)
+            .interact()?
+    };
+    if !result {
+        return Err(Error::Cancelled);
+    }
+
+    instant = Instant::now();
+
+    runtime::block_on(client.cache_packages(&missing))?;
+
+    timing.fetch = instant.elapsed();
+    instant = Instant::now();
+
+    let selections = resolved
+        .into_iter()
+        .map(|resolved| resolved.selection)
+        .collect::<Vec<_>>();
+    timing.boot = client.new_state(&selections)?.map(|(_, outcome)| outcome);
 
     timing.blit = instant.elapsed();
 
     Ok(timing)
 }
 
+/// Name of the package among `resolved` which pulled `package` into the transaction
+fn dependency_of(
+    tx: &transaction::Transaction<'_>,
+    resolved: &[Package],
+    package: &package::Id,
+) -> Option<package::Name> {
+    let parent = tx.dependency_of(package)?;
+
+    resolved.iter().find(|p| p.id == *parent).map(|p| p.meta.name.clone())
+}
+
 /// Resolves the package arguments as valid input packages. Returns an error
 /// if any args are invalid.
 fn resolve_input(pkgs: &[&str], client: &Client) -> Result<Vec<package::Id>, Error> {
@@ -164,6 +313,8 @@ pub struct Timing {
     pub resolve: Duration,
     pub fetch: Duration,
     pub blit: Duration,
+    /// Outcome of the boot synchronization, unless ephemeral
+    pub boot: Option<boot::SyncOutcome>,
 }
 
 /// Error's specific to installation operations
@@ -181,6 +332,14 @@ pub enum Error {
     #[error("no package found: {0}")]
     NoPackage(String),
 
+    /// The manifest couldn't be parsed
+    #[error("read manifest")]
+    ReadManifest(#[from] ManifestError),
+
+    /// The manifest couldn't be resolved
+    #[error("manifest")]
+    Manifest(#[from] manifest::Error),
+
     /// A transaction specific error occurred
     #[error("transaction")]
     Transaction(#[from] transaction::Error),
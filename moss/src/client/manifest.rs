@@ -0,0 +1,161 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Export of states to portable [`Manifest`]s, and their resolution on any machine
+//!
+//! Every package of a manifest is pinned by its hash, resolved against the cached and
+//! repository packages. With `allow_newer` a package whose hash is no longer available
+//! is replaced by the newest available package of the same name and architecture,
+//! provided it's not an older release.
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::{
+    client::Client,
+    db,
+    package::{self, Flags},
+    state::{self, Manifest, ManifestPackage, ManifestRepository, Selection, MANIFEST_VERSION},
+    Package,
+};
+
+/// A package of a manifest resolved to an available package
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub package: Package,
+    /// Selection of the package, as recorded in the manifest
+    pub selection: Selection,
+    /// The manifest package, if replaced by a newer release
+    pub replaces: Option<ManifestPackage>,
+}
+
+/// Export the state `id` to a manifest
+pub fn export(client: &Client, id: state::Id) -> Result<Manifest, Error> {
+    let state = client.state_db.get(id).map_err(|error| match error {
+        db::Error::RowNotFound => Error::NoState(id),
+        error => Error::DB(error),
+    })?;
+
+    let packages = state
+        .selections
+        .iter()
+        .map(|selection| {
+            let meta = client
+                .install_db
+                .get(&selection.package)
+                .map_err(|_| Error::MissingMetadata(selection.package.clone()))?;
+
+            Ok(ManifestPackage {
+                name: meta.name,
+                version: meta.version_identifier,
+                source_release: meta.source_release,
+                build_release: meta.build_release,
+                architecture: meta.architecture,
+                hash: selection.package.clone(),
+                explicit: selection.explicit,
+                reason: selection.reason.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let repositories = client
+        .repositories
+        .list()
+        .filter(|(_, repository)| repository.active)
+        .map(|(id, repository)| ManifestRepository {
+            id: id.to_string(),
+            uri: repository.uri.to_string(),
+            index: client.repositories.index_digest(id),
+        })
+        .collect();
+
+    Ok(Manifest {
+        version: MANIFEST_VERSION,
+        state: state.id,
+        summary: state.summary,
+        created: state.created.to_rfc3339(),
+        repositories,
+        packages,
+    })
+}
+
+/// Resolve every package of `manifest` to an available package
+///
+/// Packages selected by the active state are always available, all others must be
+/// downloadable. All unavailable packages are reported at once.
+pub fn resolve(client: &Client, manifest: &Manifest, allow_newer: bool) -> Result<Vec<Resolved>, Error> {
+    let active = match client.installation.active_state {
+        Some(id) if !client.is_ephemeral() => client.state_db.get(id)?.selections,
+        _ => vec![],
+    };
+    let is_available = |package: &Package| {
+        package.meta.uri.is_some() || active.iter().any(|selection| selection.package == package.id)
+    };
+
+    let mut resolved = vec![];
+    let mut unavailable = vec![];
+
+    for requested in &manifest.packages {
+        let exact = client.registry.by_id(&requested.hash).find(is_available);
+        let newer = || {
+            client
+                .registry
+                .by_name(&requested.name, Flags::new().with_available())
+                .filter(|package| {
+                    package.meta.architecture == requested.architecture
+                        && package.meta.source_release >= requested.source_release
+                })
+                .find(is_available)
+        };
+
+        let (package, replaces) = match exact {
+            Some(package) => (package, None),
+            None => match allow_newer.then(newer).flatten() {
+                Some(package) => (package, Some(requested.clone())),
+                None => {
+                    unavailable.push(requested);
+                    continue;
+                }
+            },
+        };
+
+        let selection = Selection {
+            package: package.id.clone(),
+            explicit: requested.explicit,
+            reason: requested.reason.clone(),
+        };
+
+        resolved.push(Resolved {
+            package,
+            selection,
+            replaces,
+        });
+    }
+
+    if !unavailable.is_empty() {
+        let packages = unavailable.iter().join(", ");
+
+        return Err(if allow_newer {
+            Error::NoRelease(packages)
+        } else {
+            Error::Unavailable(packages)
+        });
+    }
+
+    Ok(resolved)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("state {0} doesn't exist")]
+    NoState(state::Id),
+    #[error("no metadata found for package {0}")]
+    MissingMetadata(package::Id),
+    #[error("packages of the manifest are no longer available, use --allow-newer to accept newer releases: {0}")]
+    Unavailable(String),
+    #[error("no release of these packages of the manifest is available: {0}")]
+    NoRelease(String),
+    #[error("db")]
+    DB(#[from] db::Error),
+}
@@ -45,9 +45,14 @@ use crate::{
 pub mod boot;
 pub mod cache;
 pub mod install;
+pub mod manifest;
 mod postblit;
 pub mod prune;
-mod verify;
+pub mod resolve;
+pub mod rollback;
+pub mod selections;
+pub mod usage;
+pub mod verify;
 
 /// A Client is a connection to the underlying package management systems
 pub struct Client {
@@ -75,6 +80,16 @@ pub struct Client {
 
     /// Operational scope (real systems, ephemeral, etc)
     scope: Scope,
+
+    /// One-off override of the installation's boot management policy
+    boot_manage: Option<boot::Manage>,
+
+    /// Summary of all states recorded by this client, replacing generated ones
+    summary: Option<String>,
+
+    /// Skip system triggers and boot synchronization when applying states, e.g. for
+    /// offline activations
+    skip_triggers: bool,
 }
 
 impl Client {
@@ -122,9 +137,32 @@ impl Client {
             state_db,
             layout_db,
             scope: Scope::Stateful,
+            boot_manage: None,
+            summary: None,
+            skip_triggers: false,
         })
     }
 
+    /// Override the installation's boot management policy for this client
+    pub fn with_boot_manage(self, boot_manage: Option<boot::Manage>) -> Self {
+        Self { boot_manage, ..self }
+    }
+
+    /// Record states with the given `summary` rather than generating one
+    pub fn with_summary(self, summary: Option<String>) -> Self {
+        Self { summary, ..self }
+    }
+
+    /// Apply states without running system triggers nor synchronizing boot entries
+    pub fn with_skip_triggers(self, skip_triggers: bool) -> Self {
+        Self { skip_triggers, ..self }
+    }
+
+    /// The one-off override of the boot management policy, if any
+    pub fn boot_manage(&self) -> Option<boot::Manage> {
+        self.boot_manage
+    }
+
     /// Returns `true` if this is an ephemeral client
     pub fn is_ephemeral(&self) -> bool {
         matches!(self.scope, Scope::Ephemeral { .. })
@@ -135,6 +173,21 @@ impl Client {
         install(self, packages, yes)
     }
 
+    /// Recreate the state of a manifest via [`install::install_manifest`]
+    pub fn install_manifest(
+        &mut self,
+        manifest: &state::Manifest,
+        allow_newer: bool,
+        yes: bool,
+    ) -> Result<install::Timing, install::Error> {
+        install::install_manifest(self, manifest, allow_newer, yes)
+    }
+
+    /// Export the state `id` to a portable manifest, as described by [`manifest`]
+    pub fn export_state(&self, id: state::Id) -> Result<state::Manifest, manifest::Error> {
+        manifest::export(self, id)
+    }
+
     /// Transition to an ephemeral client that doesn't record state changes
     /// and blits to a different root.
     ///
@@ -188,23 +241,58 @@ impl Client {
         Ok(())
     }
 
+    /// Resolve the `reference` to a recorded state, as described by [`resolve`]
+    pub fn resolve_state(&self, reference: state::Reference) -> Result<state::Id, resolve::Error> {
+        resolve::resolve(&self.state_db, self.installation.active_state, reference)
+    }
+
+    /// Compare the installed files of the `state`, or of the active state, against
+    /// its layouts, as described by [`verify::check`]
+    pub fn verify_files(&self, state: Option<state::Id>) -> Result<verify::Report, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        verify::check(self, state)
+    }
+
+    /// Refcounted asset usage of all states, as described by [`usage`]
+    pub fn usage(&self) -> Result<usage::Usage, Error> {
+        Ok(usage::Usage::load(
+            &self.state_db.all()?,
+            &self.layout_db,
+            &self.installation,
+        )?)
+    }
+
+    /// Asset usage of the state `id` alone, without sizing the assets of any other state
+    pub fn state_usage(&self, id: state::Id) -> Result<usage::Sizes, Error> {
+        let usage = usage::Usage::load_state(id, &self.state_db.all()?, &self.layout_db, &self.installation)?;
+
+        Ok(usage.state(id))
+    }
+
+    /// Re-derive which selections of all states were explicitly requested, as
+    /// described by [`selections`]
+    pub fn repair_selections(&self, dry_run: bool) -> Result<Vec<selections::Change>, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        selections::repair(self, dry_run)
+    }
+
     /// Prune states with the provided [`prune::Strategy`].
     ///
     /// This allows automatic removal of unused states (and their associated assets)
-    /// from the disk, acting as a garbage collection facility.
-    pub fn prune(&self, strategy: prune::Strategy, yes: bool) -> Result<(), Error> {
+    /// from the disk, acting as a garbage collection facility. With `dry_run` the
+    /// removals are only printed.
+    pub fn prune(&self, strategy: prune::Strategy, yes: bool, dry_run: bool) -> Result<(), Error> {
         if self.scope.is_ephemeral() {
             return Err(Error::EphemeralProhibitedOperation);
         }
 
-        prune(
-            strategy,
-            &self.state_db,
-            &self.install_db,
-            &self.layout_db,
-            &self.installation,
-            yes,
-        )?;
+        prune(self, strategy, yes, dry_run)?;
         Ok(())
     }
 
@@ -225,62 +313,40 @@ impl Client {
         Ok(metadata)
     }
 
-    /// Activates the provided state and runs system triggers once applied.
-    ///
-    /// The current state gets archived.\
-    /// Returns the old state that was archived.
-    pub fn activate_state(&self, id: state::Id, skip_triggers: bool) -> Result<state::Id, Error> {
-        // Fetch the new state
-        let new = self.state_db.get(id).map_err(|_| Error::StateDoesntExist(id))?;
-
-        // Get old (current) state
-        let Some(old) = self.installation.active_state else {
-            return Err(Error::NoActiveState);
-        };
-
-        if new.id == old {
-            return Err(Error::StateAlreadyActive(id));
-        }
-
-        let staging_dir = self.installation.staging_dir();
-
-        // Ensure staging dir exists
-        if !staging_dir.exists() {
-            fs::create_dir(&staging_dir)?;
-        }
-
-        // Move new (archived) state to staging
-        fs::rename(self.installation.root_path(new.id.to_string()), &staging_dir)?;
-
-        // Promote staging
-        self.promote_staging()?;
-
-        // Archive old state
-        self.archive_state(old)?;
-
-        // Build VFS from new state selections
-        // to build triggers from
-        let fstree = self.vfs(new.selections.iter().map(|selection| &selection.package))?;
-
-        if skip_triggers {
-            return Ok(old);
-        }
-
-        // Run system triggers
-        let sys_triggers = postblit::triggers(TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-        for trigger in sys_triggers {
-            trigger.execute()?;
+    /// Roll back to the `target` state, or the one preceding the active state, by
+    /// recording a copy of it as a new state
+    pub fn rollback(&self, target: Option<state::Id>, fetch: bool) -> Result<rollback::Rollback, rollback::Error> {
+        if self.scope.is_ephemeral() {
+            return Err(rollback::Error::Ephemeral);
         }
 
-        Ok(old)
+        rollback::rollback(self, target, fetch)
     }
 
     /// Create a new recorded state from the provided packages
     /// provided packages and write that state ID to the installation
     /// Then blit the filesystem, promote it, finally archiving the active ID
     ///
-    /// Returns `None` if the client is ephemeral
-    pub fn new_state(&self, selections: &[Selection], summary: impl ToString) -> Result<Option<State>, Error> {
+    /// The state is summarized by its changes relative to the active state, as
+    /// generated by [`state::summarize`], unless overridden via [`Self::with_summary`].
+    ///
+    /// Returns `None` if the client is ephemeral, otherwise the new state and
+    /// the outcome of its boot synchronization
+    pub fn new_state(&self, selections: &[Selection]) -> Result<Option<(State, boot::SyncOutcome)>, Error> {
+        self.new_state_with_kind(selections, None, None, state::Kind::Transaction)
+    }
+
+    /// Create a new recorded state of `kind`, as [`Self::new_state`] does
+    ///
+    /// The `summary` replaces the generated one, while the `description` precedes
+    /// the generated list of changes.
+    pub fn new_state_with_kind(
+        &self,
+        selections: &[Selection],
+        summary: Option<&str>,
+        description: Option<&str>,
+        kind: state::Kind,
+    ) -> Result<Option<(State, boot::SyncOutcome)>, Error> {
         let _guard = signal::ignore([Signal::SIGINT])?;
         let _fd = signal::inhibit(
             vec!["shutdown", "sleep", "idle", "handle-lid-switch"],
@@ -291,18 +357,53 @@ impl Client {
 
         let old_state = self.installation.active_state;
 
-        let fstree = self.blit_root(selections.iter().map(|s| &s.package))?;
-
         match &self.scope {
             Scope::Stateful => {
-                // Add to db
-                let state = self.state_db.add(selections, Some(&summary.to_string()), None)?;
+                if let Some((incomplete, _)) = self.state_db.incomplete()?.pop() {
+                    return Err(Error::IncompleteState(incomplete.id));
+                }
 
-                self.apply_stateful_blit(fstree, &state, old_state)?;
+                let parent = old_state.map(|id| self.state_db.get(id)).transpose()?;
+                let (generated, changes) =
+                    state::summarize(parent.as_ref(), selections, |id| self.install_db.get(id).ok());
+
+                let summary = self.summary.as_deref().or(summary).unwrap_or(&generated);
+                let description = [description, changes.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+
+                // Recorded ahead of the transaction, so an interrupted transaction
+                // leaves an incomplete state behind
+                let state = self.state_db.add_incomplete(
+                    selections,
+                    Some(summary),
+                    (!description.is_empty()).then_some(description.as_str()),
+                    kind,
+                    old_state,
+                )?;
+
+                // Failing before promotion leaves the active state intact, so there's
+                // nothing to resume
+                let id = state.id;
+                let promoted = self
+                    .stage_state(state, kind)
+                    .and_then(|(fstree, state)| Ok((fstree, self.complete_state(state)?)));
+                let (fstree, state) = match promoted {
+                    Ok(promoted) => promoted,
+                    Err(error) => {
+                        if let Err(abandon) = self.state_db.abandon(id) {
+                            log::warn!("Failed to abandon state {id}: {abandon}");
+                        }
+                        return Err(error);
+                    }
+                };
 
-                Ok(Some(state))
+                Ok(Some(self.finish_state(&fstree, state, old_state)?))
             }
             Scope::Ephemeral { blit_root } => {
+                let fstree = self.blit_root(selections.iter().map(|s| &s.package))?;
                 self.apply_ephemeral_blit(fstree, blit_root)?;
 
                 Ok(None)
@@ -310,6 +411,99 @@ impl Client {
         }
     }
 
+    /// Retry the transaction of the interrupted state, blitting it again from its
+    /// recorded selections
+    ///
+    /// Returns the completed state and the outcome of its boot synchronization.
+    pub fn resume_state(&self) -> Result<(State, boot::SyncOutcome), Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        let _guard = signal::ignore([Signal::SIGINT])?;
+        let _fd = signal::inhibit(
+            vec!["shutdown", "sleep", "idle", "handle-lid-switch"],
+            "moss".into(),
+            "Resuming interrupted state".into(),
+            "block".into(),
+        );
+
+        let Some((state, kind)) = self.state_db.incomplete()?.pop() else {
+            return Err(Error::NoIncompleteState);
+        };
+
+        // Once promoted, the root already carries the id of the interrupted state
+        // and the tree of the previously active state was archived, or is lost
+        let old_state = self.installation.active_state.filter(|id| *id != state.id);
+
+        let (fstree, state) = self.stage_state(state, kind)?;
+        let state = self.complete_state(state)?;
+        self.finish_state(&fstree, state, old_state)
+    }
+
+    /// The state left incomplete by an interrupted transaction, if any
+    pub fn incomplete_state(&self) -> Result<Option<State>, Error> {
+        if self.scope.is_ephemeral() {
+            return Ok(None);
+        }
+
+        Ok(self.state_db.incomplete()?.pop().map(|(state, _)| state))
+    }
+
+    /// Give up on the interrupted state, releasing its selections
+    pub fn discard_state(&self) -> Result<State, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        let Some((state, _)) = self.state_db.incomplete()?.pop() else {
+            return Err(Error::NoIncompleteState);
+        };
+
+        Ok(self.state_db.abandon(state.id)?)
+    }
+
+    /// Blit and stage the incomplete `state`, to be completed as a state of `kind`
+    ///
+    /// Nothing of the installation root is touched yet, so a failed transaction
+    /// leaves the active state intact.
+    fn stage_state(&self, state: State, kind: state::Kind) -> Result<(vfs::Tree<PendingFile>, State), Error> {
+        let fstree = self.blit_root(state.selections.iter().map(|s| &s.package))?;
+
+        // Boot entries are generated for the state as it will be once completed
+        let pending = State { kind, ..state };
+        self.stage_blit(&fstree, &pending)?;
+
+        Ok((fstree, pending))
+    }
+
+    /// Promote the staged `state`, completing it as its tree is live from here on
+    ///
+    /// The state keeps its id throughout, as recorded in the blitted tree and
+    /// referenced by its boot entries via `moss.fstx=`.
+    fn complete_state(&self, state: State) -> Result<State, Error> {
+        self.promote_staging()?;
+
+        Ok(self.state_db.complete(state.id)?)
+    }
+
+    /// Finish the transaction of the completed `state`, archiving `old_state` and
+    /// running system triggers and boot synchronization
+    ///
+    /// The state stays complete and active when any of these fail, which is
+    /// reported as [`Error::UnfinishedState`].
+    fn finish_state(
+        &self,
+        fstree: &vfs::Tree<PendingFile>,
+        state: State,
+        old_state: Option<state::Id>,
+    ) -> Result<(State, boot::SyncOutcome), Error> {
+        match self.finish_blit(fstree, &state, old_state) {
+            Ok(outcome) => Ok((state, outcome)),
+            Err(error) => Err(Error::UnfinishedState(state.id, Box::new(error))),
+        }
+    }
+
     /// Apply all triggers with the given scope, wrapping with a progressbar.
     fn apply_triggers(scope: TriggerScope<'_>, fstree: &vfs::Tree<PendingFile>) -> Result<(), postblit::Error> {
         let triggers = postblit::triggers(scope, fstree)?;
@@ -334,21 +528,49 @@ impl Client {
         Ok(())
     }
 
+    /// Apply the blitted `fstree` of `state` to the installation root, returning
+    /// the outcome of its boot synchronization
     pub fn apply_stateful_blit(
         &self,
         fstree: vfs::Tree<PendingFile>,
         state: &State,
         old_state: Option<state::Id>,
-    ) -> Result<(), Error> {
+    ) -> Result<boot::SyncOutcome, Error> {
+        self.stage_blit(&fstree, state)?;
+        self.promote_blit(&fstree, state, old_state)
+    }
+
+    /// Prepare the staging tree of `state` for promotion
+    fn stage_blit(&self, fstree: &vfs::Tree<PendingFile>, state: &State) -> Result<(), Error> {
         record_state_id(&self.installation.staging_dir(), state.id)?;
         record_os_release(&self.installation.staging_dir())?;
 
         create_root_links(&self.installation.isolation_dir())?;
-        Self::apply_triggers(TriggerScope::Transaction(&self.installation, &self.scope), &fstree)?;
+        Self::apply_triggers(TriggerScope::Transaction(&self.installation, &self.scope), fstree)?;
 
+        Ok(())
+    }
+
+    /// Promote the staging tree of `state` to the installation root
+    fn promote_blit(
+        &self,
+        fstree: &vfs::Tree<PendingFile>,
+        state: &State,
+        old_state: Option<state::Id>,
+    ) -> Result<boot::SyncOutcome, Error> {
         // Staging is only used with [`Scope::Stateful`]
         self.promote_staging()?;
 
+        self.finish_blit(fstree, state, old_state)
+    }
+
+    /// Finish the promoted tree of `state`, archiving the tree of `old_state`
+    fn finish_blit(
+        &self,
+        fstree: &vfs::Tree<PendingFile>,
+        state: &State,
+        old_state: Option<state::Id>,
+    ) -> Result<boot::SyncOutcome, Error> {
         // Now we got it staged, we need working rootfs
         create_root_links(&self.installation.root)?;
 
@@ -356,12 +578,14 @@ impl Client {
             self.archive_state(id)?;
         }
 
-        // At this point we're allowed to run system triggers
-        Self::apply_triggers(TriggerScope::System(&self.installation, &self.scope), &fstree)?;
+        if self.skip_triggers {
+            return Ok(boot::SyncOutcome::Skipped(boot::SkipReason::TriggersSkipped));
+        }
 
-        boot::synchronize(self, state)?;
+        // At this point we're allowed to run system triggers
+        Self::apply_triggers(TriggerScope::System(&self.installation, &self.scope), fstree)?;
 
-        Ok(())
+        Ok(boot::synchronize(self, state)?)
     }
 
     pub fn apply_ephemeral_blit(&self, fstree: vfs::Tree<PendingFile>, blit_root: &Path) -> Result<(), Error> {
@@ -990,6 +1214,14 @@ pub enum Error {
     StateAlreadyActive(state::Id),
     #[error("state {0} doesn't exist")]
     StateDoesntExist(state::Id),
+    #[error("the transaction of state {0} was interrupted, run `moss state resume` or `moss state discard` first")]
+    IncompleteState(state::Id),
+    #[error("no interrupted state to resume or discard")]
+    NoIncompleteState,
+    #[error("state {0} is active, but finishing its transaction failed")]
+    UnfinishedState(state::Id, #[source] Box<Error>),
+    #[error("no tree of state {0} found on disk")]
+    MissingStateTree(state::Id),
     #[error("No metadata found for package {0:?}")]
     MissingMetadata(package::Id),
     #[error("Ephemeral client not allowed on installation root")]
@@ -1025,3 +1257,55 @@ pub enum Error {
     #[error("ignore signals during blit")]
     BlitSignalIgnore(#[from] signal::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        ops::Deref,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use fs_err as fs;
+
+    /// Scratch tree beneath the temporary directory, removed on drop so a
+    /// panicking test doesn't leave it behind
+    pub struct Scratch(PathBuf);
+
+    impl Scratch {
+        /// Create a uniquely named scratch tree containing the given `(file, contents)` pairs
+        pub fn new(files: &[(&str, &str)]) -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+            let root = std::env::temp_dir().join(format!(
+                "moss-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+
+            for (file, contents) in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+
+            Self(root)
+        }
+    }
+
+    impl Deref for Scratch {
+        type Target = Path;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
@@ -8,14 +8,16 @@
 //! system states (i.e. historical snapshots) that cleans up database entries
 //! and assets on disk by way of refcounting.
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeSet;
 use std::{
     io,
     path::{Path, PathBuf},
 };
 
+use chrono::{DateTime, Datelike, Duration, Utc};
 use fs_err as fs;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use tui::{
@@ -23,126 +25,260 @@ use tui::{
     pretty::autoprint_columns,
 };
 
-use crate::{client::cache, db, package, state, Installation, State};
+use crate::{
+    client::{boot, cache, usage, Client},
+    db, package, state, Installation, Package, State,
+};
+
+/// Number of states kept by default, including the active state
+pub const DEFAULT_KEEP: u64 = 10;
 
 /// The prune strategy for removing old states
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Strategy {
-    /// Keep the most recent N states, remove the rest
-    KeepRecent { keep: u64, include_newer: bool },
+    /// Keep the states retained by the [`Policy`], remove the rest
+    Retain(Policy),
     /// Removes a specific state
     Remove(state::Id),
 }
 
+/// State retention policy, loaded from `prune.yaml` and `prune.d/*.yaml`
+/// within `/usr/share/moss` and `/etc/moss`
+///
+/// All keys are optional so that multiple files can be layered, with
+/// later (`/etc`) files taking precedence.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Policy {
+    /// Number of most recent states to keep including the active state, defaulting to [`DEFAULT_KEEP`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<u64>,
+    /// Additionally keep the newest state of each calendar week for this many weeks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weekly: Option<u64>,
+    /// States which are never pruned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<Vec<i32>>,
+    /// Whether states newer than the active state may be pruned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_newer: Option<bool>,
+}
+
+impl Policy {
+    /// Load and merge all retention policies visible to the config `manager`
+    pub fn load(manager: &config::Manager) -> Self {
+        manager
+            .load::<Self>()
+            .into_iter()
+            .reduce(Self::merge)
+            .unwrap_or_default()
+    }
+
+    /// Layer `other` on top of this policy
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            keep: other.keep.or(self.keep),
+            weekly: other.weekly.or(self.weekly),
+            pinned: other.pinned.or(self.pinned),
+            include_newer: other.include_newer.or(self.include_newer),
+        }
+    }
+
+    /// All pinned states
+    pub fn pinned(&self) -> BTreeSet<state::Id> {
+        self.pinned.iter().flatten().map(|id| state::Id::from(*id)).collect()
+    }
+
+    /// States to remove from all `states`, given the `active` state and those `protected`
+    ///
+    /// Weeks are counted back from `now`.
+    pub fn removals(
+        &self,
+        states: &[(state::Id, DateTime<Utc>)],
+        active: state::Id,
+        protected: &BTreeSet<state::Id>,
+        now: DateTime<Utc>,
+    ) -> Vec<state::Id> {
+        let include_newer = self.include_newer.unwrap_or_default();
+
+        // Filter for all removal candidates
+        let candidates = states
+            .iter()
+            .filter(|(id, _)| if include_newer { *id != active } else { *id < active })
+            .filter(|(id, _)| !protected.contains(id))
+            .sorted_by_key(|(_, created)| *created)
+            .collect::<Vec<_>>();
+
+        // The active state counts towards those kept
+        let mut kept = states
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| !candidates.iter().any(|(candidate, _)| candidate == id))
+            .collect::<BTreeSet<_>>();
+        kept.extend(
+            candidates
+                .iter()
+                .rev()
+                .take((self.keep.unwrap_or(DEFAULT_KEEP) as usize).saturating_sub(1))
+                .map(|(id, _)| *id),
+        );
+
+        // Keep the newest state of each recent week without a kept state
+        if let Some(weeks) = self.weekly.filter(|weeks| *weeks > 0) {
+            let since = now - Duration::weeks(weeks as i64);
+            let by_week = states
+                .iter()
+                .filter(|(_, created)| *created > since)
+                .into_group_map_by(|(_, created)| (created.iso_week().year(), created.iso_week().week()));
+
+            for week in by_week.into_values() {
+                if week.iter().any(|(id, _)| kept.contains(id)) {
+                    continue;
+                }
+                if let Some((id, _)) = week.into_iter().max_by_key(|(_, created)| *created) {
+                    kept.insert(*id);
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .map(|(id, _)| *id)
+            .filter(|id| !kept.contains(id))
+            .collect()
+    }
+}
+
+impl config::Config for Policy {
+    fn domain() -> String {
+        "prune".into()
+    }
+}
+
 /// Prune old states using [`Strategy`] and garbage collect
 /// all cached data related to those states being removed
 ///
+/// States are removed from the database in a single transaction before anything
+/// else. Packages, assets and archives are then collected by what the remaining
+/// states reference, rather than by what was removed, so that a prune interrupted
+/// at any point is completed by the next one.
+///
 /// # Arguments
 ///
+/// * - `client`       - Client of the installation, whose boot management policy applies
 /// * - `strategy`     - pruning strategy to employ
-/// * - `state_db`     - Installation's state database
-/// * - `install_db`   - Installation's "installed" database
-/// * - `layout_db`    - Installation's layout database
-/// * - `installation` - Client specific target filesystem encapsulation
-pub fn prune(
-    strategy: Strategy,
-    state_db: &db::state::Database,
-    install_db: &db::meta::Database,
-    layout_db: &db::layout::Database,
-    installation: &Installation,
-    yes: bool,
-) -> Result<(), Error> {
+/// * - `dry_run`      - Only print what would be removed
+pub fn prune(client: &Client, strategy: Strategy, yes: bool, dry_run: bool) -> Result<(), Error> {
+    let (state_db, install_db, layout_db, installation) = (
+        &client.state_db,
+        &client.install_db,
+        &client.layout_db,
+        &client.installation,
+    );
+
     // Only prune if the moss root has an active state (otherwise
     // it's probably borked or not setup yet)
     let Some(current_state) = installation.active_state else {
         return Err(Error::NoActiveState);
     };
 
-    let state_ids = state_db.list_ids()?;
+    let states = state_db.all()?;
+    let state_ids = states.iter().map(|state| (state.id, state.created)).collect::<Vec<_>>();
+
+    // Pinned states and the booted one are never removed
+    let mut protected = Policy::load(&config::Manager::system(&installation.root, "moss")).pinned();
+    protected.extend(states.iter().filter(|state| state.pinned).map(|state| state.id));
+    // Interrupted states are resumed or discarded, not pruned
+    protected.extend(
+        states
+            .iter()
+            .filter(|state| state.kind == state::Kind::Incomplete)
+            .map(|state| state.id),
+    );
+    protected.extend(boot::booted_state(installation));
 
     // Find each state we need to remove
     let removal_ids = match strategy {
-        Strategy::KeepRecent { keep, include_newer } => {
-            // Filter for all removal candidates
-            let candidates = state_ids
+        Strategy::Retain(policy) => {
+            protected.extend(policy.pinned());
+            policy.removals(&state_ids, current_state, &protected, Utc::now())
+        }
+        Strategy::Remove(remove) => {
+            if protected.contains(&remove) {
+                return Err(Error::Protected(remove));
+            }
+
+            state_ids
                 .iter()
-                .filter(|(id, _)| {
-                    if include_newer {
-                        *id != current_state
-                    } else {
-                        *id < current_state
-                    }
-                })
-                .collect::<Vec<_>>();
-            // Deduct current state from num candidates to keep
-            let candidate_limit = (keep as usize).saturating_sub(1);
-
-            // Calculate how many candidate states over the limit we are
-            let num_to_remove = candidates.len().saturating_sub(candidate_limit);
-
-            // Sort ascending and assign first `num_to_remove` as `Status::Remove`
-            candidates
+                // Remove if this id actually exists
+                .find_map(|(id, _)| (*id == remove).then_some(remove))
                 .into_iter()
-                .sorted_by_key(|(_, created)| *created)
-                .enumerate()
-                .filter_map(|(idx, (id, _))| if idx < num_to_remove { Some(*id) } else { None })
-                .collect::<Vec<_>>()
+                .collect()
         }
-        Strategy::Remove(remove) => state_ids
-            .iter()
-            // Remove if this id actually exists
-            .find_map(|(id, _)| (*id == remove).then_some(remove))
-            .into_iter()
-            .collect(),
     };
 
-    // Bail if there's no states to remove
-    if removal_ids.is_empty() {
-        // TODO: Print no states to be removed
-        return Ok(());
+    // Ensure we're not pruning the active state!!
+    if removal_ids.contains(&current_state) {
+        return Err(Error::PruneCurrent);
     }
 
-    // Keep track of how many active states are using a package
-    let mut packages_counts = BTreeMap::<package::Id, usize>::new();
-    let mut removals = vec![];
-
-    // Get net refcount of each package in all states
-    for (id, _) in state_ids {
-        // Get metadata
-        let state = state_db.get(id)?;
-
-        // Increment each package
-        state.selections.iter().for_each(|selection| {
-            *packages_counts.entry(selection.package.clone()).or_default() += 1;
-        });
-
-        // Decrement if removal
-        if removal_ids.contains(&id) {
-            // Ensure we're not pruning the active state!!
-            if id == current_state {
-                return Err(Error::PruneCurrent);
-            }
-
-            state.selections.iter().for_each(|selection| {
-                *packages_counts.entry(selection.package.clone()).or_default() -= 1;
-            });
-            removals.push(state);
-        }
-    }
+    // Refcounted while all states are still known
+    let freed = if removal_ids.is_empty() {
+        0
+    } else {
+        usage::Usage::load(&states, layout_db, installation)?.freed(&removal_ids.iter().copied().collect())
+    };
 
-    // Get all packages which were decremented to 0,
-    // these are the packages we want to remove since
-    // no more states reference them
-    let package_removals = packages_counts
+    let (removals, remaining): (Vec<_>, Vec<_>) = states.into_iter().partition(|state| removal_ids.contains(&state.id));
+
+    // All packages no remaining state references, including
+    // those left behind by an interrupted prune. Pinned states
+    // always remain, keeping their packages referenced
+    let referenced = remaining
+        .iter()
+        .flat_map(|state| state.selections.iter().map(|selection| &selection.package))
+        .collect::<BTreeSet<_>>();
+    let package_removals = install_db
+        .query(None)?
         .into_iter()
-        .filter_map(|(pkg, count)| (count == 0).then_some(pkg))
+        .filter(|(id, _)| !referenced.contains(id))
+        .map(|(id, meta)| Package {
+            id,
+            meta,
+            flags: package::Flags::default(),
+        })
         .collect::<Vec<_>>();
 
+    // Bail if there's nothing to remove
+    if removals.is_empty() && package_removals.is_empty() {
+        println!("No states to remove");
+        return Ok(());
+    }
+
     // Print out the states to be removed to the user
-    println!("The following state(s) will be removed:");
-    println!();
-    autoprint_columns(&removals.iter().map(state::ColumnDisplay).collect::<Vec<_>>());
-    println!();
+    if !removals.is_empty() {
+        println!("The following state(s) will be removed:");
+        println!();
+        autoprint_columns(&removals.iter().map(state::ColumnDisplay).collect::<Vec<_>>());
+        println!();
+        println!(
+            "{} of assets are only referenced by these state(s)",
+            usage::format_size(freed)
+        );
+        println!();
+    }
+    if !package_removals.is_empty() {
+        println!("The following package(s) will no longer be cached:");
+        println!();
+        autoprint_columns(&package_removals.iter().collect::<Vec<_>>());
+        println!();
+    }
+
+    if dry_run {
+        if !removals.is_empty() {
+            println!("Boot entries & assets of the removed state(s) would be cleaned up");
+        }
+        return Ok(());
+    }
 
     let result = if yes {
         true
@@ -156,6 +292,17 @@ pub fn prune(
         return Err(Error::Cancelled);
     }
 
+    // Resolved while the packages of the active state are known for certain
+    let arch = state_db
+        .get(current_state)
+        .ok()
+        .and_then(|state| boot::architecture(install_db, &state));
+
+    let package_removals = package_removals
+        .into_iter()
+        .map(|package| package.id)
+        .collect::<Vec<_>>();
+
     // Prune these states / packages from all dbs
     prune_databases(&removals, &package_removals, state_db, install_db, layout_db)?;
 
@@ -179,14 +326,11 @@ pub fn prune(
         |hash| Some(cache::asset_path(installation, &hash)),
     )?;
 
-    // Remove each state's archive folder
-    for state in removals {
-        let archive_path = installation.root_path(state.id.to_string());
+    // Remove the archive folder of each state no longer recorded
+    remove_stale_archives(installation, &remaining)?;
 
-        if archive_path.exists() {
-            fs::remove_dir_all(&archive_path)?;
-        }
-    }
+    // Remove boot entries & assets of the removed states
+    boot::cleanup(client, &removal_ids, arch)?;
 
     Ok(())
 }
@@ -219,6 +363,27 @@ fn prune_databases(
     Ok(())
 }
 
+/// Removes the archive folder of every state not within `remaining`
+fn remove_stale_archives(installation: &Installation, remaining: &[State]) -> Result<(), Error> {
+    let root = installation.root_path("");
+    if !root.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&root)? {
+        let entry = entry?;
+        let Some(id) = entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) else {
+            continue;
+        };
+
+        if entry.file_type()?.is_dir() && !remaining.iter().any(|state| state.id == state::Id::from(id)) {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Removes all files under `root` that no longer exist in the provided `final_hashes` set
 fn remove_orphaned_files(
     root: PathBuf,
@@ -344,10 +509,65 @@ pub enum Error {
     NoActiveState,
     #[error("cannot prune the currently active state")]
     PruneCurrent,
+    #[error("state {0} is pinned or currently booted")]
+    Protected(state::Id),
     #[error("db")]
     DB(#[from] db::Error),
     #[error("io")]
     Io(#[from] io::Error),
+    #[error("boot")]
+    Boot(#[from] boot::Error),
     #[error("string processing")]
     Dialog(#[from] tui::dialoguer::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn retention_policy() {
+        let now = Utc.with_ymd_and_hms(2025, 6, 18, 12, 0, 0).unwrap();
+        // One state per day for 5 weeks, the newest being active
+        let states = (1..=35)
+            .map(|id| (state::Id::from(id), now - Duration::days(35 - i64::from(id))))
+            .collect::<Vec<_>>();
+        let active = state::Id::from(35);
+        let protected = [state::Id::from(2)].into_iter().collect();
+
+        let policy = Policy {
+            keep: Some(3),
+            ..Default::default()
+        };
+        let removals = policy.removals(&states, active, &protected, now);
+        // Active, pinned and 2 more recent states kept
+        assert_eq!(removals.len(), 31);
+        assert!(!removals.contains(&state::Id::from(2)));
+        assert!(!removals.contains(&state::Id::from(33)));
+        assert!(removals.contains(&state::Id::from(32)));
+
+        let weekly = Policy {
+            weekly: Some(3),
+            ..policy.clone()
+        };
+        let removals = weekly.removals(&states, active, &protected, now);
+        // The newest state of each week within the window is kept as well
+        assert_eq!(removals.len(), 28);
+        for sunday in [state::Id::from(32), state::Id::from(25), state::Id::from(18)] {
+            assert!(!removals.contains(&sunday));
+        }
+        assert!(removals.contains(&state::Id::from(17)));
+        assert!(removals.contains(&state::Id::from(11)));
+
+        let newer = Policy {
+            keep: Some(1),
+            include_newer: Some(true),
+            ..Default::default()
+        };
+        let removals = newer.removals(&states, state::Id::from(10), &BTreeSet::new(), now);
+        assert_eq!(removals.len(), 34);
+        assert!(!removals.contains(&state::Id::from(10)));
+    }
+}
@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Resolution of [`state::Reference`]s to recorded states
+//!
+//! Relative references count back through the recorded states preceding the active
+//! state by id, so `active~1` is the state a plain `moss rollback` activates. States
+//! whose transaction never completed are never resolved.
+
+use thiserror::Error;
+
+use crate::{
+    db,
+    state::{self, Reference},
+};
+
+/// Resolve the `reference` against the states recorded in `db`, relative to the `active` state
+pub fn resolve(db: &db::state::Database, active: Option<state::Id>, reference: Reference) -> Result<state::Id, Error> {
+    let mut ids = db
+        .list_complete_ids()?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    ids.sort();
+
+    match reference {
+        Reference::Id(id) => ids.contains(&id).then_some(id).ok_or(Error::NoSuchState(id)),
+        // Unless interrupted after promoting its tree
+        Reference::Active(0) => active
+            .filter(|id| ids.contains(id))
+            .ok_or(Error::NoActiveState(reference)),
+        Reference::Active(n) => {
+            let active = active.ok_or(Error::NoActiveState(reference))?;
+            let preceding = ids.iter().rev().filter(|id| **id < active).copied().collect::<Vec<_>>();
+
+            preceding.get(n as usize - 1).copied().ok_or(Error::BeyondHistory {
+                reference,
+                depth: preceding.len(),
+            })
+        }
+        Reference::Latest => ids.last().copied().ok_or(Error::NoStates),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("state {0} doesn't exist")]
+    NoSuchState(state::Id),
+    #[error("`{0}` needs an active state, but the root has none")]
+    NoActiveState(Reference),
+    #[error("`{reference}` goes back further than the {depth} state(s) preceding the active state")]
+    BeyondHistory { reference: Reference, depth: usize },
+    #[error("no states recorded yet")]
+    NoStates,
+    #[error("db")]
+    DB(#[from] db::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{package, state::Selection};
+
+    #[test]
+    fn resolve_references() {
+        let db = db::state::Database::new(":memory:").unwrap();
+
+        let selections = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| Selection::explicit(package::Id::from((*name).to_owned())))
+                .collect::<Vec<_>>()
+        };
+        db.add(&selections(&["nano"]), Some("Install"), None).unwrap();
+        db.add(&selections(&["nano", "curl"]), Some("Install"), None).unwrap();
+        db.add(&selections(&["nano", "curl", "vim"]), Some("Install"), None)
+            .unwrap();
+        // Recorded after booting into an older state
+        db.add(&selections(&["vim"]), Some("Remove"), None).unwrap();
+
+        let active = Some(state::Id::from(3));
+        let resolve = |reference: &str| resolve(&db, active, reference.parse().unwrap());
+
+        assert_eq!(resolve("active").unwrap(), state::Id::from(3));
+        assert_eq!(resolve("previous").unwrap(), state::Id::from(2));
+        assert_eq!(resolve("active~2").unwrap(), state::Id::from(1));
+        assert_eq!(resolve("latest").unwrap(), state::Id::from(4));
+        assert_eq!(resolve("4").unwrap(), state::Id::from(4));
+        assert!(matches!(resolve("9"), Err(Error::NoSuchState(_))));
+        assert!(matches!(
+            resolve("active~3"),
+            Err(Error::BeyondHistory { depth: 2, .. })
+        ));
+        assert!(matches!(
+            super::resolve(&db, None, Reference::Active(1)),
+            Err(Error::NoActiveState(_))
+        ));
+
+        // As `moss state diff active~1`
+        let from = db.get(resolve("active~1").unwrap()).unwrap();
+        let to = db.get(resolve("active").unwrap()).unwrap();
+        let diff = state::diff(&from, &to, |_| None);
+
+        assert_eq!((diff.from, diff.to), (state::Id::from(2), state::Id::from(3)));
+        assert_eq!(
+            diff.added
+                .iter()
+                .map(|package| package.name.as_str())
+                .collect::<Vec<_>>(),
+            ["vim"]
+        );
+        assert!(diff.removed.is_empty() && diff.changed.is_empty());
+
+        // Interrupted after promoting its tree
+        let incomplete = db
+            .add_incomplete(
+                &selections(&["vim", "curl"]),
+                None,
+                None,
+                state::Kind::Transaction,
+                None,
+            )
+            .unwrap();
+        assert_eq!(resolve("latest").unwrap(), state::Id::from(4));
+        assert!(matches!(
+            resolve(&incomplete.id.to_string()),
+            Err(Error::NoSuchState(_))
+        ));
+
+        let resolve = |reference: &str| super::resolve(&db, Some(incomplete.id), reference.parse().unwrap());
+        assert!(matches!(resolve("active"), Err(Error::NoActiveState(_))));
+        assert_eq!(resolve("previous").unwrap(), state::Id::from(4));
+    }
+}
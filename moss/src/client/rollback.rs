@@ -0,0 +1,171 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Rollback to a previously recorded state
+//!
+//! History is never rewritten: the selections of the target state are blitted as a
+//! new state of [`state::Kind::Rollback`], whose description names the source state.
+//! All packages of the target state must still be cached, or be fetched again.
+
+use std::collections::BTreeSet;
+
+use stone::payload::layout;
+use thiserror::Error;
+
+use crate::{
+    client::{self, boot, cache, Client},
+    db, package, runtime,
+    state::{self, StateDiff},
+    Package, State,
+};
+
+/// The outcome of a rollback
+#[derive(Debug)]
+pub struct Rollback {
+    /// The state copied by the rollback
+    pub source: state::Id,
+    /// The newly recorded state
+    pub state: State,
+    /// Boot synchronization of the new state
+    pub boot: boot::SyncOutcome,
+    /// Changes relative to the previously active state
+    pub diff: StateDiff,
+}
+
+/// Roll back to the `target` state, or the one preceding the active state
+///
+/// Packages of the target state missing from the cache are downloaded again if
+/// `fetch` is set, otherwise the rollback is refused.
+pub fn rollback(client: &Client, target: Option<state::Id>, fetch: bool) -> Result<Rollback, Error> {
+    let Some(active) = client.installation.active_state else {
+        return Err(Error::NoActiveState);
+    };
+
+    let source = match target {
+        Some(id) => id,
+        None => client
+            .state_db
+            .list_complete_ids()?
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| *id < active)
+            .max()
+            .ok_or(Error::NoPreviousState)?,
+    };
+    if source == active {
+        return Err(Error::AlreadyActive(source));
+    }
+
+    let target = client.state_db.get(source).map_err(|_| Error::NoState(source))?;
+    let previous = client.state_db.get(active)?;
+
+    let missing = missing_packages(client, &target)?;
+    if !missing.is_empty() {
+        if !fetch {
+            return Err(Error::NotCached(source, names(client, &missing)));
+        }
+
+        let packages = missing
+            .iter()
+            .map(|id| resolve(client, id).ok_or_else(|| Error::Unavailable(id.to_string())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        runtime::block_on(client.cache_packages(&packages))?;
+    }
+
+    let Some((state, boot)) = client.new_state_with_kind(
+        &target.selections,
+        None,
+        Some(&format!("Rollback to state #{source}")),
+        state::Kind::Rollback,
+    )?
+    else {
+        return Err(Error::Ephemeral);
+    };
+
+    let diff = state::diff(&previous, &state, |id| client.install_db.get(id).ok());
+
+    Ok(Rollback {
+        source,
+        state,
+        boot,
+        diff,
+    })
+}
+
+/// Packages of `state` whose metadata, layouts or assets are no longer cached
+fn missing_packages(client: &Client, state: &State) -> Result<Vec<package::Id>, Error> {
+    let packages = state
+        .selections
+        .iter()
+        .map(|selection| &selection.package)
+        .collect::<BTreeSet<_>>();
+
+    let layouts = client.layout_db.query(packages.iter().copied())?;
+
+    let mut missing = packages
+        .iter()
+        .copied()
+        .filter(|id| client.install_db.get(id).is_err() || !layouts.iter().any(|(package, _)| package == *id))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    missing.extend(layouts.iter().filter_map(|(package, layout)| {
+        let layout::Entry::Regular(hash, _) = &layout.entry else {
+            return None;
+        };
+        let path = cache::asset_path(&client.installation, &format!("{hash:02x}"));
+
+        (!path.exists()).then(|| package.clone())
+    }));
+
+    Ok(missing.into_iter().collect())
+}
+
+/// Resolve the downloadable package `id`, preferring the installed metadata
+fn resolve(client: &Client, id: &package::Id) -> Option<Package> {
+    match client.install_db.get(id) {
+        Ok(meta) if meta.uri.is_some() => Some(Package {
+            id: id.clone(),
+            meta,
+            flags: package::Flags::default(),
+        }),
+        _ => client.registry.by_id(id).find(|package| package.meta.uri.is_some()),
+    }
+}
+
+/// Display names of the packages `ids`
+fn names(client: &Client, ids: &[package::Id]) -> String {
+    ids.iter()
+        .map(|id| {
+            client
+                .install_db
+                .get(id)
+                .map_or_else(|_| id.to_string(), |meta| meta.name.to_string())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("root must have an active state")]
+    NoActiveState,
+    #[error("no state precedes the active state")]
+    NoPreviousState,
+    #[error("state {0} doesn't exist")]
+    NoState(state::Id),
+    #[error("state {0} already active")]
+    AlreadyActive(state::Id),
+    #[error("packages of state {0} are no longer cached, use --fetch to download them: {1}")]
+    NotCached(state::Id, String),
+    #[error("package {0} is not available from any repository")]
+    Unavailable(String),
+    #[error("operation not allowed with ephemeral client")]
+    Ephemeral,
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("db")]
+    DB(#[from] db::Error),
+}
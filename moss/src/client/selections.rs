@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Repair of the explicit flag recorded for state selections
+//!
+//! Earlier moss releases recorded every dependency pulled in by an install as
+//! explicitly requested. The original intent can't be recovered, so it's re-derived
+//! from the installed metadata instead: the packages no other selection depends on
+//! are kept explicit, while every explicit selection reachable from them as a
+//! dependency is demoted. Dependency cycles unreachable from such a package, and
+//! packages lacking metadata, are left untouched.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    client::{self, Client},
+    package::{self, Meta},
+    state::{self, Selection},
+    Provider,
+};
+
+/// Selections demoted to transitive within a state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub state: state::Id,
+    pub demoted: Vec<package::Id>,
+}
+
+/// Re-derive the explicit selections of all states, returning the changes made
+///
+/// Nothing is written when `dry_run` is set.
+pub fn repair(client: &Client, dry_run: bool) -> Result<Vec<Change>, client::Error> {
+    let mut metadata = BTreeMap::new();
+    let mut changes = vec![];
+
+    for state in client.state_db.all()? {
+        for selection in &state.selections {
+            if !metadata.contains_key(&selection.package) {
+                if let Ok(meta) = client.install_db.get(&selection.package) {
+                    metadata.insert(selection.package.clone(), meta);
+                }
+            }
+        }
+
+        let demoted = demotions(&state.selections, &metadata);
+        if demoted.is_empty() {
+            continue;
+        }
+
+        if !dry_run {
+            client.state_db.set_explicit(state.id, &demoted, false)?;
+        }
+
+        changes.push(Change {
+            state: state.id,
+            demoted,
+        });
+    }
+
+    Ok(changes)
+}
+
+/// Explicit `selections` only reachable as dependencies of other selections
+pub fn demotions(selections: &[Selection], metadata: &BTreeMap<package::Id, Meta>) -> Vec<package::Id> {
+    let providers = selections
+        .iter()
+        .filter_map(|selection| Some((&selection.package, metadata.get(&selection.package)?)))
+        .flat_map(|(package, meta)| meta.providers.iter().map(move |provider| (provider, package)))
+        .collect::<BTreeMap<_, _>>();
+
+    // Selections each selection depends on
+    let dependencies = selections
+        .iter()
+        .map(|selection| {
+            let dependencies = metadata
+                .get(&selection.package)
+                .into_iter()
+                .flat_map(|meta| &meta.dependencies)
+                .filter_map(|dependency| {
+                    providers.get(&Provider {
+                        kind: dependency.kind,
+                        name: dependency.name.clone(),
+                    })
+                })
+                .copied()
+                .filter(|package| **package != selection.package)
+                .collect::<BTreeSet<_>>();
+
+            (&selection.package, dependencies)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let required = dependencies.values().flatten().copied().collect::<BTreeSet<_>>();
+
+    let mut reachable = BTreeSet::new();
+    let mut queue = dependencies
+        .keys()
+        .filter(|package| !required.contains(*package))
+        .flat_map(|package| &dependencies[package])
+        .copied()
+        .collect::<Vec<_>>();
+
+    while let Some(package) = queue.pop() {
+        if reachable.insert(package) {
+            queue.extend(dependencies.get(package).into_iter().flatten().copied());
+        }
+    }
+
+    selections
+        .iter()
+        .filter(|selection| selection.explicit && reachable.contains(&selection.package))
+        .map(|selection| selection.package.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Dependency;
+
+    fn meta(name: &str, dependencies: &[&str]) -> Meta {
+        Meta {
+            name: name.to_owned().into(),
+            version_identifier: "1.0".to_owned(),
+            source_release: 1,
+            build_release: 1,
+            architecture: "x86_64".to_owned(),
+            summary: String::new(),
+            description: String::new(),
+            source_id: name.to_owned(),
+            homepage: String::new(),
+            licenses: vec![],
+            dependencies: dependencies
+                .iter()
+                .map(|dependency| Dependency::from_name(dependency).unwrap())
+                .collect(),
+            providers: [Provider::from_name(name).unwrap()].into_iter().collect(),
+            conflicts: Default::default(),
+            uri: None,
+            hash: None,
+            download_size: None,
+        }
+    }
+
+    #[test]
+    fn demote_dependencies() {
+        let metadata = [
+            meta("editor", &["libc", "ncurses"]),
+            meta("ncurses", &["libc"]),
+            meta("libc", &[]),
+            meta("cycle-a", &["cycle-b"]),
+            meta("cycle-b", &["cycle-a"]),
+        ]
+        .into_iter()
+        .map(|meta| (package::Id::from(meta.name.to_string()), meta))
+        .collect::<BTreeMap<_, _>>();
+
+        let selections = ["editor", "ncurses", "libc", "cycle-a", "cycle-b", "unknown"]
+            .into_iter()
+            .map(|name| Selection::explicit(package::Id::from(name.to_owned())))
+            .collect::<Vec<_>>();
+
+        let demoted = demotions(&selections, &metadata);
+
+        assert_eq!(
+            demoted,
+            vec![
+                package::Id::from("ncurses".to_owned()),
+                package::Id::from("libc".to_owned())
+            ]
+        );
+    }
+}
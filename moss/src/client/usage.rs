@@ -0,0 +1,253 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Disk usage of states within the content store
+//!
+//! States share most of their assets, so every state has two sizes: the total size
+//! of the assets referenced by its layouts, and its unique size of the assets no other
+//! state references, i.e. what pruning the state would actually free. The usage of all
+//! states is computed in a single pass over the layouts, refcounting each asset by the
+//! states referencing it.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use fs_err as fs;
+use serde::Serialize;
+use stone::payload::{layout, Layout};
+
+use crate::{client::cache, db, package, state, Installation, State};
+
+/// Asset sizes of a single state, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Sizes {
+    /// Size of all assets referenced by the state
+    pub total: u64,
+    /// Size of the assets referenced by no other state
+    pub unique: u64,
+}
+
+/// An asset and the states referencing it
+#[derive(Debug, Clone)]
+struct Asset {
+    size: u64,
+    states: BTreeSet<state::Id>,
+}
+
+/// Refcounted assets of all states
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    assets: BTreeMap<String, Asset>,
+}
+
+impl Usage {
+    /// Refcount the assets of `states` from the `layouts` of their packages, with
+    /// `size` giving the size of an asset by its hash
+    ///
+    /// Each asset is only sized once.
+    pub fn new(
+        states: &[State],
+        layouts: impl IntoIterator<Item = (package::Id, Layout)>,
+        size: impl Fn(&str) -> u64,
+    ) -> Self {
+        let mut packages = BTreeMap::<_, Vec<_>>::new();
+        for state in states {
+            for selection in &state.selections {
+                packages.entry(selection.package.clone()).or_default().push(state.id);
+            }
+        }
+
+        let mut referenced = BTreeMap::<_, BTreeSet<_>>::new();
+        for (package, layout) in layouts {
+            let (Some(states), layout::Entry::Regular(hash, _)) = (packages.get(&package), &layout.entry) else {
+                continue;
+            };
+            referenced
+                .entry(format!("{hash:02x}"))
+                .or_default()
+                .extend(states.iter().copied());
+        }
+
+        let assets = referenced
+            .into_iter()
+            .map(|(hash, states)| {
+                let size = size(&hash);
+                (hash, Asset { size, states })
+            })
+            .collect();
+
+        Self { assets }
+    }
+
+    /// Refcount the assets of `states` from the layout db, sized by their file
+    /// within the content store of `installation`
+    pub fn load(
+        states: &[State],
+        layout_db: &db::layout::Database,
+        installation: &Installation,
+    ) -> Result<Self, db::Error> {
+        Ok(Self::new(states, layout_db.all()?, |hash| {
+            asset_size(installation, hash)
+        }))
+    }
+
+    /// Refcount the assets of the state `id` against all `states`, as [`Self::load`]
+    /// does, but only sizing the assets referenced by `id`
+    ///
+    /// Only the sizes of `id` itself are complete.
+    pub fn load_state(
+        id: state::Id,
+        states: &[State],
+        layout_db: &db::layout::Database,
+        installation: &Installation,
+    ) -> Result<Self, db::Error> {
+        let packages = states
+            .iter()
+            .flat_map(|state| &state.selections)
+            .map(|selection| &selection.package)
+            .collect::<BTreeSet<_>>();
+
+        let mut usage = Self::new(states, layout_db.query(packages)?, |_| 0);
+        usage.assets.retain(|_, asset| asset.states.contains(&id));
+        for (hash, asset) in &mut usage.assets {
+            asset.size = asset_size(installation, hash);
+        }
+
+        Ok(usage)
+    }
+
+    /// Sizes of every state referencing at least one asset
+    pub fn states(&self) -> BTreeMap<state::Id, Sizes> {
+        let mut sizes = BTreeMap::<_, Sizes>::new();
+
+        for asset in self.assets.values() {
+            for state in &asset.states {
+                let sizes = sizes.entry(*state).or_default();
+                sizes.total += asset.size;
+                if asset.states.len() == 1 {
+                    sizes.unique += asset.size;
+                }
+            }
+        }
+
+        sizes
+    }
+
+    /// Sizes of the state `id`
+    pub fn state(&self, id: state::Id) -> Sizes {
+        self.states().remove(&id).unwrap_or_default()
+    }
+
+    /// Size freed by removing all of `states`, i.e. of the assets referenced by
+    /// none but them
+    pub fn freed(&self, states: &BTreeSet<state::Id>) -> u64 {
+        self.assets
+            .values()
+            .filter(|asset| asset.states.is_subset(states))
+            .map(|asset| asset.size)
+            .sum()
+    }
+}
+
+/// Size of the asset with `hash` within the content store of `installation`
+fn asset_size(installation: &Installation, hash: &str) -> u64 {
+    fs::metadata(cache::asset_path(installation, hash)).map_or(0, |metadata| metadata.len())
+}
+
+/// Human readable form of `bytes`, e.g. `1.5 GiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use stone::payload::layout::Entry;
+
+    use super::*;
+    use crate::state::{Kind, Selection};
+
+    fn state(id: i32, packages: &[&str]) -> State {
+        State {
+            id: state::Id::from(id),
+            summary: None,
+            description: None,
+            selections: packages
+                .iter()
+                .map(|package| Selection::explicit(package::Id::from((*package).to_owned())))
+                .collect(),
+            created: Utc::now(),
+            updated: None,
+            kind: Kind::Transaction,
+            pinned: false,
+            parent: None,
+        }
+    }
+
+    fn layout(package: &str, hash: u128) -> (package::Id, Layout) {
+        (
+            package::Id::from(package.to_owned()),
+            Layout {
+                uid: 0,
+                gid: 0,
+                mode: 0o644,
+                tag: 0,
+                entry: Entry::Regular(hash, format!("{package}-{hash}")),
+            },
+        )
+    }
+
+    #[test]
+    fn refcount_states() {
+        let states = [state(1, &["a", "b"]), state(2, &["b", "c"]), state(3, &["c"])];
+        let layouts = [
+            layout("a", 1),
+            // Duplicated within a package
+            layout("a", 2),
+            layout("a", 2),
+            layout("b", 3),
+            layout("c", 4),
+            // Shared between packages of different states
+            layout("c", 1),
+            // Not selected by any state
+            layout("d", 5),
+        ];
+        let usage = Usage::new(&states, layouts, |hash| u64::from_str_radix(hash, 16).unwrap() * 100);
+
+        let sizes = usage.states();
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(
+            sizes[&state::Id::from(1)],
+            Sizes {
+                total: 600,
+                unique: 200
+            }
+        );
+        assert_eq!(sizes[&state::Id::from(2)], Sizes { total: 800, unique: 0 });
+        assert_eq!(sizes[&state::Id::from(3)], Sizes { total: 500, unique: 0 });
+        assert_eq!(usage.state(state::Id::from(4)), Sizes::default());
+
+        let removals = [1, 2].into_iter().map(state::Id::from).collect();
+        assert_eq!(usage.freed(&removals), 500);
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -2,11 +2,17 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::BTreeSet, fmt, io, path::PathBuf};
+use std::{
+    collections::BTreeSet,
+    fmt, io,
+    path::{Path, PathBuf},
+};
 
 use itertools::Itertools;
 
 use fs_err as fs;
+use rayon::prelude::*;
+use serde::Serialize;
 use stone::{payload::layout, write::digest};
 use tui::{
     dialoguer::{theme::ColorfulTheme, Confirm},
@@ -105,8 +111,13 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
         }
     }
 
-    // Get all states
-    let states = client.state_db.all()?;
+    // Get all states, incomplete ones never got a tree of their own
+    let states = client
+        .state_db
+        .all()?
+        .into_iter()
+        .filter(|state| state.kind.is_complete())
+        .collect::<Vec<_>>();
 
     pb.set_length(states.len() as u64);
     pb.set_position(0);
@@ -240,6 +251,21 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
         "block".into(),
     );
 
+    // Reblit an archived state in place
+    let reblit_archive = |state: &state::State| -> Result<(), client::Error> {
+        // Blits to staging dir
+        let fstree = client.blit_root(state.selections.iter().map(|s| &s.package))?;
+
+        // Use the staged blit as an ephereral target for the non-active state
+        // then archive it to it's archive directory
+        client::record_state_id(&client.installation.staging_dir(), state.id)?;
+        client.apply_ephemeral_blit(fstree, &client.installation.staging_dir())?;
+
+        // Remove the old archive state so the new blit can be archived
+        fs::remove_dir_all(client.installation.root_path(state.id.to_string()))?;
+        client.archive_state(state.id)
+    };
+
     // Reblit each state
     for id in issue_states {
         let state = states
@@ -249,23 +275,23 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
 
         let is_active = client.installation.active_state == Some(state.id);
 
-        // Blits to staging dir
-        let fstree = client.blit_root(state.selections.iter().map(|s| &s.package))?;
-
         if is_active {
-            // Override install root with the newly blitted active state
-            client.apply_stateful_blit(fstree, state, None)?;
-            // Remove corrupt (swapped) state from staging directory
-            fs::remove_dir_all(client.installation.staging_dir())?;
+            // Record the repaired active state as a new state, archiving the
+            // corrupt tree which is then reblitted as any other archive
+            let repaired = client.new_state_with_kind(
+                &state.selections,
+                Some("Repair"),
+                Some(&format!("Repair of state #{}", state.id)),
+                state::Kind::Repair,
+            )?;
+            reblit_archive(state)?;
+
+            if let Some((repaired, _)) = repaired {
+                println!(" {} state #{} as state #{}", "»".green(), state.id, repaired.id);
+                continue;
+            }
         } else {
-            // Use the staged blit as an ephereral target for the non-active state
-            // then archive it to it's archive directory
-            client::record_state_id(&client.installation.staging_dir(), state.id)?;
-            client.apply_ephemeral_blit(fstree, &client.installation.staging_dir())?;
-
-            // Remove the old archive state so the new blit can be archived
-            fs::remove_dir_all(client.installation.root_path(state.id.to_string()))?;
-            client.archive_state(state.id)?;
+            reblit_archive(state)?;
         }
 
         println!(" {} state #{}", "»".green(), state.id);
@@ -327,3 +353,307 @@ impl fmt::Display for Issue {
         }
     }
 }
+
+/// The outcome of [`check`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// The verified state
+    pub state: state::Id,
+    /// The `/usr` tree of the state
+    pub usr: PathBuf,
+    /// Number of layout entries checked
+    pub checked: usize,
+    pub missing: usize,
+    pub modified: usize,
+    pub extra: usize,
+    /// All findings, ordered by path
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Returns true unless any layout entry is missing or modified
+    ///
+    /// Extra files don't fail verification, as triggers generate them too.
+    pub fn is_ok(&self) -> bool {
+        self.missing == 0 && self.modified == 0
+    }
+}
+
+/// A discrepancy between a state's layouts and its `/usr` tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "finding", rename_all = "kebab-case")]
+pub enum Finding {
+    /// Absent from the tree
+    Missing { path: PathBuf, package: package::Id },
+    /// Present, but not as described by the layout
+    Modified {
+        path: PathBuf,
+        package: package::Id,
+        modification: Modification,
+    },
+    /// Present in the tree, but not part of any layout
+    Extra { path: PathBuf },
+}
+
+impl Finding {
+    pub fn path(&self) -> &Path {
+        match self {
+            Finding::Missing { path, .. } | Finding::Modified { path, .. } | Finding::Extra { path } => path,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Missing { path, package } => write!(f, "Missing {} ({package})", path.display()),
+            Finding::Modified {
+                path,
+                package,
+                modification,
+            } => write!(f, "Modified {} ({package}): {modification}", path.display()),
+            Finding::Extra { path } => write!(f, "Extra {}", path.display()),
+        }
+    }
+}
+
+/// How a path differs from its layout
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Modification {
+    /// Content doesn't match the layout digest
+    Content,
+    /// Symlink pointing elsewhere
+    Target { expected: String, actual: String },
+    /// Different file type, e.g. a regular file in place of a directory
+    Kind,
+}
+
+impl fmt::Display for Modification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Modification::Content => write!(f, "content differs"),
+            Modification::Target { expected, actual } => write!(f, "links to {actual} instead of {expected}"),
+            Modification::Kind => write!(f, "file type differs"),
+        }
+    }
+}
+
+/// Compare the `/usr` tree of the `state`, or of the active state, against its layouts
+///
+/// Archived states are compared against their archived tree. Nothing is modified.
+pub fn check(client: &Client, state: Option<state::Id>) -> Result<Report, client::Error> {
+    let id = state
+        .or(client.installation.active_state)
+        .ok_or(client::Error::NoActiveState)?;
+    let state = client
+        .state_db
+        .get(id)
+        .map_err(|_| client::Error::StateDoesntExist(id))?;
+
+    let usr = if client.installation.active_state == Some(id) {
+        client.installation.root.join("usr")
+    } else {
+        client.installation.root_path(id.to_string()).join("usr")
+    };
+    if !usr.is_dir() {
+        return Err(client::Error::MissingStateTree(id));
+    }
+
+    let vfs = client.vfs(state.selections.iter().map(|s| &s.package))?;
+    let entries = vfs
+        .iter()
+        .filter_map(|file| {
+            let path = file.path();
+            let relative = path.strip_prefix("/usr/")?.to_owned();
+
+            Some((relative, file))
+        })
+        .collect::<Vec<_>>();
+
+    let pb = ProgressBar::new(entries.len() as u64)
+        .with_message(format!("Verifying state #{id}"))
+        .with_style(
+            ProgressStyle::with_template("\n|{bar:20.red/blue}| {pos}/{len} {wide_msg}")
+                .unwrap()
+                .progress_chars("■≡=- "),
+        );
+    pb.tick();
+
+    // Hashing dominates, so check entries in parallel
+    let mut findings = entries
+        .par_iter()
+        .map(|(relative, file)| -> io::Result<Option<Finding>> {
+            let status = check_entry(&usr, relative, &file.layout.entry)?;
+            pb.inc(1);
+
+            let path = usr.join(relative);
+            let package = file.id.clone();
+
+            Ok(match status {
+                Status::Ok => None,
+                Status::Missing => Some(Finding::Missing { path, package }),
+                Status::Modified(modification) => Some(Finding::Modified {
+                    path,
+                    package,
+                    modification,
+                }),
+            })
+        })
+        .filter_map(Result::transpose)
+        .collect::<io::Result<Vec<_>>>()?;
+
+    pb.finish_and_clear();
+
+    let expected = entries
+        .iter()
+        .map(|(relative, _)| relative.as_str())
+        .collect::<BTreeSet<_>>();
+    findings.extend(
+        extra_paths(&usr, &usr, &expected)?
+            .into_iter()
+            .map(|path| Finding::Extra { path }),
+    );
+    findings.sort_by(|a, b| a.path().cmp(b.path()));
+
+    let count = |is: fn(&Finding) -> bool| findings.iter().filter(|finding| is(finding)).count();
+
+    Ok(Report {
+        state: id,
+        checked: entries.len(),
+        missing: count(|finding| matches!(finding, Finding::Missing { .. })),
+        modified: count(|finding| matches!(finding, Finding::Modified { .. })),
+        extra: count(|finding| matches!(finding, Finding::Extra { .. })),
+        usr,
+        findings,
+    })
+}
+
+/// Outcome of checking a single layout entry
+#[derive(Debug, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Missing,
+    Modified(Modification),
+}
+
+/// Check the `relative` path beneath `usr` against its layout `entry`
+fn check_entry(usr: &Path, relative: &str, entry: &layout::Entry) -> io::Result<Status> {
+    let path = usr.join(relative);
+
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Status::Missing),
+        Err(e) => return Err(e),
+    };
+    let file_type = metadata.file_type();
+
+    match entry {
+        layout::Entry::Regular(hash, _) => {
+            if !file_type.is_file() {
+                return Ok(Status::Modified(Modification::Kind));
+            }
+
+            let mut hasher = digest::Hasher::new();
+            let mut digest_writer = digest::Writer::new(io::sink(), &mut hasher);
+            io::copy(&mut fs::File::open(&path)?, &mut digest_writer)?;
+
+            if hasher.digest128() != *hash {
+                return Ok(Status::Modified(Modification::Content));
+            }
+        }
+        layout::Entry::Symlink(source, _) => {
+            if !file_type.is_symlink() {
+                return Ok(Status::Modified(Modification::Kind));
+            }
+
+            let actual = fs::read_link(&path)?.to_string_lossy().into_owned();
+            if actual != *source {
+                return Ok(Status::Modified(Modification::Target {
+                    expected: source.clone(),
+                    actual,
+                }));
+            }
+        }
+        layout::Entry::Directory(_) => {
+            if !file_type.is_dir() {
+                return Ok(Status::Modified(Modification::Kind));
+            }
+        }
+        // Special files only need to exist
+        layout::Entry::CharacterDevice(_)
+        | layout::Entry::BlockDevice(_)
+        | layout::Entry::Fifo(_)
+        | layout::Entry::Socket(_) => {}
+    }
+
+    Ok(Status::Ok)
+}
+
+/// Paths beneath `dir` not `expected` relative to `usr`, without descending into extra directories
+fn extra_paths(usr: &Path, dir: &Path, expected: &BTreeSet<&str>) -> io::Result<Vec<PathBuf>> {
+    let mut extra = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path.strip_prefix(usr).unwrap_or(&path).to_string_lossy().into_owned();
+
+        if relative == STATE_ID {
+            continue;
+        }
+
+        if !expected.contains(relative.as_str()) {
+            extra.push(path);
+        } else if entry.file_type()?.is_dir() {
+            extra.extend(extra_paths(usr, &path, expected)?);
+        }
+    }
+
+    Ok(extra)
+}
+
+/// Recorded in every `/usr` tree by moss itself
+const STATE_ID: &str = ".stateID";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::test::Scratch;
+
+    #[test]
+    fn check_entries() {
+        let usr = Scratch::new(&[("bin/nano", "nano"), ("bin/vim", "tampered"), ("bin/pico", "stray")]);
+        std::os::unix::fs::symlink("nano", usr.join("bin/editor")).unwrap();
+
+        let regular = |name: &str, content: &str| {
+            layout::Entry::Regular(xxhash_rust::xxh3::xxh3_128(content.as_bytes()), name.to_owned())
+        };
+        let check = |relative: &str, entry: layout::Entry| check_entry(&usr, relative, &entry).unwrap();
+
+        assert_eq!(check("bin/nano", regular("bin/nano", "nano")), Status::Ok);
+        assert_eq!(
+            check("bin/vim", regular("bin/vim", "vim")),
+            Status::Modified(Modification::Content)
+        );
+        assert_eq!(check("bin/emacs", regular("bin/emacs", "emacs")), Status::Missing);
+        assert_eq!(
+            check(
+                "bin/editor",
+                layout::Entry::Symlink("vim".to_owned(), "bin/editor".to_owned())
+            ),
+            Status::Modified(Modification::Target {
+                expected: "vim".to_owned(),
+                actual: "nano".to_owned()
+            })
+        );
+        assert_eq!(
+            check("bin", layout::Entry::Symlink("sbin".to_owned(), "bin".to_owned())),
+            Status::Modified(Modification::Kind)
+        );
+        assert_eq!(check("bin", layout::Entry::Directory("bin".to_owned())), Status::Ok);
+
+        let expected = ["bin", "bin/nano", "bin/vim", "bin/editor"].into_iter().collect();
+        assert_eq!(extra_paths(&usr, &usr, &expected).unwrap(), vec![usr.join("bin/pico")]);
+    }
+}
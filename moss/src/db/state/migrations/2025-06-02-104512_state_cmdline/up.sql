@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS state_cmdline (
+    state_id INTEGER NOT NULL PRIMARY KEY,
+    cmdline TEXT NOT NULL,
+    FOREIGN KEY(state_id) REFERENCES state(id) ON DELETE CASCADE
+);
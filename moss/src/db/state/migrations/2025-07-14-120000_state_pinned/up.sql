@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE state ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0;
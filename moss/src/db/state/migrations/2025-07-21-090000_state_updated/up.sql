@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE state ADD COLUMN updated BIGINT NULL;
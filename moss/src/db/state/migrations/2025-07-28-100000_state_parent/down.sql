@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+
+-- A column referencing another table can't be dropped, so rebuild the table without it
+CREATE TABLE state_new (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    type TEXT NOT NULL,
+    created BIGINT NOT NULL DEFAULT (unixepoch()),
+    summary TEXT NULL,
+    description TEXT NULL,
+    pinned BOOLEAN NOT NULL DEFAULT 0,
+    updated BIGINT NULL
+);
+
+INSERT INTO state_new (id, type, created, summary, description, pinned, updated)
+SELECT id, type, created, summary, description, pinned, updated FROM state;
+
+DROP TABLE state;
+ALTER TABLE state_new RENAME TO state;
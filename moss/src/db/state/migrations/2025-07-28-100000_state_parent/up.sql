@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Unknown for states recorded before lineage was tracked
+ALTER TABLE state ADD COLUMN parent INTEGER NULL REFERENCES state(id) ON DELETE SET NULL;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Kind an incomplete state is recorded as once its transaction completes
+ALTER TABLE state ADD COLUMN pending TEXT NULL;
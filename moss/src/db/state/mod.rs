@@ -9,8 +9,8 @@ use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use itertools::Itertools;
 
 use super::{Connection, Error, MAX_VARIABLE_NUMBER};
-use crate::state::{self, Id, Selection};
-use crate::State;
+use crate::state::{self, Id, Reason, Selection};
+use crate::{package, State};
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("src/db/state/migrations");
 
@@ -21,6 +21,15 @@ pub struct Database {
     conn: Connection,
 }
 
+/// A package selected by a state, as found by [`Database::containing`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Containing {
+    pub state: Id,
+    pub kind: state::Kind,
+    pub created: DateTime<Utc>,
+    pub selection: Selection,
+}
+
 impl Database {
     pub fn new(url: &str) -> Result<Self, Error> {
         let mut conn = SqliteConnection::establish(url)?;
@@ -60,7 +69,7 @@ impl Database {
                         Selection {
                             package: row.package_id,
                             explicit: row.explicit,
-                            reason: row.reason,
+                            reason: row.reason.map(Reason::from),
                         },
                     )
                 })
@@ -70,7 +79,8 @@ impl Database {
                 .into_iter()
                 .map(|state| {
                     let id = state.id.into();
-                    let selections = selections.remove(&id).unwrap_or_default();
+                    let mut selections = selections.remove(&id).unwrap_or_default();
+                    selections.sort_by(|a, b| a.package.cmp(&b.package));
                     State {
                         id,
                         summary: state.summary,
@@ -78,6 +88,9 @@ impl Database {
                         selections,
                         created: state.created.0,
                         kind: state.kind,
+                        pinned: state.pinned,
+                        updated: state.updated.and_then(|updated| DateTime::from_timestamp(updated, 0)),
+                        parent: state.parent.map(Id::from),
                     }
                 })
                 .collect())
@@ -92,13 +105,14 @@ impl Database {
                 .first(conn)?;
             let selections = model::Selection::belonging_to(&state)
                 .select(model::Selection::as_select())
+                .order_by(model::state_selections::package_id)
                 .load_iter(conn)?
                 .map(|result| {
                     let row = result?;
                     Ok(Selection {
                         package: row.package_id,
                         explicit: row.explicit,
-                        reason: row.reason,
+                        reason: row.reason.map(Reason::from),
                     })
                 })
                 .collect::<Result<_, Error>>()?;
@@ -110,6 +124,9 @@ impl Database {
                 selections,
                 created: state.created.0,
                 kind: state.kind,
+                pinned: state.pinned,
+                updated: state.updated.and_then(|updated| DateTime::from_timestamp(updated, 0)),
+                parent: state.parent.map(Id::from),
             })
         })
     }
@@ -120,12 +137,63 @@ impl Database {
         summary: Option<&str>,
         description: Option<&str>,
     ) -> Result<State, Error> {
+        self.add_with_kind(selections, summary, description, state::Kind::Transaction, None)
+    }
+
+    /// Record a new state of `kind`, derived from the `parent` state
+    pub fn add_with_kind(
+        &self,
+        selections: &[Selection],
+        summary: Option<&str>,
+        description: Option<&str>,
+        kind: state::Kind,
+        parent: Option<Id>,
+    ) -> Result<State, Error> {
+        self.insert(selections, summary, description, kind, None, parent)
+    }
+
+    /// Record a new state of [`state::Kind::Incomplete`] ahead of its transaction,
+    /// which becomes a state of `kind` once [completed](Self::complete)
+    pub fn add_incomplete(
+        &self,
+        selections: &[Selection],
+        summary: Option<&str>,
+        description: Option<&str>,
+        kind: state::Kind,
+        parent: Option<Id>,
+    ) -> Result<State, Error> {
+        self.insert(
+            selections,
+            summary,
+            description,
+            state::Kind::Incomplete,
+            Some(kind),
+            parent,
+        )
+    }
+
+    fn insert(
+        &self,
+        selections: &[Selection],
+        summary: Option<&str>,
+        description: Option<&str>,
+        kind: state::Kind,
+        pending: Option<state::Kind>,
+        parent: Option<Id>,
+    ) -> Result<State, Error> {
+        // Duplicates would violate the primary key of the selections
+        let mut selections = selections.to_vec();
+        let duplicates = state::dedup_selections(&mut selections);
+        let transaction = pending.unwrap_or(kind);
+
         self.conn
             .exclusive_tx(|tx| {
                 let state = model::NewState {
                     summary,
                     description,
-                    kind: state::Kind::Transaction.to_string(),
+                    kind: kind.to_string(),
+                    parent: parent.map(i32::from),
+                    pending: pending.map(|kind| kind.to_string()),
                 };
 
                 let id = diesel::insert_into(model::state::table)
@@ -139,7 +207,7 @@ impl Database {
                         state_id: id,
                         package_id: selection.package.as_ref(),
                         explicit: selection.explicit,
-                        reason: selection.reason.as_deref(),
+                        reason: selection.reason.as_ref().map(Reason::encode),
                     })
                     .collect::<Vec<_>>();
 
@@ -151,7 +219,244 @@ impl Database {
 
                 Ok(id.into())
             })
-            .and_then(|id| self.get(id))
+            .and_then(|id| {
+                for package in &duplicates {
+                    log::warn!("{transaction} of state {id} selected {package} more than once, recording it once");
+                }
+
+                self.get(id)
+            })
+    }
+
+    /// Ids and creation times of all states except incomplete and abandoned ones,
+    /// i.e. the states which can be activated
+    pub fn list_complete_ids(&self) -> Result<Vec<(Id, DateTime<Utc>)>, Error> {
+        let excluded = [state::Kind::Incomplete, state::Kind::Abandoned]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        self.conn.exec(|conn| {
+            model::state::table
+                .select(model::Created::as_select())
+                .filter(model::state::type_.ne_all(&excluded))
+                .load_iter(conn)?
+                .map(|result| {
+                    let row = result?;
+                    Ok((row.id.into(), row.created.0))
+                })
+                .collect()
+        })
+    }
+
+    /// All incomplete states, along with the kind each is recorded as once completed
+    pub fn incomplete(&self) -> Result<Vec<(State, state::Kind)>, Error> {
+        let ids = self.conn.exec(|conn| {
+            Ok(model::state::table
+                .select((model::state::id, model::state::pending))
+                .filter(model::state::type_.eq(state::Kind::Incomplete.to_string()))
+                .order_by(model::state::id)
+                .load::<(i32, Option<String>)>(conn)?)
+        })?;
+
+        ids.into_iter()
+            .map(|(id, pending)| {
+                let kind = pending.map_or(state::Kind::Transaction, state::Kind::from);
+
+                Ok((self.get(Id::from(id))?, kind))
+            })
+            .collect()
+    }
+
+    /// Complete the incomplete `state`, recording it as the kind it was added with
+    pub fn complete(&self, state: Id) -> Result<State, Error> {
+        self.conn
+            .exclusive_tx(|tx| {
+                let pending = model::state::table
+                    .select(model::state::pending)
+                    .filter(model::state::id.eq(i32::from(state)))
+                    .filter(model::state::type_.eq(state::Kind::Incomplete.to_string()))
+                    .first::<Option<String>>(tx)
+                    .optional()?
+                    .ok_or(Error::RowNotFound)?;
+                let kind = pending.map_or(state::Kind::Transaction, state::Kind::from);
+
+                diesel::update(model::state::table.find(i32::from(state)))
+                    .set((
+                        model::state::type_.eq(kind.to_string()),
+                        model::state::pending.eq(None::<String>),
+                    ))
+                    .execute(tx)?;
+
+                Ok(())
+            })
+            .and_then(|_| self.get(state))
+    }
+
+    /// Abandon the incomplete `state`, releasing its selections while keeping its
+    /// record for inspection
+    pub fn abandon(&self, state: Id) -> Result<State, Error> {
+        self.conn
+            .exclusive_tx(|tx| {
+                let updated = diesel::update(
+                    model::state::table
+                        .filter(model::state::id.eq(i32::from(state)))
+                        .filter(model::state::type_.eq(state::Kind::Incomplete.to_string())),
+                )
+                .set((
+                    model::state::type_.eq(state::Kind::Abandoned.to_string()),
+                    model::state::pending.eq(None::<String>),
+                ))
+                .execute(tx)?;
+
+                if updated == 0 {
+                    return Err(Error::RowNotFound);
+                }
+
+                diesel::delete(
+                    model::state_selections::table.filter(model::state_selections::state_id.eq(i32::from(state))),
+                )
+                .execute(tx)?;
+
+                Ok(())
+            })
+            .and_then(|_| self.get(state))
+    }
+
+    /// Pin `state`, protecting it from pruning, or unpin it
+    pub fn set_pinned(&self, state: Id, pinned: bool) -> Result<(), Error> {
+        self.conn.exclusive_tx(|tx| {
+            let updated = diesel::update(model::state::table.find(i32::from(state)))
+                .set(model::state::pinned.eq(pinned))
+                .execute(tx)?;
+
+            if updated == 0 {
+                return Err(Error::RowNotFound);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Replace the summary and description of `state`, where given, recording when
+    /// they were edited
+    ///
+    /// Empty values clear the field.
+    pub fn describe(&self, state: Id, summary: Option<&str>, description: Option<&str>) -> Result<State, Error> {
+        let value = |text: &str| (!text.is_empty()).then(|| text.to_owned());
+
+        self.conn
+            .exclusive_tx(|tx| {
+                let row = || model::state::table.find(i32::from(state));
+
+                let updated = diesel::update(row())
+                    .set(model::state::updated.eq(Utc::now().timestamp()))
+                    .execute(tx)?;
+                if updated == 0 {
+                    return Err(Error::RowNotFound);
+                }
+
+                if let Some(summary) = summary {
+                    diesel::update(row())
+                        .set(model::state::summary.eq(value(summary)))
+                        .execute(tx)?;
+                }
+                if let Some(description) = description {
+                    diesel::update(row())
+                        .set(model::state::description.eq(value(description)))
+                        .execute(tx)?;
+                }
+
+                Ok(())
+            })
+            .and_then(|_| self.get(state))
+    }
+
+    /// All selections of `packages` by any state, ordered by state
+    ///
+    /// Only the matching selections are loaded, rather than every state in full.
+    pub fn containing<'a>(
+        &self,
+        packages: impl IntoIterator<Item = &'a package::Id>,
+    ) -> Result<Vec<Containing>, Error> {
+        self.conn.exec(|conn| {
+            let packages = packages.into_iter().map(AsRef::<str>::as_ref).collect::<Vec<_>>();
+
+            let mut output = vec![];
+
+            for chunk in packages.chunks(MAX_VARIABLE_NUMBER) {
+                output.extend(
+                    model::state_selections::table
+                        .inner_join(model::state::table)
+                        .filter(model::state_selections::package_id.eq_any(chunk))
+                        .select((model::Selection::as_select(), model::State::as_select()))
+                        .load_iter::<(model::Selection, model::State), _>(conn)?
+                        .map(|result| {
+                            let (selection, state) = result?;
+                            Ok(Containing {
+                                state: state.id.into(),
+                                kind: state.kind,
+                                created: state.created.0,
+                                selection: Selection {
+                                    package: selection.package_id,
+                                    explicit: selection.explicit,
+                                    reason: selection.reason.map(Reason::from),
+                                },
+                            })
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?,
+                );
+            }
+
+            output.sort_by(|a, b| (a.state, &a.selection.package).cmp(&(b.state, &b.selection.package)));
+
+            Ok(output)
+        })
+    }
+
+    /// Mark the `packages` selected in `state` as explicit or transitive
+    pub fn set_explicit(&self, state: Id, packages: &[package::Id], explicit: bool) -> Result<(), Error> {
+        self.conn.exclusive_tx(|tx| {
+            let packages = packages.iter().map(package::Id::to_string).collect::<Vec<_>>();
+
+            // The state id is bound alongside each chunk of packages
+            for chunk in packages.chunks(MAX_VARIABLE_NUMBER - 1) {
+                diesel::update(
+                    model::state_selections::table
+                        .filter(model::state_selections::state_id.eq(i32::from(state)))
+                        .filter(model::state_selections::package_id.eq_any(chunk)),
+                )
+                .set(model::state_selections::explicit.eq(explicit))
+                .execute(tx)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// The kernel command line persisted for `state`, if any
+    pub fn cmdline(&self, state: Id) -> Result<Option<String>, Error> {
+        self.conn.exec(|conn| {
+            Ok(model::state_cmdline::table
+                .select(model::state_cmdline::cmdline)
+                .find(i32::from(state))
+                .first::<String>(conn)
+                .optional()?)
+        })
+    }
+
+    /// Persist the kernel command line of `state`, replacing any previous one
+    pub fn set_cmdline(&self, state: Id, cmdline: &str) -> Result<(), Error> {
+        self.conn.exclusive_tx(|tx| {
+            diesel::replace_into(model::state_cmdline::table)
+                .values(model::NewCmdline {
+                    state_id: i32::from(state),
+                    cmdline,
+                })
+                .execute(tx)?;
+
+            Ok(())
+        })
     }
 
     pub fn remove(&self, state: &Id) -> Result<(), Error> {
@@ -163,6 +468,8 @@ impl Database {
             let states = states.into_iter().map(|id| i32::from(*id)).collect::<Vec<_>>();
 
             for chunk in states.chunks(MAX_VARIABLE_NUMBER) {
+                diesel::delete(model::state_cmdline::table.filter(model::state_cmdline::state_id.eq_any(chunk)))
+                    .execute(tx)?;
                 // Cascading wipes other tables
                 diesel::delete(model::state::table.filter(model::state::id.eq_any(chunk))).execute(tx)?;
             }
@@ -183,7 +490,7 @@ mod model {
 
     use crate::{db::Timestamp, package, state::Kind};
 
-    pub use super::schema::{state, state_selections};
+    pub use super::schema::{state, state_cmdline, state_selections};
 
     #[derive(Queryable, Selectable, Identifiable)]
     #[diesel(table_name = state)]
@@ -196,6 +503,9 @@ mod model {
         pub description: Option<String>,
         #[diesel(column_name = "type_", deserialize_as = String)]
         pub kind: Kind,
+        pub pinned: bool,
+        pub updated: Option<i64>,
+        pub parent: Option<i32>,
     }
 
     #[derive(Queryable, Selectable, Identifiable, Associations)]
@@ -226,6 +536,8 @@ mod model {
         pub description: Option<&'a str>,
         #[diesel(column_name = "type_")]
         pub kind: String,
+        pub parent: Option<i32>,
+        pub pending: Option<String>,
     }
 
     #[derive(Insertable)]
@@ -234,7 +546,14 @@ mod model {
         pub state_id: i32,
         pub package_id: &'a str,
         pub explicit: bool,
-        pub reason: Option<&'a str>,
+        pub reason: Option<String>,
+    }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = state_cmdline)]
+    pub struct NewCmdline<'a> {
+        pub state_id: i32,
+        pub cmdline: &'a str,
     }
 }
 
@@ -270,4 +589,252 @@ mod test {
 
         assert_eq!(state.selections, selections);
     }
+
+    #[test]
+    fn mixed_selections() {
+        let database = Database::new(":memory:").unwrap();
+
+        let selections = vec![
+            Selection::explicit(package::Id::from("pkg a".to_owned())),
+            Selection::transitive(package::Id::from("pkg b".to_owned())),
+            Selection::transitive(package::Id::from("pkg c".to_owned()))
+                .reason(Reason::DependencyOf(package::Name::from("pkg a".to_owned()))),
+        ];
+        assert!(!selections[1].explicit);
+
+        let state = database.add(&selections, None, None).unwrap();
+        assert_eq!(database.get(state.id).unwrap().selections, selections);
+
+        database
+            .set_explicit(state.id, &[package::Id::from("pkg a".to_owned())], false)
+            .unwrap();
+        database
+            .set_explicit(state.id, &[package::Id::from("pkg b".to_owned())], true)
+            .unwrap();
+
+        let explicit = database.all().unwrap()[0]
+            .selections
+            .iter()
+            .map(|selection| (selection.package.to_string(), selection.explicit))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            explicit,
+            vec![
+                ("pkg a".to_owned(), false),
+                ("pkg b".to_owned(), true),
+                ("pkg c".to_owned(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn ordered_unique_selections() {
+        let database = Database::new(":memory:").unwrap();
+
+        let selections = vec![
+            Selection::transitive(package::Id::from("pkg c".to_owned())),
+            Selection::transitive(package::Id::from("pkg a".to_owned())),
+            Selection::explicit(package::Id::from("pkg c".to_owned())),
+        ];
+
+        let state = database.add(&selections, None, None).unwrap();
+
+        assert_eq!(
+            state.selections,
+            vec![
+                Selection::transitive(package::Id::from("pkg a".to_owned())),
+                Selection::explicit(package::Id::from("pkg c".to_owned())),
+            ]
+        );
+        assert_eq!(database.all().unwrap()[0].selections, state.selections);
+    }
+
+    #[test]
+    fn rollback_kind() {
+        let database = Database::new(":memory:").unwrap();
+
+        let selections = vec![Selection::explicit(package::Id::from("pkg a".to_owned()))];
+        let source = database.add(&selections, Some("Install"), None).unwrap();
+        let rollback = database
+            .add_with_kind(
+                &source.selections,
+                Some("Rollback"),
+                Some("Rollback to state #1"),
+                state::Kind::Rollback,
+                Some(source.id),
+            )
+            .unwrap();
+
+        assert_eq!(rollback.kind, state::Kind::Rollback);
+        assert_eq!(rollback.parent, Some(source.id));
+        assert_eq!(database.get(source.id).unwrap().parent, None);
+        assert_eq!(rollback.selections, source.selections);
+        assert_eq!(database.get(source.id).unwrap().kind, state::Kind::Transaction);
+    }
+
+    #[test]
+    fn pin_state() {
+        let database = Database::new(":memory:").unwrap();
+        let state = database.add(&[], None, None).unwrap();
+        assert!(!state.pinned);
+
+        database.set_pinned(state.id, true).unwrap();
+        assert!(database.get(state.id).unwrap().pinned);
+        assert!(database.all().unwrap()[0].pinned);
+
+        database.set_pinned(state.id, false).unwrap();
+        assert!(!database.get(state.id).unwrap().pinned);
+
+        assert!(matches!(
+            database.set_pinned(Id::from(42), true),
+            Err(Error::RowNotFound)
+        ));
+    }
+
+    #[test]
+    fn describe_state() {
+        let database = Database::new(":memory:").unwrap();
+        let state = database.add(&[], Some("Install"), None).unwrap();
+        assert_eq!(state.updated, None);
+
+        let described = database
+            .describe(state.id, None, Some("last known good before NVIDIA 550"))
+            .unwrap();
+        assert_eq!(described.summary.as_deref(), Some("Install"));
+        assert_eq!(
+            described.description.as_deref(),
+            Some("last known good before NVIDIA 550")
+        );
+        assert!(described.updated.is_some());
+
+        let cleared = database.describe(state.id, Some(""), None).unwrap();
+        assert_eq!(cleared.summary, None);
+        assert_eq!(cleared.description, described.description);
+
+        assert!(matches!(
+            database.describe(Id::from(42), Some("missing"), None),
+            Err(Error::RowNotFound)
+        ));
+    }
+
+    #[test]
+    fn containing_packages() {
+        let database = Database::new(":memory:").unwrap();
+        let a = package::Id::from("pkg a".to_owned());
+        let b = package::Id::from("pkg b".to_owned());
+        let c = package::Id::from("pkg c".to_owned());
+
+        let first = database
+            .add(
+                &[Selection::explicit(a.clone()), Selection::transitive(b.clone())],
+                None,
+                None,
+            )
+            .unwrap();
+        let second = database
+            .add_with_kind(
+                &[Selection::explicit(b.clone())],
+                None,
+                None,
+                state::Kind::Rollback,
+                Some(first.id),
+            )
+            .unwrap();
+        database.add(&[Selection::explicit(c)], None, None).unwrap();
+
+        let found = database.containing([&b]).unwrap();
+        assert_eq!(
+            found
+                .iter()
+                .map(|containing| (containing.state, containing.kind, containing.selection.explicit))
+                .collect::<Vec<_>>(),
+            vec![
+                (first.id, state::Kind::Transaction, false),
+                (second.id, state::Kind::Rollback, true)
+            ]
+        );
+        assert_eq!(found[0].created, first.created);
+
+        assert_eq!(database.containing([&a, &b]).unwrap().len(), 3);
+        assert!(database
+            .containing([&package::Id::from("pkg d".to_owned())])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn incomplete_states() {
+        let database = Database::new(":memory:").unwrap();
+        let selections = vec![Selection::explicit(package::Id::from("pkg a".to_owned()))];
+
+        let complete = database.add(&selections, Some("Install"), None).unwrap();
+        let rollback = database
+            .add_incomplete(&selections, None, None, state::Kind::Rollback, Some(complete.id))
+            .unwrap();
+        let interrupted = database
+            .add_incomplete(&selections, None, None, state::Kind::Transaction, Some(complete.id))
+            .unwrap();
+        assert_eq!(rollback.kind, state::Kind::Incomplete);
+
+        let ids = |list: Vec<(Id, DateTime<Utc>)>| list.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids(database.list_complete_ids().unwrap()), vec![complete.id]);
+        assert_eq!(database.list_ids().unwrap().len(), 3);
+        assert_eq!(
+            database
+                .incomplete()
+                .unwrap()
+                .into_iter()
+                .map(|(state, kind)| (state.id, kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (rollback.id, state::Kind::Rollback),
+                (interrupted.id, state::Kind::Transaction)
+            ]
+        );
+
+        let completed = database.complete(rollback.id).unwrap();
+        assert_eq!(completed.kind, state::Kind::Rollback);
+        assert_eq!(completed.selections, selections);
+        assert!(matches!(database.complete(rollback.id), Err(Error::RowNotFound)));
+
+        let abandoned = database.abandon(interrupted.id).unwrap();
+        assert_eq!(abandoned.kind, state::Kind::Abandoned);
+        assert!(abandoned.selections.is_empty());
+        assert!(database.incomplete().unwrap().is_empty());
+        assert_eq!(
+            ids(database.list_complete_ids().unwrap()),
+            vec![complete.id, rollback.id]
+        );
+    }
+
+    #[test]
+    fn persist_cmdline() {
+        let database = Database::new(":memory:").unwrap();
+        let state = database.add(&[], None, None).unwrap();
+
+        assert_eq!(database.cmdline(state.id).unwrap(), None);
+
+        database.set_cmdline(state.id, "quiet splash").unwrap();
+        database.set_cmdline(state.id, "quiet").unwrap();
+        assert_eq!(database.cmdline(state.id).unwrap().as_deref(), Some("quiet"));
+
+        database.remove(&state.id).unwrap();
+        assert_eq!(database.cmdline(state.id).unwrap(), None);
+    }
+
+    #[test]
+    fn revert_migrations() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        diesel::sql_query("INSERT INTO state (id, type) VALUES (1, 'transaction')")
+            .execute(&mut conn)
+            .unwrap();
+        diesel::sql_query("INSERT INTO state (id, type, parent) VALUES (2, 'transaction', 1)")
+            .execute(&mut conn)
+            .unwrap();
+
+        conn.revert_all_migrations(MIGRATIONS).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+    }
 }
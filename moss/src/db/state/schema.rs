@@ -8,6 +8,17 @@ diesel::table! {
         created -> BigInt,
         summary -> Nullable<Text>,
         description -> Nullable<Text>,
+        pinned -> Bool,
+        updated -> Nullable<BigInt>,
+        parent -> Nullable<Integer>,
+        pending -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    state_cmdline (state_id) {
+        state_id -> Integer,
+        cmdline -> Text,
     }
 }
 
@@ -20,6 +31,7 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(state_cmdline -> state (state_id));
 diesel::joinable!(state_selections -> state (state_id));
 
-diesel::allow_tables_to_appear_in_same_query!(state, state_selections);
+diesel::allow_tables_to_appear_in_same_query!(state, state_cmdline, state_selections);
@@ -5,6 +5,7 @@
 use std::collections::BTreeSet;
 
 use derive_more::{AsRef, Display, From, Into};
+use serde::{Deserialize, Serialize};
 use stone::payload;
 use thiserror::Error;
 
@@ -15,7 +16,7 @@ use crate::{dependency, Dependency, Provider};
 pub struct Id(pub(super) String);
 
 /// The name of a [`super::Package`]
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, AsRef, From, Into, Display)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, AsRef, From, Into, Display, Serialize, Deserialize)]
 pub struct Name(String);
 
 impl Name {
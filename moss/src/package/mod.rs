@@ -4,6 +4,7 @@
 
 use derive_more::{AsRef, Display, From, Into};
 use itertools::Itertools;
+use serde::Serialize;
 
 pub use self::meta::{Meta, MissingMetaFieldError, Name};
 
@@ -11,7 +12,9 @@ pub mod meta;
 pub mod render;
 
 /// Unique ID of a [`Package`]
-#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, From, Into, AsRef, Display)]
+#[derive(
+    Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, From, Into, AsRef, Display, Serialize, Deserialize,
+)]
 #[as_ref(forward)]
 pub struct Id(String);
 
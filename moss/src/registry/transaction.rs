@@ -2,6 +2,8 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::collections::BTreeMap;
+
 use dag::Dag;
 use thiserror::Error;
 
@@ -40,6 +42,9 @@ pub struct Transaction<'a> {
     /// during [`ProviderFilter::Pinned`] but
     /// aren't part of `packages` DAG
     pinned_providers: Vec<package::Id>,
+
+    /// the package which first pulled in each dependency
+    dependency_of: BTreeMap<package::Id, package::Id>,
 }
 
 /// Construct a new Transaction wrapped around the underlying Registry
@@ -50,6 +55,7 @@ pub(super) fn new(registry: &Registry) -> Result<Transaction<'_>, Error> {
         registry,
         packages: Dag::default(),
         pinned_providers: vec![],
+        dependency_of: BTreeMap::new(),
     })
 }
 
@@ -84,6 +90,13 @@ impl Transaction<'_> {
         });
     }
 
+    /// Return the package which pulled `package` into the transaction as its dependency
+    ///
+    /// Packages added directly have no such package.
+    pub fn dependency_of(&self, package: &package::Id) -> Option<&package::Id> {
+        self.dependency_of.get(package)
+    }
+
     /// Return the package IDs in the fully baked configuration
     pub fn finalize(&self) -> impl Iterator<Item = &package::Id> + '_ {
         self.packages.topo()
@@ -126,6 +139,7 @@ impl Transaction<'_> {
 
                     // No dag node for it previously
                     if need_search {
+                        self.dependency_of.insert(search.clone(), check_id.clone());
                         next.push(search.clone());
                     }
 
@@ -259,6 +259,15 @@ impl Manager {
         self.repositories.iter().map(|(id, state)| (id, &state.repository))
     }
 
+    /// Digest of the cached index of the repository `id`, identifying the version
+    /// of its metadata, unless it was never fetched
+    pub fn index_digest(&self, id: &repository::Id) -> Option<String> {
+        let cached = self.repositories.get(id)?;
+        let index = cache_dir(self.source.identifier(), &cached.repository, &self.installation).join("stone.index");
+
+        fs::read(index).ok().map(|bytes| format!("{:02x}", xxh3_64(&bytes)))
+    }
+
     /// Sets the repo as active or not
     async fn set_active(&mut self, id: &repository::Id, active: bool) -> Result<(), Error> {
         // Only allow disable for system repo manager
@@ -2,16 +2,27 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::io::Write;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    io::Write,
+    str::FromStr,
+};
 
 use chrono::{DateTime, Utc};
 use derive_more::{Display, From, Into};
+use itertools::{EitherOrBoth, Itertools};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tui::{pretty, Styled};
 
 use crate::package;
 
 /// Unique identifier for [`State`]
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, From, Into, Display)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, From, Into, Display, Serialize, Deserialize,
+)]
 pub struct Id(i32);
 
 impl Id {
@@ -21,6 +32,57 @@ impl Id {
     }
 }
 
+/// A state given by id or relative to the active state
+///
+/// Parsed from `<id>`, `active`, `previous`, `active~<n>` or `latest`, and resolved
+/// against the recorded states by [`crate::Client::resolve_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference {
+    Id(Id),
+    /// The state `n` states before the active state, or the active state itself
+    Active(u32),
+    /// The most recently recorded state
+    Latest,
+}
+
+impl FromStr for Reference {
+    type Err = ParseReferenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseReferenceError(s.to_owned());
+
+        match s {
+            "active" => Ok(Reference::Active(0)),
+            "previous" => Ok(Reference::Active(1)),
+            "latest" => Ok(Reference::Latest),
+            _ => match s.strip_prefix("active~") {
+                Some(n) => n.parse().map(Reference::Active).map_err(|_| invalid()),
+                None => s
+                    .parse::<u32>()
+                    .ok()
+                    .and_then(|id| i32::try_from(id).ok())
+                    .map(|id| Reference::Id(Id(id)))
+                    .ok_or_else(invalid),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reference::Id(id) => write!(f, "{id}"),
+            Reference::Active(0) => write!(f, "active"),
+            Reference::Active(n) => write!(f, "active~{n}"),
+            Reference::Latest => write!(f, "latest"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid state `{0}`, expected an id, `active`, `previous`, `active~<n>` or `latest`")]
+pub struct ParseReferenceError(String);
+
 /// State types
 #[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
 #[repr(u8)]
@@ -28,16 +90,36 @@ impl Id {
 pub enum Kind {
     /// Automatically constructed state
     Transaction,
+    /// Copy of a previous state, named in the description
+    Rollback,
+    /// User requested copy of the active state
+    Snapshot,
+    /// Copy of a state whose installed files were repaired
+    Repair,
+    /// Recorded ahead of a transaction which hasn't completed (yet)
+    Incomplete,
+    /// Incomplete state given up on, its selections released
+    Abandoned,
+    /// Recorded by a newer moss, never created
+    Unknown,
 }
 
-impl TryFrom<String> for Kind {
-    type Error = strum::ParseError;
+impl Kind {
+    /// Returns false for states whose transaction never completed, which can't be
+    /// activated or booted
+    pub fn is_complete(self) -> bool {
+        !matches!(self, Kind::Incomplete | Kind::Abandoned)
+    }
+}
 
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        value.parse()
+/// Kinds recorded by a newer moss degrade to [`Kind::Unknown`] rather than failing
+impl From<String> for Kind {
+    fn from(value: String) -> Self {
+        value.parse().unwrap_or(Kind::Unknown)
     }
 }
 
+/// Selections are always ordered by package id, see [`dedup_selections`]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
     /// Unique identifier for this state
@@ -52,18 +134,95 @@ pub struct State {
     pub created: DateTime<Utc>,
     /// Relevant type for this State
     pub kind: Kind,
+    /// Whether the state is protected from pruning
+    pub pinned: bool,
+    /// When the summary or description were last edited, if ever
+    pub updated: Option<DateTime<Utc>>,
+    /// The state this state was derived from, unknown for states
+    /// recorded before lineage was tracked
+    pub parent: Option<Id>,
 }
 
 /// The Selection records the presence of a package ID in a [`State`]
 /// It also records whether it was selected as a transitive dependency,
-/// along with an optional human-readable reason
+/// along with an optional reason
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Selection {
     pub package: package::Id,
     /// Marks whether the package was explicitly installed
     /// by the user, or if it's a "transitive" dependency
     pub explicit: bool,
-    pub reason: Option<String>,
+    pub reason: Option<Reason>,
+}
+
+/// Sort `selections` by package id, merging duplicate selections of the same package
+///
+/// A merged selection is explicit if any of its duplicates were, keeping the first
+/// known reason. Returns the ids of all duplicated packages.
+pub fn dedup_selections(selections: &mut Vec<Selection>) -> Vec<package::Id> {
+    selections.sort_by(|a, b| a.package.cmp(&b.package));
+
+    let mut duplicates = vec![];
+    selections.dedup_by(|duplicate, kept| {
+        if duplicate.package != kept.package {
+            return false;
+        }
+
+        kept.explicit |= duplicate.explicit;
+        if kept.reason.is_none() {
+            kept.reason = duplicate.reason.take();
+        }
+        duplicates.push(duplicate.package.clone());
+
+        true
+    });
+    duplicates.dedup();
+
+    duplicates
+}
+
+/// Why a package entered a state
+///
+/// Stored as JSON, see [`Reason::encode`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// Requested by the user via a moss command, e.g. `install`
+    Requested { by_command: String },
+    /// Pulled in as a dependency of the named package
+    DependencyOf(package::Name),
+    /// Recommended by another package
+    Recommended,
+    /// Reinstalled as part of a rebuild
+    Rebuilt,
+    /// Free-form reason recorded by an older moss
+    Legacy(String),
+}
+
+impl Reason {
+    /// Encode the reason for storage in the state db
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("reason is always serializable")
+    }
+}
+
+/// Reasons recorded before they were structured are kept as [`Reason::Legacy`]
+impl From<String> for Reason {
+    fn from(value: String) -> Self {
+        serde_json::from_str(&value).unwrap_or(Reason::Legacy(value))
+    }
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Requested { by_command } => write!(f, "Requested via moss {by_command}"),
+            Reason::DependencyOf(name) => write!(f, "Dependency of {name}"),
+            Reason::Recommended => write!(f, "Recommended"),
+            Reason::Rebuilt => write!(f, "Rebuilt"),
+            Reason::Legacy(reason) => write!(f, "{reason}"),
+        }
+    }
 }
 
 impl Selection {
@@ -80,29 +239,904 @@ impl Selection {
     pub fn transitive(package: package::Id) -> Self {
         Self {
             package,
-            explicit: true,
+            explicit: false,
             reason: None,
         }
     }
 
     /// Record a reason for the Selection entering the state
-    pub fn reason(self, reason: impl ToString) -> Self {
+    pub fn reason(self, reason: Reason) -> Self {
         Self {
-            reason: Some(reason.to_string()),
+            reason: Some(reason),
             ..self
         }
     }
 }
 
+/// Mark the `selections` of `packages` as explicit or transitive, returning the
+/// packages whose flag changed
+///
+/// Marking a selection explicit records it as requested via `moss mark`, while a
+/// request recorded for a selection marked transitive no longer applies.
+pub fn mark_selections(selections: &mut [Selection], packages: &[package::Id], explicit: bool) -> Vec<package::Id> {
+    selections
+        .iter_mut()
+        .filter(|selection| selection.explicit != explicit && packages.contains(&selection.package))
+        .map(|selection| {
+            selection.explicit = explicit;
+            if explicit {
+                selection.reason = Some(Reason::Requested {
+                    by_command: "mark".to_owned(),
+                });
+            } else if matches!(selection.reason, Some(Reason::Requested { .. })) {
+                selection.reason = None;
+            }
+
+            selection.package.clone()
+        })
+        .collect()
+}
+
+/// Version of the [`Manifest`] format written by this moss
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// A portable description of a state, pinning each of its packages by hash
+///
+/// Unlike the state db it's self-contained, so a state exported on one machine can
+/// be recreated on any other with access to the same packages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Format version, see [`MANIFEST_VERSION`]
+    pub version: u32,
+    /// The exported state
+    pub state: Id,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Creation time of the exported state, in RFC 3339
+    pub created: String,
+    /// Repositories active when exporting
+    #[serde(default)]
+    pub repositories: Vec<ManifestRepository>,
+    pub packages: Vec<ManifestPackage>,
+}
+
+/// A repository of a [`Manifest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestRepository {
+    pub id: String,
+    pub uri: String,
+    /// Digest of the cached repository index, unless never fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+}
+
+/// A package of a [`Manifest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestPackage {
+    pub name: package::Name,
+    pub version: String,
+    pub source_release: u64,
+    pub build_release: u64,
+    pub architecture: String,
+    /// Hash identifying the exact package
+    pub hash: package::Id,
+    pub explicit: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Reason>,
+}
+
+impl fmt::Display for ManifestPackage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}-{}-{} ({})",
+            self.name, self.version, self.source_release, self.build_release, self.hash
+        )
+    }
+}
+
+impl Manifest {
+    /// Parse a manifest, refusing those written in a newer format
+    pub fn from_json(json: &str) -> Result<Self, ManifestError> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+
+        let Versioned { version } = serde_json::from_str(json)?;
+        if version > MANIFEST_VERSION {
+            return Err(ManifestError::UnsupportedVersion(version));
+        }
+
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Encode the manifest as pretty printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("manifest is always serializable")
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("manifest version {0} is newer than the supported version {MANIFEST_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("invalid manifest")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A package selected in a state, as compared by [`diff`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffPackage {
+    /// Package name, or the id if its metadata is unknown
+    pub name: String,
+    pub id: package::Id,
+    /// Version and source release, if the metadata is known
+    pub version: Option<String>,
+    /// Version and releases, if the metadata is known
+    pub release: Option<Release>,
+    pub explicit: bool,
+}
+
+/// Version identifier and releases of a [`DiffPackage`]
+///
+/// Ordered by version, then source release and finally build release, so a version
+/// bump resetting the source release still orders after the previous version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Release {
+    pub version: String,
+    pub source: u64,
+    pub build: u64,
+}
+
+impl PartialOrd for Release {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Release {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_versions(&self.version, &other.version)
+            .then_with(|| self.source.cmp(&other.source))
+            .then_with(|| self.build.cmp(&other.build))
+    }
+}
+
+/// Compare version identifiers component by component, numerically where both
+/// components are numbers, e.g. `6.10` is newer than `6.9`
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn components(version: &str) -> impl Iterator<Item = &str> {
+        version.split(|c: char| !c.is_ascii_alphanumeric())
+    }
+
+    components(a)
+        .zip_longest(components(b))
+        .map(|pair| match pair {
+            EitherOrBoth::Both(a, b) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            },
+            EitherOrBoth::Left(_) => Ordering::Greater,
+            EitherOrBoth::Right(_) => Ordering::Less,
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// How the package of a [`DiffChange`] changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    Upgrade,
+    Downgrade,
+    /// Same version and releases, e.g. a rebuild, or unknown metadata
+    Unchanged,
+}
+
+/// A package selected in both states with differing ids, i.e. an upgrade or downgrade
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffChange {
+    pub from: DiffPackage,
+    pub to: DiffPackage,
+}
+
+impl DiffChange {
+    /// Whether the package was upgraded or downgraded, per its [`Release`]
+    pub fn direction(&self) -> Direction {
+        match (&self.from.release, &self.to.release) {
+            (Some(from), Some(to)) => match from.cmp(to) {
+                Ordering::Less => Direction::Upgrade,
+                Ordering::Greater => Direction::Downgrade,
+                Ordering::Equal => Direction::Unchanged,
+            },
+            _ => Direction::Unchanged,
+        }
+    }
+}
+
+/// The differences between the selections of two states
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StateDiff {
+    pub from: Id,
+    pub to: Id,
+    /// Packages only selected in `to`
+    pub added: Vec<DiffPackage>,
+    /// Packages only selected in `from`
+    pub removed: Vec<DiffPackage>,
+    /// Packages changing version
+    pub changed: Vec<DiffChange>,
+    /// Packages selected in both states whose explicit flag flipped, as selected in `to`
+    pub flipped: Vec<DiffPackage>,
+}
+
+impl StateDiff {
+    /// Returns true if both states select identical packages
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.flipped.is_empty()
+    }
+
+    /// All differences in display order
+    pub fn lines(&self) -> Vec<DiffLine<'_>> {
+        self.removed
+            .iter()
+            .map(DiffLine::Removed)
+            .chain(self.added.iter().map(DiffLine::Added))
+            .chain(self.changed.iter().map(DiffLine::Changed))
+            .chain(self.flipped.iter().map(DiffLine::Flipped))
+            .collect()
+    }
+
+    /// One line summary, e.g. `+3 installed, 2 removed, 14 upgraded (firefox 128-1 → 129-1, …)`
+    pub fn summary(&self) -> String {
+        let direction = |direction| {
+            self.changed
+                .iter()
+                .filter(|change| change.direction() == direction)
+                .count()
+        };
+        let explicit = self.flipped.iter().filter(|package| package.explicit).count();
+        let counts = [
+            (self.added.len(), "+", "installed"),
+            (self.removed.len(), "", "removed"),
+            (direction(Direction::Upgrade), "", "upgraded"),
+            (direction(Direction::Downgrade), "", "downgraded"),
+            (direction(Direction::Unchanged), "", "changed"),
+            (explicit, "", "now explicit"),
+            (self.flipped.len() - explicit, "", "now transitive"),
+        ]
+        .into_iter()
+        .filter(|(count, _, _)| *count > 0)
+        .map(|(count, sign, what)| format!("{sign}{count} {what}"))
+        .collect::<Vec<_>>();
+
+        if counts.is_empty() {
+            return "No package changes".to_owned();
+        }
+
+        let mut packages = self
+            .changed
+            .iter()
+            .map(|change| match (&change.from.version, &change.to.version) {
+                (Some(from), Some(to)) => format!("{} {from} → {to}", change.to.name),
+                _ => change.to.name.clone(),
+            })
+            .chain(self.added.iter().map(|package| package.name.clone()))
+            .chain(self.removed.iter().map(|package| package.name.clone()))
+            .collect::<Vec<_>>();
+        if packages.len() > SUMMARY_PACKAGES {
+            packages.truncate(SUMMARY_PACKAGES);
+            packages.push("…".to_owned());
+        }
+
+        if packages.is_empty() {
+            counts.join(", ")
+        } else {
+            format!("{} ({})", counts.join(", "), packages.join(", "))
+        }
+    }
+
+    /// Every difference, one per line
+    pub fn description(&self) -> String {
+        self.lines().iter().map(ToString::to_string).join("\n")
+    }
+}
+
+/// Packages named at most by [`StateDiff::summary`]
+const SUMMARY_PACKAGES: usize = 3;
+
+/// Summary and description of a new state selecting `selections`, derived from
+/// its `parent` state
+///
+/// The description lists every change, if any.
+pub fn summarize(
+    parent: Option<&State>,
+    selections: &[Selection],
+    meta: impl Fn(&package::Id) -> Option<package::Meta>,
+) -> (String, Option<String>) {
+    let from = parent.map_or(Id::default(), |parent| parent.id);
+    let diff = diff_selections(
+        (from, parent.map_or(&[][..], |parent| &parent.selections)),
+        (from.next(), selections),
+        meta,
+    );
+
+    (diff.summary(), (!diff.is_empty()).then(|| diff.description()))
+}
+
+/// Compare the selections of state `a` against those of state `b`
+///
+/// Packages are matched by name, as resolved through `meta`, so that a differing
+/// id for the same name is reported as changed rather than removed and added.
+pub fn diff(a: &State, b: &State, meta: impl Fn(&package::Id) -> Option<package::Meta>) -> StateDiff {
+    diff_selections((a.id, &a.selections), (b.id, &b.selections), meta)
+}
+
+/// Compare the `a` selections of a state against the `b` selections, as [`diff`] does
+fn diff_selections(
+    a: (Id, &[Selection]),
+    b: (Id, &[Selection]),
+    meta: impl Fn(&package::Id) -> Option<package::Meta>,
+) -> StateDiff {
+    let packages = |selections: &[Selection]| {
+        selections
+            .iter()
+            .map(|selection| {
+                let meta = meta(&selection.package);
+                let package = DiffPackage {
+                    name: meta
+                        .as_ref()
+                        .map_or_else(|| selection.package.to_string(), |meta| meta.name.to_string()),
+                    id: selection.package.clone(),
+                    version: meta
+                        .as_ref()
+                        .map(|meta| format!("{}-{}", meta.version_identifier, meta.source_release)),
+                    release: meta.as_ref().map(|meta| Release {
+                        version: meta.version_identifier.clone(),
+                        source: meta.source_release,
+                        build: meta.build_release,
+                    }),
+                    explicit: selection.explicit,
+                };
+
+                (package.name.clone(), package)
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+
+    let from = packages(a.1);
+    let mut to = packages(b.1);
+
+    let mut diff = StateDiff {
+        from: a.0,
+        to: b.0,
+        added: vec![],
+        removed: vec![],
+        changed: vec![],
+        flipped: vec![],
+    };
+
+    for (name, old) in from {
+        let Some(new) = to.remove(&name) else {
+            diff.removed.push(old);
+            continue;
+        };
+
+        if old.explicit != new.explicit {
+            diff.flipped.push(new.clone());
+        }
+        if old.id != new.id {
+            diff.changed.push(DiffChange { from: old, to: new });
+        }
+    }
+    diff.added.extend(to.into_values());
+
+    diff
+}
+
+/// Columnar display of a single difference of a [`StateDiff`]
+pub enum DiffLine<'a> {
+    Added(&'a DiffPackage),
+    Removed(&'a DiffPackage),
+    Changed(&'a DiffChange),
+    Flipped(&'a DiffPackage),
+}
+
+impl DiffLine<'_> {
+    fn detail(&self) -> String {
+        let version = |package: &DiffPackage| package.version.clone().unwrap_or_else(|| "?".to_owned());
+
+        match self {
+            DiffLine::Added(package) | DiffLine::Removed(package) => version(package),
+            DiffLine::Changed(change) => format!("{} → {}", version(&change.from), version(&change.to)),
+            DiffLine::Flipped(package) if package.explicit => "now explicit".to_owned(),
+            DiffLine::Flipped(_) => "now transitive".to_owned(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            DiffLine::Added(package) | DiffLine::Removed(package) | DiffLine::Flipped(package) => &package.name,
+            DiffLine::Changed(change) => &change.to.name,
+        }
+    }
+}
+
+/// Plain text of the difference, e.g. `+ curl 8.11-1`
+impl fmt::Display for DiffLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = match self {
+            DiffLine::Added(_) => "+",
+            DiffLine::Removed(_) => "-",
+            DiffLine::Changed(_) | DiffLine::Flipped(_) => "~",
+        };
+
+        write!(f, "{marker} {} {}", self.name(), self.detail())
+    }
+}
+
+impl pretty::ColumnDisplay for DiffLine<'_> {
+    fn get_display_width(&self) -> usize {
+        "+ ".len() + self.name().len() + 1 + self.detail().chars().count() + 3
+    }
+
+    fn display_column(&self, writer: &mut impl Write, col: pretty::Column, width: usize) {
+        let marker = match self {
+            DiffLine::Added(_) => "+".green(),
+            DiffLine::Removed(_) => "-".red(),
+            DiffLine::Changed(_) | DiffLine::Flipped(_) => "~".yellow(),
+        };
+        let detail = match self {
+            DiffLine::Flipped(_) => self.detail().dim(),
+            _ => self.detail().magenta(),
+        };
+
+        let _ = write!(writer, "{marker} {} {:width$}{detail}", self.name().bold(), " ");
+
+        if col != pretty::Column::Last {
+            let _ = write!(writer, "   ");
+        }
+    }
+}
+
+/// A state within the lineage [`tree`] of all states
+pub struct TreeNode<'a> {
+    /// Branch connectors preceding the state
+    pub prefix: String,
+    pub state: &'a State,
+}
+
+/// Order all `states` as a lineage tree, oldest first
+///
+/// Each state is followed by the newest state derived from it at the same depth,
+/// any other states derived from it (e.g. before a rollback) branch off indented.
+pub fn tree(states: &[State]) -> Vec<TreeNode<'_>> {
+    let ids = states.iter().map(|state| state.id).collect::<BTreeSet<_>>();
+    let mut children = BTreeMap::<Option<Id>, Vec<&State>>::new();
+    for state in states.iter().sorted_by_key(|state| state.id) {
+        // Parents may have been pruned
+        let parent = state.parent.filter(|parent| ids.contains(parent));
+        children.entry(parent).or_default().push(state);
+    }
+
+    /// Push `state` and its lineage, `first` prefixing the state and `rest` everything after it
+    fn walk<'a>(
+        state: &'a State,
+        first: String,
+        rest: &str,
+        children: &BTreeMap<Option<Id>, Vec<&'a State>>,
+        nodes: &mut Vec<TreeNode<'a>>,
+    ) {
+        let mut next = Some((state, first));
+
+        while let Some((state, prefix)) = next.take() {
+            nodes.push(TreeNode { prefix, state });
+
+            if let Some((mainline, branches)) = children.get(&Some(state.id)).and_then(|kids| kids.split_last()) {
+                for branch in branches {
+                    walk(branch, format!("{rest}├─ "), &format!("{rest}│  "), children, nodes);
+                }
+                next = Some((mainline, rest.to_owned()));
+            }
+        }
+    }
+
+    let mut nodes = vec![];
+    for root in children.get(&None).into_iter().flatten() {
+        walk(root, String::new(), "", &children, &mut nodes);
+    }
+    nodes
+}
+
+impl pretty::ColumnDisplay for TreeNode<'_> {
+    fn get_display_width(&self) -> usize {
+        self.prefix.chars().count() + "State ".len() + self.state.id.to_string().len()
+    }
+
+    fn display_column(&self, writer: &mut impl Write, _col: pretty::Column, width: usize) {
+        let _ = write!(
+            writer,
+            "{}State {}{:width$}{} {}",
+            self.prefix.clone().dim(),
+            self.state.id.to_string().bold(),
+            " ",
+            self.state.kind.to_string().magenta(),
+            self.state.summary.as_deref().unwrap_or_default()
+        );
+        if self.state.pinned {
+            let _ = write!(writer, "{}", PINNED.yellow());
+        }
+    }
+}
+
+/// Marker of pinned states in column output
+const PINNED: &str = " (pinned)";
+
 /// Columnar display encapsulation for a [`State`]
 pub struct ColumnDisplay<'a>(pub &'a State);
 
 impl pretty::ColumnDisplay for ColumnDisplay<'_> {
     fn get_display_width(&self) -> usize {
-        "State ".len() + self.0.id.to_string().len()
+        "State ".len() + self.0.id.to_string().len() + if self.0.pinned { PINNED.len() } else { 0 }
     }
 
     fn display_column(&self, writer: &mut impl Write, _col: pretty::Column, width: usize) {
-        let _ = write!(writer, "State {}{:width$}", self.0.id.to_string().bold(), " ");
+        let _ = write!(writer, "State {}", self.0.id.to_string().bold());
+        if self.0.pinned {
+            let _ = write!(writer, "{}", PINNED.yellow());
+        }
+        let _ = write!(writer, "{:width$}", " ");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_states() {
+        let state = |id: i32, selections: &[(&str, bool)]| State {
+            id: Id(id),
+            summary: None,
+            description: None,
+            selections: selections
+                .iter()
+                .map(|(package, explicit)| Selection {
+                    package: package::Id::from((*package).to_owned()),
+                    explicit: *explicit,
+                    reason: None,
+                })
+                .collect(),
+            created: DateTime::default(),
+            kind: Kind::Transaction,
+            pinned: false,
+            updated: None,
+            parent: None,
+        };
+        // Ids are `<name>@<version>`, unknown to the metadata when lacking a version
+        let meta = |id: &package::Id| {
+            let id = id.to_string();
+            let (name, version) = id.split_once('@')?;
+
+            Some(package::Meta {
+                name: package::Name::from(name.to_owned()),
+                version_identifier: version.to_owned(),
+                source_release: 1,
+                build_release: Default::default(),
+                architecture: Default::default(),
+                summary: Default::default(),
+                description: Default::default(),
+                source_id: Default::default(),
+                homepage: Default::default(),
+                licenses: Default::default(),
+                dependencies: Default::default(),
+                providers: Default::default(),
+                conflicts: Default::default(),
+                uri: Default::default(),
+                hash: Default::default(),
+                download_size: Default::default(),
+            })
+        };
+
+        let a = state(
+            1,
+            &[
+                ("nano@8.2", true),
+                ("libc@2.40", true),
+                ("zlib@1.3", false),
+                ("orphan", true),
+            ],
+        );
+        let b = state(
+            2,
+            &[
+                ("nano@8.3", true),
+                ("libc@2.40", false),
+                ("curl@8.11", true),
+                ("orphan", true),
+            ],
+        );
+
+        let diff = diff(&a, &b, meta);
+
+        assert_eq!((diff.from, diff.to), (Id(1), Id(2)));
+        assert_eq!(
+            diff.added
+                .iter()
+                .map(|package| package.name.as_str())
+                .collect::<Vec<_>>(),
+            ["curl"]
+        );
+        assert_eq!(
+            diff.removed
+                .iter()
+                .map(|package| package.name.as_str())
+                .collect::<Vec<_>>(),
+            ["zlib"]
+        );
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].from.version.as_deref(), Some("8.2-1"));
+        assert_eq!(diff.changed[0].to.version.as_deref(), Some("8.3-1"));
+        assert_eq!(diff.flipped.len(), 1);
+        assert!(!diff.flipped[0].explicit && diff.flipped[0].name == "libc");
+        assert_eq!(diff.lines().len(), 4);
+        assert_eq!(
+            diff.summary(),
+            "+1 installed, 1 removed, 1 upgraded, 1 now transitive (nano 8.2-1 → 8.3-1, curl, zlib)"
+        );
+        assert_eq!(
+            diff.description(),
+            "- zlib 1.3-1\n+ curl 8.11-1\n~ nano 8.2-1 → 8.3-1\n~ libc now transitive"
+        );
+
+        let (summary, description) = summarize(None, &a.selections, meta);
+        assert_eq!(summary, "+4 installed (libc, nano, orphan, …)");
+        assert_eq!(description.map(|description| description.lines().count()), Some(4));
+        assert_eq!(
+            summarize(Some(&b), &b.selections, meta),
+            ("No package changes".to_owned(), None)
+        );
+
+        assert!(super::diff(&a, &a, meta).is_empty());
+    }
+
+    #[test]
+    fn change_direction() {
+        let change = |from: (&str, u64, u64), to: (&str, u64, u64)| {
+            let package = |(version, source, build): (&str, u64, u64)| DiffPackage {
+                name: "nano".to_owned(),
+                id: package::Id::from(format!("nano@{version}-{source}-{build}")),
+                version: Some(format!("{version}-{source}")),
+                release: Some(Release {
+                    version: version.to_owned(),
+                    source,
+                    build,
+                }),
+                explicit: true,
+            };
+
+            DiffChange {
+                from: package(from),
+                to: package(to),
+            }
+            .direction()
+        };
+
+        assert_eq!(change(("8.2", 1, 1), ("8.3", 1, 1)), Direction::Upgrade);
+        // Version bumps reset the source release
+        assert_eq!(change(("8.2", 5, 1), ("8.3", 1, 1)), Direction::Upgrade);
+        assert_eq!(change(("6.9", 1, 1), ("6.10", 1, 1)), Direction::Upgrade);
+        assert_eq!(change(("8.2", 1, 1), ("8.2", 2, 1)), Direction::Upgrade);
+        assert_eq!(change(("8.2", 1, 1), ("8.2", 1, 2)), Direction::Upgrade);
+        assert_eq!(change(("8.3", 1, 1), ("8.2", 4, 1)), Direction::Downgrade);
+        assert_eq!(change(("8.2", 1, 1), ("8.2", 1, 1)), Direction::Unchanged);
+    }
+
+    #[test]
+    fn lineage_tree() {
+        let state = |id: i32, parent: Option<i32>| State {
+            id: Id(id),
+            summary: None,
+            description: None,
+            selections: vec![],
+            created: DateTime::default(),
+            kind: Kind::Transaction,
+            pinned: false,
+            updated: None,
+            parent: parent.map(Id),
+        };
+        // 5 and 7 were applied while 2 and 3 were booted, 1 is from before lineage was tracked and 6's parent was pruned
+        let states = [
+            state(1, None),
+            state(2, Some(1)),
+            state(3, Some(2)),
+            state(4, Some(3)),
+            state(5, Some(2)),
+            state(6, Some(0)),
+            state(7, Some(3)),
+        ];
+
+        let nodes = tree(&states)
+            .into_iter()
+            .map(|node| format!("{}{}", node.prefix, node.state.id))
+            .collect::<Vec<_>>();
+
+        assert_eq!(nodes, ["1", "2", "├─ 3", "│  ├─ 4", "│  7", "5", "6"]);
+    }
+
+    #[test]
+    fn encode_reason() {
+        for reason in [
+            Reason::Requested {
+                by_command: "install".to_owned(),
+            },
+            Reason::DependencyOf(package::Name::from("firefox".to_owned())),
+            Reason::Recommended,
+            Reason::Rebuilt,
+            Reason::Legacy("required by firefox".to_owned()),
+        ] {
+            assert_eq!(Reason::from(reason.encode()), reason);
+        }
+
+        let legacy = Reason::from("Dependency of firefox".to_owned());
+        assert_eq!(legacy, Reason::Legacy("Dependency of firefox".to_owned()));
+        assert_eq!(
+            legacy.to_string(),
+            Reason::DependencyOf(package::Name::from("firefox".to_owned())).to_string()
+        );
+    }
+
+    #[test]
+    fn mark() {
+        let mut selections = vec![
+            Selection::explicit(package::Id::from("nano".to_owned())).reason(Reason::Requested {
+                by_command: "install".to_owned(),
+            }),
+            Selection::transitive(package::Id::from("ncurses".to_owned()))
+                .reason(Reason::DependencyOf(package::Name::from("nano".to_owned()))),
+            Selection::transitive(package::Id::from("libc".to_owned())),
+        ];
+        let ids = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| package::Id::from((*name).to_owned()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            mark_selections(&mut selections, &ids(&["ncurses", "libc"]), true),
+            ids(&["ncurses", "libc"])
+        );
+        assert!(selections.iter().all(|selection| selection.explicit));
+        assert_eq!(
+            selections[1].reason,
+            Some(Reason::Requested {
+                by_command: "mark".to_owned()
+            })
+        );
+
+        // Already explicit
+        assert!(mark_selections(&mut selections, &ids(&["nano"]), true).is_empty());
+
+        assert_eq!(mark_selections(&mut selections, &ids(&["nano"]), false), ids(&["nano"]));
+        assert!(!selections[0].explicit);
+        assert_eq!(selections[0].reason, None);
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            state: Id(7),
+            summary: Some("Install nano".to_owned()),
+            created: "2025-08-01T12:00:00+00:00".to_owned(),
+            repositories: vec![ManifestRepository {
+                id: "volatile".to_owned(),
+                uri: "https://packages.example.com/volatile/x86_64/stone.index".to_owned(),
+                index: Some("0123456789abcdef".to_owned()),
+            }],
+            packages: vec![
+                ManifestPackage {
+                    name: package::Name::from("nano".to_owned()),
+                    version: "8.3".to_owned(),
+                    source_release: 1,
+                    build_release: 1,
+                    architecture: "x86_64".to_owned(),
+                    hash: package::Id::from("aaaa".to_owned()),
+                    explicit: true,
+                    reason: Some(Reason::Requested {
+                        by_command: "install".to_owned(),
+                    }),
+                },
+                ManifestPackage {
+                    name: package::Name::from("ncurses".to_owned()),
+                    version: "6.5".to_owned(),
+                    source_release: 2,
+                    build_release: 1,
+                    architecture: "x86_64".to_owned(),
+                    hash: package::Id::from("bbbb".to_owned()),
+                    explicit: false,
+                    reason: None,
+                },
+            ],
+        };
+
+        let json = manifest.to_json();
+        assert_eq!(Manifest::from_json(&json).unwrap(), manifest);
+        assert!(!json.contains("\"reason\": null"));
+
+        let newer = json.replacen(&format!("\"version\": {MANIFEST_VERSION}"), "\"version\": 99", 1);
+        assert!(matches!(
+            Manifest::from_json(&newer),
+            Err(ManifestError::UnsupportedVersion(99))
+        ));
+        assert!(matches!(
+            Manifest::from_json(r#"{"packages": []}"#),
+            Err(ManifestError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn dedup() {
+        let selection = |package: &str, explicit: bool| Selection {
+            package: package::Id::from(package.to_owned()),
+            explicit,
+            reason: None,
+        };
+        let mut selections = vec![
+            selection("zlib", false),
+            selection("nano", false),
+            selection("zlib", true),
+            selection("curl", true),
+            selection("zlib", false),
+        ];
+
+        let duplicates = dedup_selections(&mut selections);
+
+        assert_eq!(duplicates, vec![package::Id::from("zlib".to_owned())]);
+        assert_eq!(
+            selections,
+            vec![
+                selection("curl", true),
+                selection("nano", false),
+                selection("zlib", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_reference() {
+        let parse = |s: &str| s.parse::<Reference>().ok();
+
+        assert_eq!(parse("42"), Some(Reference::Id(Id(42))));
+        assert_eq!(parse("active"), Some(Reference::Active(0)));
+        assert_eq!(parse("previous"), Some(Reference::Active(1)));
+        assert_eq!(parse("active~1"), Some(Reference::Active(1)));
+        assert_eq!(parse("active~12"), Some(Reference::Active(12)));
+        assert_eq!(parse("latest"), Some(Reference::Latest));
+
+        for invalid in [
+            "",
+            "-1",
+            "3000000000",
+            "active~",
+            "active~-1",
+            "active~x",
+            "previous~1",
+            "Active",
+        ] {
+            assert_eq!(parse(invalid), None, "{invalid}");
+        }
+
+        assert_eq!(Reference::Active(2).to_string(), "active~2");
+        assert_eq!(Reference::Active(0).to_string(), "active");
+    }
+
+    #[test]
+    fn parse_kind() {
+        assert_eq!(Kind::from("rollback".to_owned()), Kind::Rollback);
+        assert_eq!(Kind::Snapshot.to_string(), "snapshot");
+        assert_eq!(Kind::from("incomplete".to_owned()), Kind::Incomplete);
+        assert_eq!(Kind::from("checkpoint".to_owned()), Kind::Unknown);
     }
 }